@@ -53,10 +53,22 @@ class MinidumpResult {
   final bool success;
   final String? error;
 
-  const MinidumpResult({required this.success, this.error});
+  /// Stable error identifier from `HandlerError::code`, e.g. `ptrace_denied`
+  final String? errorKind;
+
+  /// Crash id embedded in the written dump and its sidecar
+  final String? crashId;
+
+  const MinidumpResult({
+    required this.success,
+    this.error,
+    this.errorKind,
+    this.crashId,
+  });
 
   @override
-  int get hashCode => success.hashCode ^ error.hashCode;
+  int get hashCode =>
+      success.hashCode ^ error.hashCode ^ errorKind.hashCode ^ crashId.hashCode;
 
   @override
   bool operator ==(Object other) =>
@@ -64,5 +76,7 @@ class MinidumpResult {
       other is MinidumpResult &&
           runtimeType == other.runtimeType &&
           success == other.success &&
-          error == other.error;
+          error == other.error &&
+          errorKind == other.errorKind &&
+          crashId == other.crashId;
 }
@@ -327,11 +327,13 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
   MinidumpResult dco_decode_minidump_result(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
     final arr = raw as List<dynamic>;
-    if (arr.length != 2)
-      throw Exception('unexpected arr length: expect 2 but see ${arr.length}');
+    if (arr.length != 4)
+      throw Exception('unexpected arr length: expect 4 but see ${arr.length}');
     return MinidumpResult(
       success: dco_decode_bool(arr[0]),
       error: dco_decode_opt_String(arr[1]),
+      errorKind: dco_decode_opt_String(arr[2]),
+      crashId: dco_decode_opt_String(arr[3]),
     );
   }
 
@@ -412,7 +414,14 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     // Codec=Sse (Serialization based), see doc to use other codecs
     var var_success = sse_decode_bool(deserializer);
     var var_error = sse_decode_opt_String(deserializer);
-    return MinidumpResult(success: var_success, error: var_error);
+    var var_errorKind = sse_decode_opt_String(deserializer);
+    var var_crashId = sse_decode_opt_String(deserializer);
+    return MinidumpResult(
+      success: var_success,
+      error: var_error,
+      errorKind: var_errorKind,
+      crashId: var_crashId,
+    );
   }
 
   @protected
@@ -532,6 +541,8 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     // Codec=Sse (Serialization based), see doc to use other codecs
     sse_encode_bool(self.success, serializer);
     sse_encode_opt_String(self.error, serializer);
+    sse_encode_opt_String(self.errorKind, serializer);
+    sse_encode_opt_String(self.crashId, serializer);
   }
 
   @protected
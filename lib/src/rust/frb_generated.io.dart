@@ -145,6 +145,8 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
   ) {
     wireObj.success = cst_encode_bool(apiObj.success);
     wireObj.error = cst_encode_opt_String(apiObj.error);
+    wireObj.error_kind = cst_encode_opt_String(apiObj.errorKind);
+    wireObj.crash_id = cst_encode_opt_String(apiObj.crashId);
   }
 
   @protected
@@ -454,4 +456,8 @@ final class wire_cst_minidump_result extends ffi.Struct {
   external bool success;
 
   external ffi.Pointer<wire_cst_list_prim_u_8_strict> error;
+
+  external ffi.Pointer<wire_cst_list_prim_u_8_strict> error_kind;
+
+  external ffi.Pointer<wire_cst_list_prim_u_8_strict> crash_id;
 }
@@ -128,6 +128,8 @@ abstract class RustLibApiImplPlatform extends BaseApiImpl<RustLibWire> {
     return [
       cst_encode_bool(raw.success),
       cst_encode_opt_String(raw.error),
+      cst_encode_opt_String(raw.errorKind),
+      cst_encode_opt_String(raw.crashId),
     ].jsify()!;
   }
 
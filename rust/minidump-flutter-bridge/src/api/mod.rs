@@ -5,6 +5,10 @@ use std::path::Path;
 pub struct MinidumpResult {
     pub success: bool,
     pub error: Option<String>,
+    /// Stable error identifier from `HandlerError::code`, e.g. `ptrace_denied`
+    pub error_kind: Option<String>,
+    /// Crash id embedded in the written dump and its sidecar
+    pub crash_id: Option<String>,
 }
 
 #[derive(Debug)]
@@ -28,13 +32,17 @@ impl MinidumpApi {
 
     pub fn write_dump(&self, path: String) -> Result<MinidumpResult, anyhow::Error> {
         match minidump_handler::write_minidump(Path::new(&path)) {
-            Ok(_) => Ok(MinidumpResult {
+            Ok(crash_id) => Ok(MinidumpResult {
                 success: true,
                 error: None,
+                error_kind: None,
+                crash_id: Some(crash_id.to_string()),
             }),
             Err(e) => Ok(MinidumpResult {
                 success: false,
                 error: Some(e.to_string()),
+                error_kind: Some(e.code().to_string()),
+                crash_id: None,
             }),
         }
     }
@@ -44,10 +52,14 @@ impl MinidumpApi {
             Ok(_) => Ok(MinidumpResult {
                 success: true,
                 error: None,
+                error_kind: None,
+                crash_id: None,
             }),
             Err(e) => Ok(MinidumpResult {
                 success: false,
                 error: Some(e.to_string()),
+                error_kind: Some(e.code().to_string()),
+                crash_id: None,
             }),
         }
     }
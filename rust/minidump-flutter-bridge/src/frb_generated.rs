@@ -280,9 +280,13 @@ impl SseDecode for crate::api::MinidumpResult {
     fn sse_decode(deserializer: &mut flutter_rust_bridge::for_generated::SseDeserializer) -> Self {
         let mut var_success = <bool>::sse_decode(deserializer);
         let mut var_error = <Option<String>>::sse_decode(deserializer);
+        let mut var_errorKind = <Option<String>>::sse_decode(deserializer);
+        let mut var_crashId = <Option<String>>::sse_decode(deserializer);
         return crate::api::MinidumpResult {
             success: var_success,
             error: var_error,
+            error_kind: var_errorKind,
+            crash_id: var_crashId,
         };
     }
 }
@@ -375,6 +379,8 @@ impl flutter_rust_bridge::IntoDart for crate::api::MinidumpResult {
         [
             self.success.into_into_dart().into_dart(),
             self.error.into_into_dart().into_dart(),
+            self.error_kind.into_into_dart().into_dart(),
+            self.crash_id.into_into_dart().into_dart(),
         ]
         .into_dart()
     }
@@ -454,6 +460,8 @@ impl SseEncode for crate::api::MinidumpResult {
     fn sse_encode(self, serializer: &mut flutter_rust_bridge::for_generated::SseSerializer) {
         <bool>::sse_encode(self.success, serializer);
         <Option<String>>::sse_encode(self.error, serializer);
+        <Option<String>>::sse_encode(self.error_kind, serializer);
+        <Option<String>>::sse_encode(self.crash_id, serializer);
     }
 }
 
@@ -542,6 +550,8 @@ mod io {
             crate::api::MinidumpResult {
                 success: self.success.cst_decode(),
                 error: self.error.cst_decode(),
+                error_kind: self.error_kind.cst_decode(),
+                crash_id: self.crash_id.cst_decode(),
             }
         }
     }
@@ -560,6 +570,8 @@ mod io {
             Self {
                 success: Default::default(),
                 error: core::ptr::null_mut(),
+                error_kind: core::ptr::null_mut(),
+                crash_id: core::ptr::null_mut(),
             }
         }
     }
@@ -649,6 +661,8 @@ mod io {
     pub struct wire_cst_minidump_result {
         success: bool,
         error: *mut wire_cst_list_prim_u_8_strict,
+        error_kind: *mut wire_cst_list_prim_u_8_strict,
+        crash_id: *mut wire_cst_list_prim_u_8_strict,
     }
 }
 #[cfg(not(target_family = "wasm"))]
@@ -722,13 +736,15 @@ mod web {
                 .unwrap();
             assert_eq!(
                 self_.length(),
-                2,
-                "Expected 2 elements, got {}",
+                4,
+                "Expected 4 elements, got {}",
                 self_.length()
             );
             crate::api::MinidumpResult {
                 success: self_.get(0).cst_decode(),
                 error: self_.get(1).cst_decode(),
+                error_kind: self_.get(2).cst_decode(),
+                crash_id: self_.get(3).cst_decode(),
             }
         }
     }
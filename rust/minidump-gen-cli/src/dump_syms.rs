@@ -0,0 +1,460 @@
+//! `symbols dump`: Breakpad symbol files from ELF binaries with DWARF.
+//!
+//! Functions come from the ELF symbol table, demangled; their line records
+//! from the DWARF line programs; `STACK CFI` records from `.eh_frame` and
+//! `.debug_frame`. The debug id is derived from the GNU build id, or from the
+//! start of `.text` for binaries without one, the same way Breakpad does.
+//! Addresses are relative to the module base, where the lowest segment is
+//! mapped, like the module offsets of a dump.
+
+use anyhow::{bail, Context, Result};
+use gimli::{
+    BaseAddresses, CfaRule, CieOrFde, DebugFrame, EhFrame, EndianSlice, Register, RegisterRule,
+    RunTimeEndian, UnwindContext, UnwindSection, UnwindTableRow,
+};
+use minidump_handler::reader::breakpad_debug_id;
+use object::{Architecture, Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::path::Path;
+
+type Slice<'a> = EndianSlice<'a, RunTimeEndian>;
+
+/// A function and the line records inside it
+pub(crate) struct Function {
+    pub(crate) address: u64,
+    pub(crate) size: u64,
+    pub(crate) name: String,
+    /// (address, size, line, file index)
+    lines: Vec<(u64, u64, u64, usize)>,
+}
+
+/// The Breakpad symbol file of the ELF binary at `path`
+pub fn dump_symbols(path: &Path) -> Result<String> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let file = object::File::parse(&*data).with_context(|| format!("Failed to parse {}", path.display()))?;
+    if file.format() != object::BinaryFormat::Elf {
+        bail!("{} is not an ELF binary", path.display());
+    }
+    let Some(registers) = CfiRegisters::of(file.architecture()) else {
+        bail!("unsupported architecture {:?}", file.architecture());
+    };
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let code_id = code_id(&file)?;
+    let bias = module_bias(&file);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "MODULE Linux {} {} {}", registers.arch, breakpad_debug_id(&code_id), name);
+    let _ = writeln!(out, "INFO CODE_ID {}", hex_upper(&code_id));
+
+    let mut functions = functions(&file);
+    let files = add_lines(&file, &mut functions)?;
+    for (index, file) in files.iter().enumerate() {
+        let _ = writeln!(out, "FILE {} {}", index, file);
+    }
+    for function in &functions {
+        let _ = writeln!(out, "FUNC {:x} {:x} 0 {}", function.address - bias, function.size, function.name);
+        for &(address, size, line, file) in &function.lines {
+            let _ = writeln!(out, "{:x} {:x} {} {}", address - bias, size, line, file);
+        }
+    }
+
+    let endian = if file.is_little_endian() {
+        RunTimeEndian::Little
+    } else {
+        RunTimeEndian::Big
+    };
+    let text_address = file.section_by_name(".text").map_or(0, |s| s.address());
+    if let Some(section) = file.section_by_name(".eh_frame") {
+        let contents = section.uncompressed_data()?;
+        let mut eh_frame = EhFrame::new(&contents, endian);
+        eh_frame.set_address_size(8);
+        let bases = BaseAddresses::default()
+            .set_eh_frame(section.address())
+            .set_text(text_address);
+        write_cfi(&mut out, &eh_frame, &bases, bias, registers);
+    }
+    if let Some(section) = file.section_by_name(".debug_frame") {
+        let contents = section.uncompressed_data()?;
+        let mut debug_frame = DebugFrame::new(&contents, endian);
+        debug_frame.set_address_size(8);
+        write_cfi(&mut out, &debug_frame, &BaseAddresses::default(), bias, registers);
+    }
+    Ok(out)
+}
+
+/// Link address of the module base: the lowest segment, page aligned.
+/// Symbol file and dump addresses are relative to it.
+pub(crate) fn module_bias(file: &object::File<'_>) -> u64 {
+    file.segments().map(|s| s.address()).min().unwrap_or(0) & !0xfff
+}
+
+/// GNU build id, or `.text`'s first page folded into 16 bytes
+pub(crate) fn code_id(file: &object::File<'_>) -> Result<Vec<u8>> {
+    if let Some(build_id) = file.build_id()? {
+        return Ok(build_id.to_vec());
+    }
+    let text = file.section_by_name(".text").context("no build id and no .text section")?;
+    let mut id = vec![0u8; 16];
+    for (i, byte) in text.data()?.iter().take(4096).enumerate() {
+        id[i % 16] ^= byte;
+    }
+    Ok(id)
+}
+
+/// Function symbols with a size, demangled and sorted, one per address
+pub(crate) fn functions(file: &object::File<'_>) -> Vec<Function> {
+    let mut symbols: Vec<_> = file.symbols().collect();
+    if symbols.is_empty() {
+        symbols = file.dynamic_symbols().collect();
+    }
+    let mut by_address = BTreeMap::new();
+    for symbol in symbols {
+        if symbol.kind() != SymbolKind::Text || !symbol.is_definition() || symbol.size() == 0 {
+            continue;
+        }
+        let Ok(name) = symbol.name() else {
+            continue;
+        };
+        by_address.entry(symbol.address()).or_insert_with(|| Function {
+            address: symbol.address(),
+            size: symbol.size(),
+            name: format!("{:#}", rustc_demangle::demangle(name)),
+            lines: Vec::new(),
+        });
+    }
+    by_address.into_values().collect()
+}
+
+/// Read the DWARF line programs, attach their rows to `functions` and return
+/// the file table
+fn add_lines(file: &object::File<'_>, functions: &mut [Function]) -> Result<Vec<String>> {
+    let endian = if file.is_little_endian() {
+        RunTimeEndian::Little
+    } else {
+        RunTimeEndian::Big
+    };
+    let sections = gimli::DwarfSections::load(|id| -> Result<Cow<'_, [u8]>> {
+        Ok(match file.section_by_name(id.name()) {
+            Some(section) => section.uncompressed_data()?,
+            None => Cow::Borrowed(&[][..]),
+        })
+    })?;
+    let dwarf = sections.borrow(|section| EndianSlice::new(section, endian));
+
+    let mut files: Vec<String> = Vec::new();
+    let mut file_indices: HashMap<String, usize> = HashMap::new();
+    // (address, line, file index) rows; `None` line ends a sequence
+    let mut rows: Vec<(u64, Option<(u64, usize)>)> = Vec::new();
+
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let Some(program) = unit.line_program.clone() else {
+            continue;
+        };
+        let mut program_rows = program.rows();
+        while let Some((header, row)) = program_rows.next_row()? {
+            if row.end_sequence() {
+                rows.push((row.address(), None));
+                continue;
+            }
+            let Some(line) = row.line() else {
+                continue;
+            };
+            let Some(entry) = row.file(header) else {
+                continue;
+            };
+            let mut path = String::new();
+            if let Some(directory) = entry.directory(header) {
+                let directory = dwarf.attr_string(&unit, directory)?.to_string_lossy();
+                if !directory.starts_with('/') {
+                    if let Some(comp_dir) = &unit.comp_dir {
+                        path.push_str(&comp_dir.to_string_lossy());
+                        path.push('/');
+                    }
+                }
+                path.push_str(&directory);
+                path.push('/');
+            }
+            let file_name = dwarf.attr_string(&unit, entry.path_name())?.to_string_lossy();
+            if file_name.starts_with('/') {
+                path.clear();
+            }
+            path.push_str(&file_name);
+
+            let index = *file_indices.entry(path.clone()).or_insert_with(|| {
+                files.push(path);
+                files.len() - 1
+            });
+            rows.push((row.address(), Some((line.get(), index))));
+        }
+    }
+
+    // Each row covers the addresses up to the next row of its sequence
+    let mut lines: Vec<(u64, u64, u64, usize)> = rows
+        .windows(2)
+        .filter_map(|pair| {
+            let (address, (line, file)) = (pair[0].0, pair[0].1?);
+            (pair[1].0 > address).then(|| (address, pair[1].0 - address, line, file))
+        })
+        .collect();
+    lines.sort_unstable();
+
+    for function in functions.iter_mut() {
+        let end = function.address + function.size;
+        let first = lines.partition_point(|l| l.0 + l.1 <= function.address);
+        for &(address, size, line, file) in lines[first..].iter().take_while(|l| l.0 < end) {
+            let start = address.max(function.address);
+            let stop = (address + size).min(end);
+            if stop > start {
+                function.lines.push((start, stop - start, line, file));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// How an architecture's DWARF registers are spelled in `STACK CFI` rules
+pub(crate) struct CfiRegisters {
+    /// Architecture name in `MODULE` records
+    arch: &'static str,
+    /// DWARF register numbers and their names
+    names: &'static [(u16, &'static str)],
+    /// `.ra` rule of rows that leave the return address alone
+    return_address: &'static str,
+}
+
+impl CfiRegisters {
+    pub(crate) fn of(architecture: Architecture) -> Option<&'static Self> {
+        match architecture {
+            Architecture::X86_64 => Some(&X86_64),
+            Architecture::Aarch64 => Some(&ARM64),
+            _ => None,
+        }
+    }
+
+    fn name(&self, register: Register) -> Option<&'static str> {
+        self.names.iter().find(|r| r.0 == register.0).map(|r| r.1)
+    }
+}
+
+const X86_64: CfiRegisters = CfiRegisters {
+    arch: "x86_64",
+    names: &X86_64_REGISTERS,
+    // The call pushed it right below the CFA
+    return_address: ".cfa -8 + ^",
+};
+
+const ARM64: CfiRegisters = CfiRegisters {
+    arch: "arm64",
+    names: &ARM64_REGISTERS,
+    // Still in the link register
+    return_address: "x30",
+};
+
+const X86_64_REGISTERS: [(u16, &str); 17] = [
+    (0, "$rax"),
+    (1, "$rdx"),
+    (2, "$rcx"),
+    (3, "$rbx"),
+    (4, "$rsi"),
+    (5, "$rdi"),
+    (6, "$rbp"),
+    (7, "$rsp"),
+    (8, "$r8"),
+    (9, "$r9"),
+    (10, "$r10"),
+    (11, "$r11"),
+    (12, "$r12"),
+    (13, "$r13"),
+    (14, "$r14"),
+    (15, "$r15"),
+    (16, "$rip"),
+];
+
+const ARM64_REGISTERS: [(u16, &str); 32] = [
+    (0, "x0"),
+    (1, "x1"),
+    (2, "x2"),
+    (3, "x3"),
+    (4, "x4"),
+    (5, "x5"),
+    (6, "x6"),
+    (7, "x7"),
+    (8, "x8"),
+    (9, "x9"),
+    (10, "x10"),
+    (11, "x11"),
+    (12, "x12"),
+    (13, "x13"),
+    (14, "x14"),
+    (15, "x15"),
+    (16, "x16"),
+    (17, "x17"),
+    (18, "x18"),
+    (19, "x19"),
+    (20, "x20"),
+    (21, "x21"),
+    (22, "x22"),
+    (23, "x23"),
+    (24, "x24"),
+    (25, "x25"),
+    (26, "x26"),
+    (27, "x27"),
+    (28, "x28"),
+    (29, "x29"),
+    (30, "x30"),
+    (31, "sp"),
+];
+
+/// `STACK CFI INIT` and `STACK CFI` records for every FDE in `section`,
+/// at addresses less `bias`. FDEs that need DWARF expressions are left out.
+fn write_cfi<'a, S: UnwindSection<Slice<'a>>>(
+    out: &mut String,
+    section: &S,
+    bases: &BaseAddresses,
+    bias: u64,
+    registers: &CfiRegisters,
+) {
+    let mut context = Box::new(UnwindContext::new());
+    let mut entries = section.entries(bases);
+    while let Ok(Some(entry)) = entries.next() {
+        let CieOrFde::Fde(partial) = entry else {
+            continue;
+        };
+        let Ok(fde) = partial.parse(|section, bases, offset| section.cie_from_offset(bases, offset)) else {
+            continue;
+        };
+        let Ok(mut table) = fde.rows(section, bases, &mut context) else {
+            continue;
+        };
+        let return_address = fde.cie().return_address_register();
+
+        let mut records = Vec::new();
+        let mut previous: BTreeMap<String, String> = BTreeMap::new();
+        let mut complete = true;
+        while let Ok(Some(row)) = table.next_row() {
+            let Some(rules) = row_rules(row, return_address, registers) else {
+                complete = false;
+                break;
+            };
+            let changed = rule_changes(&previous, &rules);
+            if records.is_empty() || !changed.is_empty() {
+                records.push((row.start_address(), row.end_address(), changed));
+            }
+            previous = rules;
+        }
+
+        let (Some(first), Some(last)) = (records.first(), records.last()) else {
+            continue;
+        };
+        if !complete || first.0 < bias {
+            continue;
+        }
+        let _ = writeln!(out, "STACK CFI INIT {:x} {:x} {}", first.0 - bias, last.1 - first.0, first.2);
+        for (address, _, rules) in &records[1..] {
+            let _ = writeln!(out, "STACK CFI {:x} {}", address - bias, rules);
+        }
+    }
+}
+
+/// The `STACK CFI` delta from `previous` to `rules`. A register whose rule
+/// was dropped, as after a restore, is back to its own value: `reg: reg`.
+fn rule_changes(previous: &BTreeMap<String, String>, rules: &BTreeMap<String, String>) -> String {
+    let changed = rules
+        .iter()
+        .filter(|&(register, rule)| previous.get(register) != Some(rule))
+        .map(|(register, rule)| format!("{}: {}", register, rule));
+    let dropped = previous
+        .keys()
+        .filter(|register| !rules.contains_key(*register))
+        .map(|register| format!("{}: {}", register, register));
+    changed.chain(dropped).collect::<Vec<_>>().join(" ")
+}
+
+/// The rules of one CFI row as `STACK CFI` expressions by register, `None`
+/// when the CFA needs a DWARF expression
+pub(crate) fn row_rules(
+    row: &UnwindTableRow<usize>,
+    return_address: Register,
+    registers: &CfiRegisters,
+) -> Option<BTreeMap<String, String>> {
+    let mut rules = BTreeMap::new();
+    match row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } => {
+            rules.insert(".cfa".to_string(), format!("{} {} +", registers.name(*register)?, offset));
+        }
+        CfaRule::Expression(_) => return None,
+    }
+    for (register, rule) in row.registers() {
+        let target = if *register == return_address {
+            ".ra".to_string()
+        } else {
+            match registers.name(*register) {
+                Some(name) => name.to_string(),
+                None => continue,
+            }
+        };
+        let expression = match rule {
+            RegisterRule::Offset(offset) => format!(".cfa {} + ^", offset),
+            RegisterRule::ValOffset(offset) => format!(".cfa {} +", offset),
+            RegisterRule::Register(from) => match registers.name(*from) {
+                Some(from) => from.to_string(),
+                None => continue,
+            },
+            RegisterRule::SameValue => target.clone(),
+            _ => continue,
+        };
+        rules.insert(target, expression);
+    }
+    rules
+        .entry(".ra".to_string())
+        .or_insert_with(|| registers.return_address.to_string());
+    Some(rules)
+}
+
+fn hex_upper(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::SymbolFile;
+
+    #[test]
+    fn test_dump_own_binary() {
+        let binary = std::env::current_exe().unwrap();
+        let symbols = SymbolFile::parse(&dump_symbols(&binary).unwrap()).unwrap();
+        assert_eq!(symbols.name, binary.file_name().unwrap().to_str().unwrap());
+        assert_eq!(symbols.debug_id.len(), 33);
+
+        let function = symbols
+            .functions
+            .iter()
+            .find(|f| f.name.ends_with("dump_syms::tests::test_dump_own_binary"))
+            .expect("test function in the symbol file");
+        assert!(!function.lines.is_empty());
+        let location = symbols.locate(function.address).unwrap();
+        assert!(location.file.unwrap().ends_with("dump_syms.rs"));
+        assert!(symbols.cfi_rules(function.address).is_some());
+
+        // Relative to the module base, like the module offsets of a dump
+        let data = std::fs::read(&binary).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let linked = functions(&file).into_iter().find(|f| f.name == function.name).unwrap();
+        assert_eq!(function.address, linked.address - module_bias(&file));
+    }
+
+    #[test]
+    fn test_dropped_rule_is_restored() {
+        let rules = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs.iter().map(|(r, e)| (r.to_string(), e.to_string())).collect()
+        };
+        let pushed = rules(&[(".cfa", "$rsp 16 +"), (".ra", ".cfa -8 + ^"), ("$rbp", ".cfa -16 + ^")]);
+        let popped = rules(&[(".cfa", "$rsp 8 +"), (".ra", ".cfa -8 + ^")]);
+        assert_eq!(rule_changes(&pushed, &popped), ".cfa: $rsp 8 + $rbp: $rbp");
+    }
+}
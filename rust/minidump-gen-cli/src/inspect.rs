@@ -0,0 +1,315 @@
+//! `inspect`: what a dump holds, as text or JSON.
+
+use minidump_handler::reader::{mach_exception_name, standard_stream, stream_name, Location, Minidump, Os};
+use minidump_handler::streams::stream_type;
+use minidump_handler::{code_name, signal_name};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+/// Bytes of a stream shown when it isn't text
+const HEX_PREVIEW_BYTES: usize = 256;
+
+/// Streams decoded field by field; all others are shown raw
+const DECODED_STREAMS: [u32; 6] = [
+    standard_stream::THREAD_LIST,
+    standard_stream::MODULE_LIST,
+    standard_stream::MEMORY_LIST,
+    standard_stream::EXCEPTION,
+    standard_stream::SYSTEM_INFO,
+    standard_stream::MEMORY64_LIST,
+];
+
+/// Everything `inspect` prints, as one JSON document
+pub fn to_json(dump: &Minidump) -> Value {
+    let os = dump.system_info().map(|system| system.os);
+    let names = thread_names(dump);
+
+    let directory: Vec<Value> = dump
+        .directory
+        .iter()
+        .map(|entry| {
+            json!({
+                "type": format!("{:#x}", entry.stream_type),
+                "name": stream_name(entry.stream_type),
+                "rva": entry.rva,
+                "size": entry.data_size,
+            })
+        })
+        .collect();
+
+    let exception = dump.exception().map(|exception| {
+        let (name, code) = exception_names(os, exception.code, exception.flags);
+        json!({
+            "thread_id": exception.thread_id,
+            "name": name,
+            "code": code,
+            "raw_code": exception.code,
+            "raw_flags": exception.flags,
+            "address": format!("{:#x}", exception.address),
+        })
+    });
+
+    let threads: Vec<Value> = dump
+        .threads()
+        .iter()
+        .map(|thread| {
+            let registers = dump.registers(thread.context);
+            json!({
+                "thread_id": thread.thread_id,
+                "name": names.get(&thread.thread_id),
+                "stack_start": format!("{:#x}", thread.stack_start),
+                "stack_size": thread.stack.data_size,
+                "pc": registers.as_ref().map(|r| format!("{:#x}", r.pc)),
+                "sp": registers.as_ref().map(|r| format!("{:#x}", r.sp)),
+                "fp": registers.as_ref().map(|r| format!("{:#x}", r.fp)),
+            })
+        })
+        .collect();
+
+    let modules: Vec<Value> = dump
+        .modules()
+        .iter()
+        .map(|module| {
+            json!({
+                "base": format!("{:#x}", module.base),
+                "size": module.size,
+                "name": module.name,
+                "build_id": module.build_id,
+            })
+        })
+        .collect();
+
+    let other_streams: Vec<Value> = raw_streams(dump)
+        .map(|(stream_type, data)| {
+            let contents = match text(data) {
+                Some(text) => json!({ "utf8": text }),
+                None => json!({ "hex": hex(&data[..data.len().min(HEX_PREVIEW_BYTES)]) }),
+            };
+            json!({
+                "type": format!("{:#x}", stream_type),
+                "name": stream_name(stream_type),
+                "size": data.len(),
+                "contents": contents,
+            })
+        })
+        .collect();
+
+    json!({
+        "header": {
+            "version": format!("{:#x}", dump.header.version),
+            "stream_count": dump.header.stream_count,
+            "stream_directory_rva": dump.header.stream_directory_rva,
+            "time_date_stamp": dump.header.time_date_stamp,
+        },
+        "crash_id": dump.crash_id(),
+        "directory": directory,
+        "system_info": dump.system_info().map(|system| json!({
+            "os": system.os.as_str(),
+            "os_version": format!("{}.{}.{}", system.os_version.0, system.os_version.1, system.os_version.2),
+            "cpu": system.cpu.as_str(),
+            "cpu_count": system.cpu_count,
+        })),
+        "exception": exception,
+        "threads": threads,
+        "modules": modules,
+        "memory_regions": dump.memory_regions().len(),
+        "streams": other_streams,
+    })
+}
+
+/// Everything `inspect` prints, for reading in a terminal
+pub fn to_text(dump: &Minidump) -> String {
+    let mut out = String::new();
+    let os = dump.system_info().map(|system| system.os);
+    let header = &dump.header;
+
+    let _ = writeln!(out, "Header:");
+    let _ = writeln!(out, "  Version:      {:#x}", header.version);
+    let _ = writeln!(out, "  Streams:      {} (directory at {:#x})", header.stream_count, header.stream_directory_rva);
+    let _ = writeln!(out, "  Time:         {} (unix)", header.time_date_stamp);
+    if let Some(crash_id) = dump.crash_id() {
+        let _ = writeln!(out, "  Crash ID:     {}", crash_id);
+    }
+
+    let _ = writeln!(out, "\nStream directory:");
+    for entry in &dump.directory {
+        let _ = writeln!(
+            out,
+            "  {:#010x}  {:<22} {:>9} bytes at {:#x}",
+            entry.stream_type,
+            stream_name(entry.stream_type).unwrap_or("(unknown)"),
+            entry.data_size,
+            entry.rva
+        );
+    }
+
+    if let Some(system) = dump.system_info() {
+        let (major, minor, build) = system.os_version;
+        let _ = writeln!(out, "\nSystem info:");
+        let _ = writeln!(out, "  OS:           {} {}.{}.{}", system.os.as_str(), major, minor, build);
+        let _ = writeln!(out, "  CPU:          {} ({} CPUs)", system.cpu.as_str(), system.cpu_count);
+    }
+
+    let _ = writeln!(out, "\nException:");
+    match dump.exception() {
+        Some(exception) => {
+            let (name, code) = exception_names(os, exception.code, exception.flags);
+            let _ = writeln!(out, "  Thread:       {}", exception.thread_id);
+            let _ = writeln!(out, "  Signal:       {} ({})", name, exception.code);
+            let _ = writeln!(out, "  Code:         {} ({:#x})", code, exception.flags);
+            let _ = writeln!(out, "  Address:      {:#x}", exception.address);
+        }
+        None => {
+            let _ = writeln!(out, "  (none, dump written without a crash)");
+        }
+    }
+
+    let threads = dump.threads();
+    let names = thread_names(dump);
+    let crashing = dump.exception().map(|exception| exception.thread_id);
+    let _ = writeln!(out, "\nThreads ({}):", threads.len());
+    for thread in &threads {
+        let marker = if Some(thread.thread_id) == crashing { "*" } else { " " };
+        let name = names.get(&thread.thread_id).map(String::as_str).unwrap_or("");
+        match dump.registers(thread.context) {
+            Some(registers) => {
+                let _ = writeln!(
+                    out,
+                    " {}{:<8} pc {:#018x}  sp {:#018x}  fp {:#018x}  {}",
+                    marker, thread.thread_id, registers.pc, registers.sp, registers.fp, name
+                );
+            }
+            None => {
+                let _ = writeln!(out, " {}{:<8} (no registers)  {}", marker, thread.thread_id, name);
+            }
+        }
+    }
+
+    let modules = dump.modules();
+    let _ = writeln!(out, "\nModules ({}):", modules.len());
+    for module in &modules {
+        let _ = writeln!(
+            out,
+            "  {:#018x}-{:#018x}  {}  {}",
+            module.base,
+            module.base + module.size as u64,
+            module.file_name(),
+            module.build_id.as_deref().unwrap_or("(no build id)")
+        );
+    }
+
+    let _ = writeln!(out, "\nMemory regions: {}", dump.memory_regions().len());
+
+    for (stream_type, data) in raw_streams(dump) {
+        let name = stream_name(stream_type).unwrap_or("unknown");
+        let _ = writeln!(out, "\nStream {:#x} ({}, {} bytes):", stream_type, name, data.len());
+        match text(data) {
+            Some(text) => {
+                for line in text.lines() {
+                    let _ = writeln!(out, "  {}", line);
+                }
+            }
+            None => {
+                hex_dump(&mut out, &data[..data.len().min(HEX_PREVIEW_BYTES)]);
+                if data.len() > HEX_PREVIEW_BYTES {
+                    let _ = writeln!(out, "  ... {} more bytes", data.len() - HEX_PREVIEW_BYTES);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Signal and `si_code` names on Linux, the `EXC_*` kind and code elsewhere
+fn exception_names(os: Option<Os>, code: u32, flags: u32) -> (String, String) {
+    match os {
+        Some(os) if os.uses_signals() => (
+            signal_name(code as i32).to_string(),
+            code_name(code as i32, flags as i32).to_string(),
+        ),
+        _ => (mach_exception_name(code).to_string(), format!("{:#x}", flags)),
+    }
+}
+
+/// Thread names from the handler's thread names stream, by thread id
+fn thread_names(dump: &Minidump) -> HashMap<u32, String> {
+    let Some(threads) = dump
+        .stream(stream_type::THREAD_NAMES)
+        .and_then(|json| serde_json::from_slice::<Vec<Value>>(json).ok())
+    else {
+        return HashMap::new();
+    };
+    threads
+        .iter()
+        .filter_map(|thread| Some((thread["tid"].as_u64()? as u32, thread["name"].as_str()?.to_string())))
+        .collect()
+}
+
+/// Streams not decoded above, with their data
+fn raw_streams(dump: &Minidump) -> impl Iterator<Item = (u32, &[u8])> {
+    dump.directory
+        .iter()
+        .filter(|entry| entry.stream_type != 0 && !DECODED_STREAMS.contains(&entry.stream_type))
+        .filter_map(|entry| {
+            let location = Location {
+                data_size: entry.data_size,
+                rva: entry.rva,
+            };
+            Some((entry.stream_type, dump.location(location)?))
+        })
+}
+
+/// `data` as text, if it is printable UTF-8
+fn text(data: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(data).ok()?;
+    let text = text.trim_end_matches('\0');
+    text.chars()
+        .all(|c| !c.is_control() || c.is_whitespace())
+        .then_some(text)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_dump(out: &mut String, data: &[u8]) {
+    for (i, row) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = row.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = row
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() { byte as char } else { '.' })
+            .collect();
+        let _ = writeln!(out, "  {:08x}  {:<47}  {}", i * 16, bytes.join(" "), ascii);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_inspect_handler_dump() {
+        let mut sink = Cursor::new(Vec::new());
+        let crash_id = minidump_handler::write_minidump_to(&mut sink).unwrap().to_string();
+        let dump = Minidump::from_bytes(sink.into_inner()).unwrap();
+
+        let json = to_json(&dump);
+        assert_eq!(json["crash_id"], crash_id.as_str());
+        assert!(json["exception"].is_null());
+        let directory = json["directory"].as_array().unwrap();
+        assert_eq!(directory.len(), dump.header.stream_count as usize);
+        assert!(directory.iter().any(|entry| entry["name"] == "crash_id"));
+        assert!(json["streams"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|stream| stream["name"] == "crash_id" && stream["contents"]["utf8"] == crash_id.as_str()));
+
+        let text = to_text(&dump);
+        assert!(text.contains(&format!("  Crash ID:     {}", crash_id)));
+        assert!(text.contains("(none, dump written without a crash)"));
+        assert!(text.contains(&format!("crash_id, {} bytes):\n  {}\n", crash_id.len(), crash_id)));
+    }
+}
@@ -1,8 +1,22 @@
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::*;
-use minidump_handler::{crash_triggers, init_crash_handler, write_minidump, HandlerConfig};
-use std::path::PathBuf;
+use minidump_handler::{
+    append_log, crash_triggers, decrypt, encrypted_path, format_key, generate_encryption_keys,
+    generate_signing_keys, init_crash_handler, parse_key, reader::Minidump, scrub_dump, verify_signature,
+    write_file, write_minidump, DumpMode, HandlerConfig, LowDiskSpace, ScrubOptions, Sidecar, SignalInfo,
+    DEFAULT_MIN_FREE_DISK_SPACE, DEFAULT_SCRUB_STACK_BYTES,
+};
+use std::path::{Path, PathBuf};
+
+mod dump_syms;
+mod inspect;
+mod stackwalk;
+mod symbolicate;
+mod symbols;
+
+#[cfg(target_os = "linux")]
+use minidump_handler::{connect_crash_server, CrashServer};
 
 #[derive(Parser)]
 #[command(name = "minidump-gen")]
@@ -16,10 +30,39 @@ struct Cli {
     #[arg(short, long, default_value = "crash")]
     prefix: String,
 
+    /// Filename template, e.g. "{prefix}_{signal}_{pid}_{timestamp_ms}.dmp"
+    #[arg(short, long)]
+    template: Option<String>,
+
     /// Install crash handler before executing command
     #[arg(short = 'H', long)]
     install_handler: bool,
 
+    /// How much of the process the handler's dumps hold
+    #[arg(short, long, value_enum, requires = "install_handler")]
+    mode: Option<Mode>,
+
+    /// Encrypt the handler's dumps to this X25519 public key (hex or a file)
+    #[arg(short, long, value_name = "PUBLIC_KEY", requires = "install_handler")]
+    encrypt_to: Option<String>,
+
+    /// Sign the handler's dumps and sidecars with this Ed25519 private key (hex or a file)
+    #[arg(long, value_name = "PRIVATE_KEY", requires = "install_handler")]
+    sign_with: Option<String>,
+
+    /// Free bytes the dump filesystem needs for a full-size dump
+    #[arg(long, value_name = "BYTES", requires = "install_handler")]
+    min_free_space: Option<u64>,
+
+    /// Below --min-free-space write no dump instead of a minimal one
+    #[arg(long, requires = "install_handler")]
+    skip_on_low_disk: bool,
+
+    /// Let the crash server listening on this socket write the dumps
+    #[cfg(target_os = "linux")]
+    #[arg(short, long, requires = "install_handler")]
+    connect: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -49,6 +92,143 @@ enum Commands {
         #[arg(short, long, default_value = "0")]
         timeout: u64,
     },
+
+    /// Remove stack contents, heap memory, environment and annotations that
+    /// could hold personal data
+    Scrub {
+        /// Dump to scrub
+        input: PathBuf,
+        /// Where to write the scrubbed dump
+        output: PathBuf,
+        /// Stack bytes kept above the stack pointer
+        #[arg(long, default_value_t = DEFAULT_SCRUB_STACK_BYTES)]
+        stack_bytes: usize,
+        /// Environment variable to keep (repeatable; default: a safe allowlist)
+        #[arg(long = "keep-env")]
+        keep_env: Vec<String>,
+        /// Annotation key to keep (repeatable)
+        #[arg(long = "keep-annotation")]
+        keep_annotation: Vec<String>,
+        /// Keep memory regions outside stacks and modules
+        #[arg(long)]
+        keep_heap: bool,
+    },
+
+    /// Decrypt a `.enc` dump, sidecar or report
+    Decrypt {
+        /// Encrypted file
+        input: PathBuf,
+        /// Where to write the plaintext (default: input without `.enc`)
+        output: Option<PathBuf>,
+        /// X25519 private key (hex or a file)
+        #[arg(short, long, value_name = "PRIVATE")]
+        key: String,
+    },
+
+    /// Generate a key pair for --encrypt-to and decrypt
+    Keygen {
+        /// Generate an Ed25519 key pair for --sign-with and verify instead
+        #[arg(long)]
+        signing: bool,
+    },
+
+    /// Print what a dump holds: header, streams, system info, exception,
+    /// threads, modules and any other streams
+    Inspect {
+        /// Dump to read
+        file: PathBuf,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Unwind every thread and resolve the frames with Breakpad symbol files
+    Stackwalk {
+        /// Dump to walk
+        file: PathBuf,
+        /// Symbol store laid out as `<module>/<DEBUGID>/<module>.sym`
+        #[arg(short, long)]
+        symbols: PathBuf,
+    },
+
+    /// Unwind every thread and resolve the frames, inlined functions
+    /// included, from the DWARF of unstripped binaries
+    Symbolicate {
+        /// Dump to walk
+        file: PathBuf,
+        /// Directory searched recursively for the dump's binaries, matched by
+        /// build id (repeatable)
+        #[arg(short, long, value_name = "DIR", required = true)]
+        binaries: Vec<PathBuf>,
+    },
+
+    /// Produce Breakpad symbol files and add them to a symbol store
+    Symbols {
+        #[command(subcommand)]
+        command: SymbolsCommand,
+    },
+
+    /// Check a dump's structure and the signature in its sidecar
+    Verify {
+        /// Dump to check
+        dump: PathBuf,
+        /// Ed25519 public key the dump must be signed with (hex or a file)
+        #[arg(short, long, value_name = "PUBLIC", required_unless_present = "unsigned")]
+        key: Option<String>,
+        /// Only check the structure, for dumps written without --sign-with
+        #[arg(long)]
+        unsigned: bool,
+    },
+
+    /// Write dumps for crashing clients connected with --connect
+    #[cfg(target_os = "linux")]
+    Server {
+        /// Unix socket to listen on
+        #[arg(short, long)]
+        socket: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum SymbolsCommand {
+    /// Write the symbol file of an ELF binary: functions, lines from DWARF
+    /// and unwind rules
+    Dump {
+        /// Unstripped ELF binary or shared library
+        binary: PathBuf,
+        /// Where to write it (default: standard output)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Copy a symbol file into a store under its debug id; binaries are
+    /// dumped first
+    Add {
+        /// `.sym` file or ELF binary
+        file: PathBuf,
+        /// Symbol store laid out as `<module>/<DEBUGID>/<module>.sym`
+        #[arg(short, long)]
+        store: PathBuf,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum Mode {
+    /// Crashing thread's stack and registers only
+    Minimal,
+    /// Every thread's stack and registers
+    Normal,
+    /// Also all private anonymous memory, within a size limit (Linux)
+    Full,
+}
+
+impl From<Mode> for DumpMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Minimal => DumpMode::Minimal,
+            Mode::Normal => DumpMode::Normal,
+            Mode::Full => DumpMode::Full,
+        }
+    }
 }
 
 #[derive(Subcommand, Clone)]
@@ -66,6 +246,12 @@ enum CrashType {
     IllegalInstruction,
     /// Stack overflow
     StackOverflow,
+    /// Several threads crashing at once
+    MultiThread,
+    /// Crash inside the crash handler's pre-dump callback
+    InCallback,
+    /// Segfault inside the pre-dump callback of a segfault
+    InCallbackSameSignal,
 }
 
 impl CrashType {
@@ -78,6 +264,9 @@ impl CrashType {
             Self::DivideByZero => "Integer division by zero causing SIGFPE",
             Self::IllegalInstruction => "Invalid CPU instruction causing SIGILL",
             Self::StackOverflow => "Recursive function causing stack exhaustion",
+            Self::MultiThread => "Several threads dereferencing null at the same time",
+            Self::InCallback => "Abort whose pre-dump callback segfaults (reentrant crash)",
+            Self::InCallbackSameSignal => "Segfault whose pre-dump callback segfaults again (same-signal reentrant crash)",
         }
     }
 
@@ -90,10 +279,35 @@ impl CrashType {
             Self::DivideByZero => crash_triggers::trigger_divide_by_zero(),
             Self::IllegalInstruction => crash_triggers::trigger_illegal_instruction(),
             Self::StackOverflow => crash_triggers::trigger_stack_overflow(),
+            Self::MultiThread => crash_triggers::trigger_multi_thread_crash(),
+            Self::InCallback => crash_triggers::trigger_abort(),
+            Self::InCallbackSameSignal => crash_triggers::trigger_segfault(),
         }
     }
 }
 
+/// Pre-dump callback: tell the user what crashed before the dump is written.
+///
+/// This runs in the signal handler, so the line is put together on the stack
+/// from static names and written with one `write(2)`; the full reason is in
+/// the sidecar.
+fn report_crash(signal_info: &SignalInfo) {
+    let mut line = [0u8; 128];
+    let mut len = 0;
+    for part in [
+        "Crash detected: ",
+        signal_info.signal_name(),
+        " (",
+        signal_info.code_name(),
+        "), writing minidump...\n",
+    ] {
+        let n = part.len().min(line.len() - len);
+        line[len..len + n].copy_from_slice(&part.as_bytes()[..n]);
+        len += n;
+    }
+    unsafe { libc::write(libc::STDERR_FILENO, line.as_ptr() as *const libc::c_void, len) };
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
@@ -105,17 +319,35 @@ fn main() -> Result<()> {
     if cli.install_handler {
         println!("{}", "Installing crash handler...".green());
         
+        let callback: fn(&SignalInfo) = match &cli.command {
+            Commands::Crash {
+                crash_type: CrashType::InCallback | CrashType::InCallbackSameSignal,
+            } => crash_triggers::crashing_callback,
+            _ => report_crash,
+        };
         let config = HandlerConfig {
             dump_directory: cli.output.clone(),
             filename_prefix: cli.prefix.clone(),
             append_timestamp: true,
-            pre_dump_callback: Some(|| {
-                eprintln!("{}", "Crash detected! Writing minidump...".red().bold());
-            }),
+            filename_template: cli.template.clone(),
+            pre_dump_callback: Some(callback),
+            dump_mode: cli.mode.map(DumpMode::from).unwrap_or_default(),
+            encryption_key: cli.encrypt_to.as_deref().map(read_key).transpose()?,
+            signing_key: cli.sign_with.as_deref().map(read_key).transpose()?,
+            min_free_disk_space: cli.min_free_space.unwrap_or(DEFAULT_MIN_FREE_DISK_SPACE),
+            low_disk_space: if cli.skip_on_low_disk { LowDiskSpace::Skip } else { LowDiskSpace::Minimal },
+            ..Default::default()
         };
         
         init_crash_handler(config)?;
         println!("{}", "✓ Crash handler installed".green());
+
+        #[cfg(target_os = "linux")]
+        if let Some(socket) = &cli.connect {
+            connect_crash_server(socket, &[("tool", "minidump-gen")])
+                .with_context(|| format!("Failed to connect to crash server at {}", socket.display()))?;
+            println!("{} {}", "✓ Connected to crash server:".green(), socket.display());
+        }
     }
 
     match cli.command {
@@ -123,10 +355,12 @@ fn main() -> Result<()> {
             println!("{}", "Generating minidump...".blue());
             let dump_path = cli.output.join(format!("{}.dmp", name));
             
-            write_minidump(&dump_path)
+            let crash_id = write_minidump(&dump_path)
                 .with_context(|| "Failed to write minidump")?;
+            let dump_path = if cli.encrypt_to.is_some() { encrypted_path(&dump_path) } else { dump_path };
             
             println!("{} {}", "✓ Minidump written to:".green(), dump_path.display());
+            println!("  Crash ID: {}", crash_id);
         }
 
         Commands::Crash { crash_type } => {
@@ -135,6 +369,7 @@ fn main() -> Result<()> {
             }
             
             println!("{} {}", "Triggering crash:".red(), crash_type.description());
+            append_log(&format!("minidump-gen: triggering crash: {}", crash_type.description()));
             println!("{}", "This will terminate the process!".red().bold());
             
             // Small delay to ensure output is flushed
@@ -152,6 +387,9 @@ fn main() -> Result<()> {
             println!("  {} - {}", "divide-by-zero".cyan(), CrashType::DivideByZero.description());
             println!("  {} - {}", "illegal-instruction".cyan(), CrashType::IllegalInstruction.description());
             println!("  {} - {}", "stack-overflow".cyan(), CrashType::StackOverflow.description());
+            println!("  {} - {}", "multi-thread".cyan(), CrashType::MultiThread.description());
+            println!("  {} - {}", "in-callback".cyan(), CrashType::InCallback.description());
+            println!("  {} - {}", "in-callback-same-signal".cyan(), CrashType::InCallbackSameSignal.description());
             
             println!("\n{}", "Example usage:".bold());
             println!("  minidump-gen -H crash segfault");
@@ -164,9 +402,9 @@ fn main() -> Result<()> {
                     dump_directory: cli.output.clone(),
                     filename_prefix: cli.prefix.clone(),
                     append_timestamp: true,
-                    pre_dump_callback: Some(|| {
-                        eprintln!("{}", "Crash detected! Writing minidump...".red().bold());
-                    }),
+                    filename_template: cli.template.clone(),
+                    pre_dump_callback: Some(report_crash),
+                    ..Default::default()
                 };
                 
                 init_crash_handler(config)?;
@@ -185,7 +423,163 @@ fn main() -> Result<()> {
                 std::thread::park();
             }
         }
+
+        Commands::Scrub {
+            input,
+            output,
+            stack_bytes,
+            keep_env,
+            keep_annotation,
+            keep_heap,
+        } => {
+            let mut options = ScrubOptions {
+                stack_bytes,
+                annotation_allowlist: keep_annotation,
+                remove_heap: !keep_heap,
+                ..Default::default()
+            };
+            if !keep_env.is_empty() {
+                options.environment_allowlist = keep_env;
+            }
+
+            let summary = scrub_dump(&input, &output, &options)
+                .with_context(|| format!("Failed to scrub {}", input.display()))?;
+
+            println!("{} {}", "✓ Scrubbed dump written to:".green(), output.display());
+            println!("  Stack bytes zeroed:     {}", summary.stack_bytes_zeroed);
+            println!("  Memory regions removed: {}", summary.regions_removed);
+            println!("  Environment removed:    {}", summary.environment_removed);
+            println!("  Annotations removed:    {}", summary.annotations_removed);
+        }
+
+        Commands::Decrypt { input, output, key } => {
+            let output = output.unwrap_or_else(|| input.with_extension(""));
+            let sealed = std::fs::read(&input).with_context(|| format!("Failed to read {}", input.display()))?;
+            let plaintext =
+                decrypt(&sealed, &read_key(&key)?).with_context(|| format!("Failed to decrypt {}", input.display()))?;
+            write_file(&output, plaintext).with_context(|| format!("Failed to write {}", output.display()))?;
+            println!("{} {}", "✓ Decrypted to:".green(), output.display());
+        }
+
+        Commands::Keygen { signing } => {
+            let (private_key, public_key) = if signing {
+                generate_signing_keys()
+            } else {
+                generate_encryption_keys()
+            };
+            println!("Private key: {}", format_key(&private_key));
+            println!("Public key:  {}", format_key(&public_key));
+        }
+
+        Commands::Inspect { file, json } => {
+            let dump = Minidump::read(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&inspect::to_json(&dump))?);
+            } else {
+                print!("{}", inspect::to_text(&dump));
+            }
+        }
+
+        Commands::Stackwalk { file, symbols } => {
+            let dump = Minidump::read(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            let mut walker = stackwalk::Walker::new(&dump, stackwalk::SymbolStore(&symbols))
+                .context("The dump has no system info for a supported CPU")?;
+            print!("{}", walker.report());
+        }
+
+        Commands::Symbolicate { file, binaries } => {
+            let dump = Minidump::read(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            let binaries = symbolicate::Binaries::find(&binaries);
+            if binaries.is_empty() {
+                println!("{}", "No ELF binaries found in the search path".yellow());
+            }
+            let mut walker = stackwalk::Walker::new(&dump, binaries)
+                .context("The dump has no system info for a supported CPU")?;
+            print!("{}", walker.report());
+        }
+
+        Commands::Symbols {
+            command: SymbolsCommand::Dump { binary, output },
+        } => {
+            let text = dump_syms::dump_symbols(&binary)?;
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, text).with_context(|| format!("Failed to write {}", output.display()))?;
+                    println!("{} {}", "✓ Symbols written to:".green(), output.display());
+                }
+                None => print!("{}", text),
+            }
+        }
+
+        Commands::Symbols {
+            command: SymbolsCommand::Add { file, store },
+        } => {
+            let contents = std::fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            let text = match String::from_utf8(contents) {
+                Ok(text) if text.starts_with("MODULE ") => text,
+                _ => dump_syms::dump_symbols(&file)?,
+            };
+            let symbols = symbols::SymbolFile::parse(&text)
+                .with_context(|| format!("Failed to parse symbols of {}", file.display()))?;
+            let path = symbols::store_path(&store, &symbols.name, &symbols.debug_id);
+            std::fs::create_dir_all(path.parent().unwrap())
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            write_file(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("{} {}", "✓ Added:".green(), path.display());
+        }
+
+        Commands::Verify { dump, key, unsigned } => {
+            let minidump = Minidump::read(&dump)
+                .with_context(|| format!("Failed to read {} (truncated or not a minidump)", dump.display()))?;
+            let problems = minidump.structural_problems();
+            for problem in &problems {
+                println!("{} {}", "✗".red(), problem);
+            }
+            if !problems.is_empty() {
+                bail!("{} is damaged", dump.display());
+            }
+            println!("{} {}", "✓ Structure OK:".green(), dump.display());
+
+            if !unsigned {
+                let key = key.context("Pass the signer's public key with --key")?;
+                let sidecar = Sidecar::read(&dump).context("Failed to read the sidecar")?;
+                let trusted = read_key(&key)?;
+                verify_signature(minidump.bytes(), &sidecar, &trusted).context("Signature check failed")?;
+                println!("{} {}", "✓ Signed by:".green(), format_key(&trusted));
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        Commands::Server { socket } => {
+            let server = CrashServer::bind(&socket, &cli.output)
+                .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+
+            println!("{} {}", "Crash server listening on".green(), socket.display());
+            println!("Output directory: {}", cli.output.display());
+
+            server.run(|dump| match &dump.result {
+                Ok(path) => println!(
+                    "{} pid {} {}: {}\n  Crash ID: {}",
+                    "✓ Dumped".green(),
+                    dump.pid,
+                    dump.signal_info,
+                    path.display(),
+                    dump.crash_id
+                ),
+                Err(e) => eprintln!("{} pid {}: {}", "✗ Dump failed for".red(), dump.pid, e),
+            })?;
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// A key given as hex digits or as the path of a file holding them
+fn read_key(key: &str) -> Result<[u8; 32]> {
+    let hex = if Path::new(key).is_file() {
+        std::fs::read_to_string(key).with_context(|| format!("Failed to read key file {}", key))?
+    } else {
+        key.to_string()
+    };
+    parse_key(&hex).context("Invalid key")
+}
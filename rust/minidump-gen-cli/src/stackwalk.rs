@@ -0,0 +1,487 @@
+//! `stackwalk`: unwind every thread of a dump and symbolicate the frames.
+//!
+//! Each caller is found with the first method that works: the `STACK CFI`
+//! rules from the module's symbol file, the frame pointer chain, then a scan
+//! of the stack for a word that points into a module's code. A frame pointer
+//! chain leading outside every module, as through JIT code, is followed when
+//! scanning finds nothing, instead of ending the walk there. Symbols come
+//! from a [`SymbolSource`]: a Breakpad symbol store, or the binaries
+//! themselves for `symbolicate`.
+
+use crate::symbols::{evaluate, store_path, SourceLocation, SymbolFile};
+use minidump_handler::reader::{Cpu, Minidump, Module, Registers};
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::path::Path;
+
+/// Frames unwound per thread at most
+const MAX_FRAMES: usize = 256;
+/// Stack words scanned for a return address below the context frame
+const CONTEXT_SCAN_WORDS: u64 = 1024;
+/// Stack words scanned for a return address below other frames
+const FRAME_SCAN_WORDS: u64 = 64;
+
+/// How a frame was found, from most to least reliable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    /// A function inlined into the frame below it
+    Inlined,
+    Context,
+    CallFrameInfo,
+    FramePointer,
+    Scan,
+}
+
+impl fmt::Display for Trust {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Inlined => "inlining",
+            Self::Context => "given context",
+            Self::CallFrameInfo => "call frame info",
+            Self::FramePointer => "frame pointer",
+            Self::Scan => "stack scanning",
+        })
+    }
+}
+
+/// One unwound frame
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pc: u64,
+    pub sp: u64,
+    pub trust: Trust,
+    /// Registers by their `STACK CFI` names, e.g. `$rip` or `x29`
+    registers: HashMap<String, u64>,
+}
+
+/// Register names of an architecture, as symbol files spell them
+struct Arch {
+    pc: &'static str,
+    sp: &'static str,
+    fp: &'static str,
+    /// Registers a callee preserves, so callers inherit their values
+    callee_saved: &'static [&'static str],
+}
+
+const AMD64: Arch = Arch {
+    pc: "$rip",
+    sp: "$rsp",
+    fp: "$rbp",
+    callee_saved: &["$rbx", "$rbp", "$r12", "$r13", "$r14", "$r15"],
+};
+
+const ARM64: Arch = Arch {
+    pc: "pc",
+    sp: "sp",
+    fp: "x29",
+    callee_saved: &["x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28", "x29"],
+};
+
+/// What is known about one module's code; addresses are module-relative
+pub trait ModuleSymbols {
+    /// Where `address` is in the source, innermost first: functions inlined
+    /// at `address` come before the function they were inlined into
+    fn locate(&self, address: u64) -> Vec<SourceLocation>;
+
+    /// Unwind rules in effect at `address`, as `register -> postfix
+    /// expression`
+    fn cfi_rules(&self, address: u64) -> Option<HashMap<String, String>>;
+}
+
+impl ModuleSymbols for SymbolFile {
+    fn locate(&self, address: u64) -> Vec<SourceLocation> {
+        SymbolFile::locate(self, address).into_iter().collect()
+    }
+
+    fn cfi_rules(&self, address: u64) -> Option<HashMap<String, String>> {
+        SymbolFile::cfi_rules(self, address)
+    }
+}
+
+/// Where the walker gets a module's symbols from
+pub trait SymbolSource {
+    /// Symbols of `module`, `None` when there are none
+    fn load(&mut self, module: &Module) -> Option<Box<dyn ModuleSymbols>>;
+}
+
+/// Breakpad symbol store laid out as `<module>/<DEBUGID>/<module>.sym`
+pub struct SymbolStore<'a>(pub &'a Path);
+
+impl SymbolSource for SymbolStore<'_> {
+    fn load(&mut self, module: &Module) -> Option<Box<dyn ModuleSymbols>> {
+        let path = store_path(self.0, module.file_name(), &module.debug_id()?);
+        Some(Box::new(SymbolFile::read(&path).ok()?))
+    }
+}
+
+/// Unwinds threads of one dump, loading symbols on demand
+pub struct Walker<'a> {
+    dump: &'a Minidump,
+    arch: &'static Arch,
+    modules: Vec<Module>,
+    source: Box<dyn SymbolSource + 'a>,
+    /// Symbols per module index; `None` when the source has none
+    symbols: HashMap<usize, Option<Box<dyn ModuleSymbols>>>,
+}
+
+impl<'a> Walker<'a> {
+    /// `None` for CPUs without register decoding
+    pub fn new(dump: &'a Minidump, source: impl SymbolSource + 'a) -> Option<Self> {
+        let arch = match dump.system_info()?.cpu {
+            Cpu::X86_64 => &AMD64,
+            Cpu::Arm64 => &ARM64,
+            Cpu::Other(_) => return None,
+        };
+        Some(Self {
+            dump,
+            arch,
+            modules: dump.modules(),
+            source: Box::new(source),
+            symbols: HashMap::new(),
+        })
+    }
+
+    /// The stacks of all threads, the crashing one first, as text
+    pub fn report(&mut self) -> String {
+        let mut out = String::new();
+        let exception = self.dump.exception();
+        let mut threads = self.dump.threads();
+        threads.sort_by_key(|t| Some(t.thread_id) != exception.map(|e| e.thread_id));
+
+        for thread in threads {
+            let crashed = exception.filter(|e| e.thread_id == thread.thread_id);
+            let context = crashed.map_or(thread.context, |e| e.context);
+            let _ = writeln!(
+                out,
+                "Thread {}{}",
+                thread.thread_id,
+                if crashed.is_some() { " (crashed)" } else { "" }
+            );
+            let Some(registers) = self.dump.registers(context) else {
+                let _ = writeln!(out, "  (no registers)\n");
+                continue;
+            };
+            let mut number = 0;
+            for (i, frame) in self.walk(&registers).iter().enumerate() {
+                let mut descriptions = self.describe(frame, i == 0);
+                let function = descriptions.pop().unwrap_or_default();
+                for inlined in descriptions {
+                    let _ = writeln!(out, "{:>3}  {}", number, inlined);
+                    let _ = writeln!(out, "     Found by: {}", Trust::Inlined);
+                    number += 1;
+                }
+                let _ = writeln!(out, "{:>3}  {}", number, function);
+                let _ = writeln!(out, "     pc = {:#018x}  sp = {:#018x}", frame.pc, frame.sp);
+                let _ = writeln!(out, "     Found by: {}", frame.trust);
+                number += 1;
+            }
+            let _ = writeln!(out);
+        }
+
+        let missing: Vec<String> = (0..self.modules.len())
+            .filter(|&i| self.symbols.get(&i).is_some_and(Option::is_none))
+            .map(|i| {
+                let module = &self.modules[i];
+                format!("{} ({})", module.file_name(), module.debug_id().unwrap_or_default())
+            })
+            .collect();
+        if !missing.is_empty() {
+            let _ = writeln!(out, "No symbols for: {}", missing.join(", "));
+        }
+        out
+    }
+
+    /// Unwind from the context `registers`
+    pub fn walk(&mut self, registers: &Registers) -> Vec<Frame> {
+        let mut frames = vec![Frame {
+            pc: registers.pc,
+            sp: registers.sp,
+            trust: Trust::Context,
+            registers: self.named(registers),
+        }];
+        while frames.len() < MAX_FRAMES {
+            let callee = frames.last().unwrap();
+            let first = frames.len() == 1;
+            let Some(caller) = self
+                .unwind_cfi(callee, first)
+                .or_else(|| {
+                    self.unwind_frame_pointer(callee)
+                        .filter(|frame| self.module_index(frame.pc).is_some())
+                })
+                .or_else(|| self.unwind_scan(callee, first))
+                .or_else(|| self.unwind_frame_pointer(callee))
+            else {
+                break;
+            };
+            frames.push(caller);
+        }
+        frames
+    }
+
+    /// `module!function [file : line + offset]`, or what is known of it,
+    /// preceded by the functions inlined there
+    fn describe(&mut self, frame: &Frame, first: bool) -> Vec<String> {
+        // Return addresses point after the call; look up the call itself
+        let address = if first { frame.pc } else { frame.pc.saturating_sub(1) };
+        let Some(index) = self.module_index(address) else {
+            return vec![format!("{:#x}", frame.pc)];
+        };
+        let module_name = self.modules[index].file_name().to_string();
+        let relative = address - self.modules[index].base;
+        let locations = self.symbols_for(index).map_or_else(Vec::new, |s| s.locate(relative));
+        if locations.is_empty() {
+            return vec![format!("{} + {:#x}", module_name, frame.pc - self.modules[index].base)];
+        }
+        let outermost = locations.len() - 1;
+        locations
+            .iter()
+            .enumerate()
+            .map(|(i, location)| format!("{}!{}", module_name, format_location(location, first, i < outermost)))
+            .collect()
+    }
+
+    fn unwind_cfi(&mut self, callee: &Frame, first: bool) -> Option<Frame> {
+        let address = if first { callee.pc } else { callee.pc.checked_sub(1)? };
+        let index = self.module_index(address)?;
+        let base = self.modules[index].base;
+        let rules = self.symbols_for(index)?.cfi_rules(address - base)?;
+
+        let mut inputs = callee.registers.clone();
+        let cfa = evaluate(rules.get(".cfa")?, &inputs, |a| self.read_u64(a))?;
+        inputs.insert(".cfa".to_string(), cfa);
+
+        let mut registers = self.callee_saved(callee);
+        for (register, rule) in &rules {
+            if register != ".cfa" && register != ".ra" {
+                if let Some(value) = evaluate(rule, &inputs, |a| self.read_u64(a)) {
+                    registers.insert(register.clone(), value);
+                }
+            }
+        }
+        let pc = evaluate(rules.get(".ra")?, &inputs, |a| self.read_u64(a))?;
+        self.caller(callee, pc, cfa, registers, Trust::CallFrameInfo)
+    }
+
+    fn unwind_frame_pointer(&self, callee: &Frame) -> Option<Frame> {
+        let fp = *callee.registers.get(self.arch.fp)?;
+        if fp < callee.sp || fp % 8 != 0 {
+            return None;
+        }
+        let pc = self.read_u64(fp.checked_add(8)?)?;
+        let mut registers = self.callee_saved(callee);
+        registers.insert(self.arch.fp.to_string(), self.read_u64(fp)?);
+        self.caller(callee, pc, fp.checked_add(16)?, registers, Trust::FramePointer)
+    }
+
+    fn unwind_scan(&mut self, callee: &Frame, first: bool) -> Option<Frame> {
+        let words = if first { CONTEXT_SCAN_WORDS } else { FRAME_SCAN_WORDS };
+        for slot in (0..words).map_while(|i| callee.sp.checked_add(i * 8)) {
+            let value = self.read_u64(slot)?;
+            let Some(index) = self.module_index(value.wrapping_sub(1)) else {
+                continue;
+            };
+            // With symbols, only addresses inside a function count
+            let relative = value - 1 - self.modules[index].base;
+            if let Some(symbols) = self.symbols_for(index) {
+                if symbols.locate(relative).is_empty() {
+                    continue;
+                }
+            }
+            let registers = self.callee_saved(callee);
+            if let Some(frame) = self.caller(callee, value, slot.checked_add(8)?, registers, Trust::Scan) {
+                return Some(frame);
+            }
+        }
+        None
+    }
+
+    /// The caller frame, if it makes progress up the stack
+    fn caller(
+        &self,
+        callee: &Frame,
+        pc: u64,
+        sp: u64,
+        mut registers: HashMap<String, u64>,
+        trust: Trust,
+    ) -> Option<Frame> {
+        if pc < 0x1000 || sp <= callee.sp {
+            return None;
+        }
+        registers.insert(self.arch.pc.to_string(), pc);
+        registers.insert(self.arch.sp.to_string(), sp);
+        Some(Frame { pc, sp, trust, registers })
+    }
+
+    fn callee_saved(&self, callee: &Frame) -> HashMap<String, u64> {
+        self.arch
+            .callee_saved
+            .iter()
+            .filter_map(|&name| Some((name.to_string(), *callee.registers.get(name)?)))
+            .collect()
+    }
+
+    /// Registers of a context under the names symbol files use
+    fn named(&self, registers: &Registers) -> HashMap<String, u64> {
+        registers
+            .named
+            .iter()
+            .map(|&(name, value)| {
+                let name = match (self.arch.pc, name) {
+                    ("$rip", name) => format!("${}", name),
+                    (_, "fp") => "x29".to_string(),
+                    (_, "lr") => "x30".to_string(),
+                    (_, name) => name.to_string(),
+                };
+                (name, value)
+            })
+            .collect()
+    }
+
+    fn module_index(&self, address: u64) -> Option<usize> {
+        self.modules.iter().position(|m| m.contains(address))
+    }
+
+    fn symbols_for(&mut self, index: usize) -> Option<&dyn ModuleSymbols> {
+        let module = &self.modules[index];
+        let source = &mut self.source;
+        self.symbols
+            .entry(index)
+            .or_insert_with(|| source.load(module))
+            .as_deref()
+    }
+
+    fn read_u64(&self, address: u64) -> Option<u64> {
+        let bytes = self.dump.memory_at(address)?.get(..8)?;
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+/// `function [file : line + offset]`; inlined functions have no offset
+fn format_location(location: &SourceLocation, first: bool, inlined: bool) -> String {
+    // Undo the -1 applied to return addresses
+    let offset = location.function_offset + u64::from(!first);
+    match (&location.file, location.line, inlined) {
+        (Some(file), Some(line), true) => format!("{} [{} : {}]", location.function, file, line),
+        (Some(file), Some(line), false) => {
+            format!("{} [{} : {} + {:#x}]", location.function, file, line, offset)
+        }
+        (_, _, true) => location.function.clone(),
+        (_, _, false) => format!("{} + {:#x}", location.function, offset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minidump_handler::reader::standard_stream;
+    use minidump_handler::streams::{self, testing::TestDump};
+
+    const BASE: u64 = 0x10000;
+    const STACK: u64 = 0x8000;
+
+    /// One module whose function at 0x100 has CFI and keeps no frame pointer
+    struct TestSymbols;
+
+    impl ModuleSymbols for TestSymbols {
+        fn locate(&self, address: u64) -> Vec<SourceLocation> {
+            let function = match address {
+                0x100..=0x1ff => "leaf",
+                0x200..=0x2ff => "middle",
+                0x300..=0x3ff => "outer",
+                _ => return Vec::new(),
+            };
+            vec![SourceLocation {
+                function: function.to_string(),
+                function_offset: address & 0xff,
+                file: None,
+                line: None,
+            }]
+        }
+
+        fn cfi_rules(&self, address: u64) -> Option<HashMap<String, String>> {
+            (0x100..0x110).contains(&address).then(|| {
+                [(".cfa", "$rsp 16 +"), (".ra", ".cfa -8 + ^"), ("$rbp", ".cfa -16 + ^")]
+                    .iter()
+                    .map(|&(register, rule)| (register.to_string(), rule.to_string()))
+                    .collect()
+            })
+        }
+    }
+
+    impl SymbolSource for TestSymbols {
+        fn load(&mut self, module: &Module) -> Option<Box<dyn ModuleSymbols>> {
+            (module.base == BASE).then(|| Box::new(TestSymbols) as Box<dyn ModuleSymbols>)
+        }
+    }
+
+    /// An x86_64 dump with the module and 256 bytes of stack holding `words`
+    fn dump_with_stack(words: &[(u64, u64)]) -> Minidump {
+        let mut module_list = 1u32.to_le_bytes().to_vec();
+        let mut module = vec![0u8; 108];
+        module[0..8].copy_from_slice(&BASE.to_le_bytes());
+        module[8..12].copy_from_slice(&0x1000u32.to_le_bytes());
+        module_list.extend_from_slice(&module);
+        let mut stack = vec![0u8; 256];
+        for &(address, value) in words {
+            let offset = (address - STACK) as usize;
+            stack[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        TestDump::new()
+            .amd64_linux()
+            .stream(standard_stream::MODULE_LIST, module_list)
+            .stream(standard_stream::MEMORY_LIST, streams::memory_list_stream(&[(STACK, stack)]).unwrap())
+            .build()
+    }
+
+    #[test]
+    fn test_walk_cfi_and_frame_pointer() {
+        let dump = dump_with_stack(&[
+            // leaf: saved rbp and return address, found by CFI
+            (STACK, STACK + 0x40),
+            (STACK + 0x08, BASE + 0x210),
+            // middle's frame record, leading to outer
+            (STACK + 0x40, STACK + 0x60),
+            (STACK + 0x48, BASE + 0x310),
+            // outer's frame record, leading out of every module
+            (STACK + 0x60, 0),
+            (STACK + 0x68, 0x7000_0000),
+        ]);
+        let registers = Registers {
+            pc: BASE + 0x100,
+            sp: STACK,
+            fp: STACK + 0x40,
+            named: vec![("rip", BASE + 0x100), ("rsp", STACK), ("rbp", STACK + 0x40)],
+        };
+
+        let mut walker = Walker::new(&dump, TestSymbols).unwrap();
+        let frames = walker.walk(&registers);
+        let found: Vec<_> = frames.iter().map(|f| (f.pc, f.sp, f.trust)).collect();
+        assert_eq!(
+            found,
+            [
+                (BASE + 0x100, STACK, Trust::Context),
+                (BASE + 0x210, STACK + 0x10, Trust::CallFrameInfo),
+                (BASE + 0x310, STACK + 0x50, Trust::FramePointer),
+                (0x7000_0000, STACK + 0x70, Trust::FramePointer),
+            ]
+        );
+        assert_eq!(walker.describe(&frames[1], false), ["!middle + 0x10"]);
+        assert_eq!(walker.describe(&frames[3], false), ["0x70000000"]);
+    }
+
+    #[test]
+    fn test_walk_stops_at_the_top_of_the_address_space() {
+        let dump = dump_with_stack(&[]);
+        let registers = Registers {
+            pc: BASE + 0x500,
+            sp: u64::MAX - 15,
+            fp: u64::MAX - 7,
+            named: vec![("rip", BASE + 0x500), ("rsp", u64::MAX - 15), ("rbp", u64::MAX - 7)],
+        };
+
+        let mut walker = Walker::new(&dump, TestSymbols).unwrap();
+        let frames = walker.walk(&registers);
+        assert_eq!(frames.len(), 1);
+    }
+}
@@ -0,0 +1,258 @@
+//! `symbolicate`: resolve stacks straight from the DWARF of local binaries.
+//!
+//! ELF executables and shared libraries under the search directories are
+//! matched to the dump's modules by debug id, which is derived from the build
+//! id as for symbol files. Frames, with the functions inlined into them, are
+//! read from the DWARF with `addr2line` and unwind rules from `.eh_frame`, so
+//! `cargo build` output works without producing symbol files first.
+
+use crate::dump_syms::{code_id, functions, module_bias, row_rules, CfiRegisters, Function};
+use crate::stackwalk::{ModuleSymbols, SymbolSource};
+use crate::symbols::SourceLocation;
+use anyhow::{Context, Result};
+use gimli::{
+    BaseAddresses, DebugFrame, EhFrame, EndianRcSlice, EndianSlice, RunTimeEndian, UnwindContext, UnwindSection,
+};
+use minidump_handler::reader::{breakpad_debug_id, Module};
+use object::{Object, ObjectKind, ObjectSection};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+type DwarfReader = EndianRcSlice<RunTimeEndian>;
+
+/// Binaries found in the search directories, by debug id
+pub struct Binaries {
+    /// Path and whether it has DWARF
+    by_debug_id: HashMap<String, (PathBuf, bool)>,
+}
+
+impl Binaries {
+    /// Index the ELF executables and shared libraries under `directories`
+    pub fn find(directories: &[PathBuf]) -> Self {
+        let mut binaries = Self {
+            by_debug_id: HashMap::new(),
+        };
+        for directory in directories {
+            binaries.scan(directory);
+        }
+        binaries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_debug_id.is_empty()
+    }
+
+    fn scan(&mut self, directory: &Path) {
+        let Ok(entries) = std::fs::read_dir(directory) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Symlinks are not followed, so loops can't happen
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => self.scan(&path),
+                Ok(kind) if kind.is_file() && is_elf(&path) => self.add(path),
+                _ => {}
+            }
+        }
+    }
+
+    fn add(&mut self, path: PathBuf) {
+        let Ok(data) = std::fs::read(&path) else {
+            return;
+        };
+        let Ok(file) = object::File::parse(&*data) else {
+            return;
+        };
+        if !matches!(file.kind(), ObjectKind::Executable | ObjectKind::Dynamic) {
+            return;
+        }
+        let Ok(code_id) = code_id(&file) else {
+            return;
+        };
+        let has_dwarf = file.section_by_name(".debug_info").is_some();
+        // Of several copies, such as cargo's hard links, prefer one with DWARF
+        let entry = self
+            .by_debug_id
+            .entry(breakpad_debug_id(&code_id))
+            .or_insert_with(|| (path.clone(), has_dwarf));
+        if has_dwarf && !entry.1 {
+            *entry = (path, has_dwarf);
+        }
+    }
+}
+
+impl SymbolSource for Binaries {
+    fn load(&mut self, module: &Module) -> Option<Box<dyn ModuleSymbols>> {
+        let (path, _) = self.by_debug_id.get(&module.debug_id()?)?;
+        Some(Box::new(DwarfModule::load(path).ok()?))
+    }
+}
+
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+/// One binary's DWARF, symbol table and call frame information
+struct DwarfModule {
+    context: addr2line::Context<DwarfReader>,
+    /// Link address of the first segment, where the module base is
+    bias: u64,
+    /// Sorted by address
+    functions: Vec<Function>,
+    registers: &'static CfiRegisters,
+    endian: RunTimeEndian,
+    text_address: u64,
+    /// Section address and contents
+    eh_frame: Option<(u64, Vec<u8>)>,
+    debug_frame: Option<Vec<u8>>,
+}
+
+impl DwarfModule {
+    fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let file = object::File::parse(&*data).with_context(|| format!("Failed to parse {}", path.display()))?;
+        let registers = CfiRegisters::of(file.architecture()).context("unsupported architecture")?;
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+
+        let dwarf = gimli::Dwarf::load(|id| -> Result<DwarfReader> {
+            let contents = match file.section_by_name(id.name()) {
+                Some(section) => section.uncompressed_data()?.into_owned(),
+                None => Vec::new(),
+            };
+            Ok(EndianRcSlice::new(Rc::from(contents), endian))
+        })?;
+        let section = |name| -> Result<Option<(u64, Vec<u8>)>> {
+            match file.section_by_name(name) {
+                Some(section) => Ok(Some((section.address(), section.uncompressed_data()?.into_owned()))),
+                None => Ok(None),
+            }
+        };
+
+        Ok(Self {
+            context: addr2line::Context::from_dwarf(dwarf)?,
+            bias: module_bias(&file),
+            functions: functions(&file),
+            registers,
+            endian,
+            text_address: file.section_by_name(".text").map_or(0, |s| s.address()),
+            eh_frame: section(".eh_frame")?,
+            debug_frame: section(".debug_frame")?.map(|(_, contents)| contents),
+        })
+    }
+
+    /// The symbol table's function containing the link-time `address`
+    fn function(&self, address: u64) -> Option<&Function> {
+        let index = self.functions.partition_point(|f| f.address <= address);
+        let function = &self.functions[index.checked_sub(1)?];
+        (address < function.address + function.size).then_some(function)
+    }
+}
+
+impl ModuleSymbols for DwarfModule {
+    fn locate(&self, address: u64) -> Vec<SourceLocation> {
+        let address = address + self.bias;
+        let symbol = self.function(address);
+        let function_offset = symbol.map_or(0, |f| address - f.address);
+
+        let mut locations = Vec::new();
+        if let Ok(mut frames) = self.context.find_frames(address).skip_all_loads() {
+            while let Ok(Some(frame)) = frames.next() {
+                let name = frame
+                    .function
+                    .as_ref()
+                    .and_then(|function| function.raw_name().ok())
+                    .map(|name| format!("{:#}", rustc_demangle::demangle(&name)));
+                let Some(function) = name.or_else(|| symbol.map(|f| f.name.clone())) else {
+                    continue;
+                };
+                locations.push(SourceLocation {
+                    function,
+                    function_offset,
+                    file: frame.location.as_ref().and_then(|l| l.file.map(str::to_string)),
+                    line: frame.location.as_ref().and_then(|l| l.line),
+                });
+            }
+        }
+        if locations.is_empty() {
+            // No DWARF for this address: the symbol table is all there is
+            locations.extend(symbol.map(|f| SourceLocation {
+                function: f.name.clone(),
+                function_offset,
+                file: None,
+                line: None,
+            }));
+        }
+        locations
+    }
+
+    fn cfi_rules(&self, address: u64) -> Option<HashMap<String, String>> {
+        let address = address + self.bias;
+        let from_eh_frame = self.eh_frame.as_ref().and_then(|(section_address, contents)| {
+            let mut eh_frame = EhFrame::new(contents, self.endian);
+            eh_frame.set_address_size(8);
+            let bases = BaseAddresses::default()
+                .set_eh_frame(*section_address)
+                .set_text(self.text_address);
+            unwind_rules(&eh_frame, &bases, address, self.registers)
+        });
+        from_eh_frame.or_else(|| {
+            let mut debug_frame = DebugFrame::new(self.debug_frame.as_deref()?, self.endian);
+            debug_frame.set_address_size(8);
+            unwind_rules(&debug_frame, &BaseAddresses::default(), address, self.registers)
+        })
+    }
+}
+
+/// Rules of the CFI row covering the link-time `address` in `section`
+fn unwind_rules<'a, S: UnwindSection<EndianSlice<'a, RunTimeEndian>>>(
+    section: &S,
+    bases: &BaseAddresses,
+    address: u64,
+    registers: &CfiRegisters,
+) -> Option<HashMap<String, String>> {
+    let fde = section
+        .fde_for_address(bases, address, |section, bases, offset| section.cie_from_offset(bases, offset))
+        .ok()?;
+    let mut context = Box::new(UnwindContext::new());
+    let row = fde.unwind_info_for_address(section, bases, &mut context, address).ok()?;
+    let rules = row_rules(row, fde.cie().return_address_register(), registers)?;
+    Some(rules.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_in_own_binary() {
+        let binary = std::env::current_exe().unwrap();
+        let module = DwarfModule::load(&binary).unwrap();
+        let function = module
+            .functions
+            .iter()
+            .find(|f| f.name.ends_with("symbolicate::tests::test_locate_in_own_binary"))
+            .expect("test function in the symbol table");
+        let address = function.address - module.bias;
+
+        let locations = module.locate(address);
+        let outermost = locations.last().unwrap();
+        assert!(outermost.function.ends_with("test_locate_in_own_binary"));
+        assert!(outermost.file.as_deref().unwrap().ends_with("symbolicate.rs"));
+        assert_eq!(outermost.function_offset, 0);
+        assert!(module.cfi_rules(address).unwrap().contains_key(".ra"));
+
+        let binaries = Binaries::find(&[binary.parent().unwrap().to_path_buf()]);
+        assert!(!binaries.is_empty());
+    }
+}
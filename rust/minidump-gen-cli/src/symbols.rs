@@ -0,0 +1,302 @@
+//! Breakpad symbol files and the store they are kept in.
+//!
+//! A store lays files out as `<module>/<DEBUGID>/<module>.sym`. Only the
+//! records needed to symbolicate and unwind are read: `MODULE`, `FILE`,
+//! `FUNC` with its line records, `PUBLIC` and `STACK CFI`.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// One function from a `FUNC` record
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub address: u64,
+    pub size: u64,
+    pub name: String,
+    /// (address, size, line, file index), sorted by address
+    pub lines: Vec<(u64, u64, u32, u32)>,
+}
+
+/// Where an address is in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub function: String,
+    /// Offset of the address from the function's start
+    pub function_offset: u64,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// `STACK CFI INIT` record with the `STACK CFI` records following it
+#[derive(Debug, Clone)]
+pub struct CfiRange {
+    pub address: u64,
+    pub size: u64,
+    /// Rules at `address`
+    pub initial: String,
+    /// Rules changed at later addresses, sorted by address
+    pub changes: Vec<(u64, String)>,
+}
+
+/// A parsed `.sym` file; all addresses are relative to the module base
+#[derive(Debug, Default)]
+pub struct SymbolFile {
+    pub os: String,
+    pub arch: String,
+    pub debug_id: String,
+    pub name: String,
+    pub files: HashMap<u32, String>,
+    /// Sorted by address
+    pub functions: Vec<Function>,
+    /// (address, name), sorted by address
+    pub publics: Vec<(u64, String)>,
+    /// Sorted by address
+    pub cfi: Vec<CfiRange>,
+}
+
+/// Path of a module's symbol file in the store at `store`
+pub fn store_path(store: &Path, module_name: &str, debug_id: &str) -> PathBuf {
+    store
+        .join(module_name)
+        .join(debug_id)
+        .join(format!("{}.sym", module_name.strip_suffix(".pdb").unwrap_or(module_name)))
+}
+
+impl SymbolFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut symbols = SymbolFile::default();
+        for (number, line) in text.lines().enumerate() {
+            let malformed = || format!("line {}: malformed record {:?}", number + 1, line);
+            let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match kind {
+                "MODULE" => {
+                    let fields: Vec<&str> = rest.splitn(4, ' ').collect();
+                    let [os, arch, debug_id, name] = fields[..] else {
+                        bail!(malformed());
+                    };
+                    (symbols.os, symbols.arch) = (os.to_string(), arch.to_string());
+                    (symbols.debug_id, symbols.name) = (debug_id.to_string(), name.to_string());
+                }
+                "FILE" => {
+                    let (index, name) = rest.split_once(' ').with_context(malformed)?;
+                    symbols.files.insert(index.parse().with_context(malformed)?, name.to_string());
+                }
+                "FUNC" => {
+                    let rest = rest.strip_prefix("m ").unwrap_or(rest);
+                    let fields: Vec<&str> = rest.splitn(4, ' ').collect();
+                    let [address, size, _parameter_size, name] = fields[..] else {
+                        bail!(malformed());
+                    };
+                    symbols.functions.push(Function {
+                        address: hex(address).with_context(malformed)?,
+                        size: hex(size).with_context(malformed)?,
+                        name: name.to_string(),
+                        lines: Vec::new(),
+                    });
+                }
+                "PUBLIC" => {
+                    let rest = rest.strip_prefix("m ").unwrap_or(rest);
+                    let fields: Vec<&str> = rest.splitn(3, ' ').collect();
+                    let [address, _parameter_size, name] = fields[..] else {
+                        bail!(malformed());
+                    };
+                    symbols.publics.push((hex(address).with_context(malformed)?, name.to_string()));
+                }
+                "STACK" => {
+                    if let Some(rest) = rest.strip_prefix("CFI INIT ") {
+                        let fields: Vec<&str> = rest.splitn(3, ' ').collect();
+                        let [address, size, rules] = fields[..] else {
+                            bail!(malformed());
+                        };
+                        symbols.cfi.push(CfiRange {
+                            address: hex(address).with_context(malformed)?,
+                            size: hex(size).with_context(malformed)?,
+                            initial: rules.to_string(),
+                            changes: Vec::new(),
+                        });
+                    } else if let Some(rest) = rest.strip_prefix("CFI ") {
+                        let (address, rules) = rest.split_once(' ').with_context(malformed)?;
+                        let entry = symbols.cfi.last_mut().with_context(malformed)?;
+                        entry.changes.push((hex(address).with_context(malformed)?, rules.to_string()));
+                    }
+                    // `STACK WIN` records only matter for Windows modules
+                }
+                "INFO" | "INLINE" | "INLINE_ORIGIN" | "" => {}
+                _ => {
+                    // A line record belongs to the last FUNC
+                    let fields: Vec<&str> = line.split(' ').collect();
+                    let [address, size, line_number, file] = fields[..] else {
+                        bail!(malformed());
+                    };
+                    let function = symbols.functions.last_mut().with_context(malformed)?;
+                    function.lines.push((
+                        hex(address).with_context(malformed)?,
+                        hex(size).with_context(malformed)?,
+                        line_number.parse().with_context(malformed)?,
+                        file.parse().with_context(malformed)?,
+                    ));
+                }
+            }
+        }
+
+        symbols.functions.sort_by_key(|f| f.address);
+        for function in &mut symbols.functions {
+            function.lines.sort_by_key(|l| l.0);
+        }
+        symbols.publics.sort_by_key(|p| p.0);
+        symbols.cfi.sort_by_key(|c| c.address);
+        Ok(symbols)
+    }
+
+    /// Function, file and line of the module-relative `address`
+    pub fn locate(&self, address: u64) -> Option<SourceLocation> {
+        let index = self.functions.partition_point(|f| f.address <= address);
+        if let Some(function) = index.checked_sub(1).map(|i| &self.functions[i]) {
+            if address < function.address + function.size.max(1) {
+                let line_index = function.lines.partition_point(|l| l.0 <= address);
+                let line = line_index
+                    .checked_sub(1)
+                    .map(|i| function.lines[i])
+                    .filter(|&(start, size, _, _)| address < start + size.max(1));
+                return Some(SourceLocation {
+                    function: function.name.clone(),
+                    function_offset: address - function.address,
+                    file: line.and_then(|(_, _, _, file)| self.files.get(&file).cloned()),
+                    line: line.map(|(_, _, line, _)| line),
+                });
+            }
+        }
+
+        let index = self.publics.partition_point(|p| p.0 <= address);
+        let (start, name) = &self.publics[index.checked_sub(1)?];
+        Some(SourceLocation {
+            function: name.clone(),
+            function_offset: address - start,
+            file: None,
+            line: None,
+        })
+    }
+
+    /// Unwind rules in effect at the module-relative `address`, as
+    /// `register -> postfix expression`
+    pub fn cfi_rules(&self, address: u64) -> Option<HashMap<String, String>> {
+        let index = self.cfi.partition_point(|c| c.address <= address);
+        let range = &self.cfi[index.checked_sub(1)?];
+        if address >= range.address + range.size {
+            return None;
+        }
+        let mut rules = HashMap::new();
+        parse_rules(&range.initial, &mut rules);
+        for (_, changed) in range.changes.iter().take_while(|(at, _)| *at <= address) {
+            parse_rules(changed, &mut rules);
+        }
+        Some(rules)
+    }
+}
+
+/// Split `".cfa: $rsp 8 + .ra: .cfa -8 + ^"` into its rules
+fn parse_rules(text: &str, rules: &mut HashMap<String, String>) {
+    let mut register: Option<&str> = None;
+    let mut expression: Vec<&str> = Vec::new();
+    for token in text.split_whitespace() {
+        if let Some(name) = token.strip_suffix(':') {
+            if let Some(register) = register {
+                rules.insert(register.to_string(), expression.join(" "));
+            }
+            register = Some(name);
+            expression.clear();
+        } else {
+            expression.push(token);
+        }
+    }
+    if let Some(register) = register {
+        rules.insert(register.to_string(), expression.join(" "));
+    }
+}
+
+/// Evaluate a Breakpad postfix expression. `read` dereferences memory for
+/// `^`; registers and `.cfa` come from `registers`.
+pub fn evaluate(
+    expression: &str,
+    registers: &HashMap<String, u64>,
+    read: impl Fn(u64) -> Option<u64>,
+) -> Option<u64> {
+    let mut stack: Vec<u64> = Vec::new();
+    for token in expression.split_whitespace() {
+        let binary = |stack: &mut Vec<u64>, op: fn(u64, u64) -> Option<u64>| {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(op(a, b)?);
+            Some(())
+        };
+        match token {
+            "+" => binary(&mut stack, |a, b| Some(a.wrapping_add(b)))?,
+            "-" => binary(&mut stack, |a, b| Some(a.wrapping_sub(b)))?,
+            "*" => binary(&mut stack, |a, b| Some(a.wrapping_mul(b)))?,
+            "/" => binary(&mut stack, |a, b| a.checked_div(b))?,
+            "%" => binary(&mut stack, |a, b| a.checked_rem(b))?,
+            "@" => binary(&mut stack, |a, b| (b != 0).then(|| a & !(b - 1)))?,
+            "^" => {
+                let address = stack.pop()?;
+                stack.push(read(address)?);
+            }
+            _ => {
+                let value = match registers.get(token) {
+                    Some(&value) => value,
+                    None => match token.strip_prefix('-') {
+                        Some(digits) => digits.parse::<u64>().ok()?.wrapping_neg(),
+                        None => token.parse().ok()?,
+                    },
+                };
+                stack.push(value);
+            }
+        }
+    }
+    (stack.len() == 1).then(|| stack[0])
+}
+
+fn hex(field: &str) -> Result<u64> {
+    Ok(u64::from_str_radix(field, 16)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_and_unwind_rules() {
+        let symbols = SymbolFile::parse(
+            "MODULE Linux x86_64 0123456789ABCDEF0123456789ABCDEF0 libdemo.so\n\
+             FILE 0 src/lib.rs\n\
+             FUNC 1000 40 0 demo::crash\n\
+             1000 10 12 0\n\
+             1010 30 13 0\n\
+             PUBLIC 2000 0 exported\n\
+             STACK CFI INIT 1000 40 .cfa: $rsp 8 + .ra: .cfa -8 + ^\n\
+             STACK CFI 1001 .cfa: $rsp 16 + $rbp: .cfa -16 + ^\n",
+        )
+        .unwrap();
+        assert_eq!(symbols.name, "libdemo.so");
+
+        let location = symbols.locate(0x1014).unwrap();
+        assert_eq!(location.function, "demo::crash");
+        assert_eq!((location.file.as_deref(), location.line), (Some("src/lib.rs"), Some(13)));
+        assert_eq!(symbols.locate(0x2008).unwrap().function, "exported");
+        assert!(symbols.locate(0x10).is_none());
+
+        let rules = symbols.cfi_rules(0x1020).unwrap();
+        assert_eq!(rules[".cfa"], "$rsp 16 +");
+        let mut registers = HashMap::from([("$rsp".to_string(), 0x7000u64)]);
+        let cfa = evaluate(&rules[".cfa"], &registers, |_| None).unwrap();
+        registers.insert(".cfa".to_string(), cfa);
+        let ra = evaluate(&rules[".ra"], &registers, |address| (address == 0x7008).then_some(0x1234)).unwrap();
+        assert_eq!((cfa, ra), (0x7010, 0x1234));
+        assert!(symbols.cfi_rules(0x1040).is_none());
+    }
+}
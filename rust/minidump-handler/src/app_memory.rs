@@ -0,0 +1,164 @@
+//! Application-registered memory regions copied into every dump.
+//!
+//! Minidumps only hold stacks and a little memory around them. Heap state the
+//! application cares about is registered here, copied when a dump is written
+//! and merged into the dump's memory list; a name mapping stream says which
+//! range is which.
+
+use serde::Serialize;
+use std::sync::{Mutex, MutexGuard};
+
+/// Total bytes of registered memory copied into a dump by default
+pub const DEFAULT_APP_MEMORY_LIMIT: usize = 4 * 1024 * 1024;
+
+static REGIONS: Mutex<Vec<Region>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Serialize)]
+struct Region {
+    address: usize,
+    size: usize,
+    name: String,
+}
+
+/// Regions captured for one dump
+#[derive(Debug, Default)]
+pub(crate) struct Captured {
+    /// (start address, bytes) for the memory list
+    pub memory: Vec<(u64, Vec<u8>)>,
+    /// JSON list of `{ address, size, name }`
+    pub names: Vec<u8>,
+}
+
+fn lock() -> MutexGuard<'static, Vec<Region>> {
+    REGIONS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Include `len` bytes at `ptr` in every dump written from now on, under
+/// `name`. Registering the same pointer again replaces the earlier entry.
+///
+/// Regions are copied in registration order until
+/// `HandlerConfig::app_memory_limit` is reached; the region crossing the
+/// limit is truncated and later ones are left out.
+///
+/// # Safety
+///
+/// The range must stay readable until it is passed to
+/// [`unregister_memory_region`]: it is read from the crash handler without
+/// any further checks.
+pub unsafe fn register_memory_region(ptr: *const u8, len: usize, name: &str) {
+    if ptr.is_null() || len == 0 {
+        return;
+    }
+    let mut regions = lock();
+    regions.retain(|region| region.address != ptr as usize);
+    regions.push(Region {
+        address: ptr as usize,
+        size: len,
+        name: name.to_string(),
+    });
+}
+
+/// Stop including the region registered at `ptr`. Returns whether one was
+/// registered.
+pub fn unregister_memory_region(ptr: *const u8) -> bool {
+    let mut regions = lock();
+    let before = regions.len();
+    regions.retain(|region| region.address != ptr as usize);
+    regions.len() != before
+}
+
+/// Copy the registered regions, at most `limit` bytes in total, from
+/// `process`'s memory (`None` for this process). Gives up rather than wait if
+/// a registration is in progress.
+///
+/// The writer child reads its crashed parent this way: the registry is the
+/// child's copy of the parent's, the bytes come from `/proc/<pid>/mem`.
+pub(crate) fn capture(limit: usize, process: Option<u32>) -> Option<Captured> {
+    let regions = REGIONS.try_lock().ok()?;
+    if regions.is_empty() {
+        return None;
+    }
+    let memory = match process {
+        Some(pid) => Some(std::fs::File::open(format!("/proc/{}/mem", pid)).ok()?),
+        None => None,
+    };
+
+    let mut captured = Captured::default();
+    let mut names = Vec::new();
+    let mut remaining = limit;
+    for region in regions.iter() {
+        let size = region.size.min(remaining);
+        if size == 0 {
+            break;
+        }
+        let bytes = match &memory {
+            Some(memory) => {
+                use std::os::unix::fs::FileExt;
+
+                let mut bytes = vec![0u8; size];
+                if memory.read_exact_at(&mut bytes, region.address as u64).is_err() {
+                    continue;
+                }
+                bytes
+            }
+            // SAFETY: `register_memory_region` requires the range to stay readable
+            None => unsafe { std::slice::from_raw_parts(region.address as *const u8, size) }.to_vec(),
+        };
+        captured.memory.push((region.address as u64, bytes));
+        names.push(Region {
+            size,
+            ..region.clone()
+        });
+        remaining -= size;
+    }
+    captured.names = serde_json::to_vec(&names).ok()?;
+    Some(captured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Held by tests that use the global registry, which start from an empty one
+    static REGISTRY: Mutex<()> = Mutex::new(());
+
+    fn empty_registry() -> MutexGuard<'static, ()> {
+        let guard = REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        lock().clear();
+        guard
+    }
+
+    #[test]
+    fn test_capture_respects_limit() {
+        let _registry = empty_registry();
+        let state = [7u8; 64];
+        let counters = [1u8; 32];
+        unsafe {
+            register_memory_region(state.as_ptr(), state.len(), "state");
+            register_memory_region(counters.as_ptr(), counters.len(), "counters");
+        }
+
+        // Registration order decides what the limit cuts off
+        let captured = capture(80, None).unwrap();
+        let at = |ptr: *const u8| {
+            captured
+                .memory
+                .iter()
+                .find(|(address, _)| *address == ptr as u64)
+                .map(|(_, bytes)| bytes.clone())
+        };
+        assert_eq!(captured.memory.len(), 2);
+        assert_eq!(at(state.as_ptr()), Some(state.to_vec()));
+        assert_eq!(at(counters.as_ptr()), Some(vec![1u8; 16]));
+
+        let names: serde_json::Value = serde_json::from_slice(&captured.names).unwrap();
+        assert!(names.as_array().unwrap().iter().any(|r| r["name"] == "counters" && r["size"] == 16));
+
+        let through_proc = capture(80, Some(std::process::id())).unwrap();
+        assert!(through_proc.memory.contains(&(state.as_ptr() as u64, state.to_vec())));
+
+        assert!(unregister_memory_region(state.as_ptr()));
+        assert!(unregister_memory_region(counters.as_ptr()));
+        assert!(!unregister_memory_region(counters.as_ptr()));
+    }
+}
@@ -0,0 +1,142 @@
+use libc::c_void;
+
+/// How the faulting instruction accessed memory, when the CPU reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+impl FaultAccess {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Execute => "execute",
+        }
+    }
+}
+
+/// Registers of the crashing thread, taken from the signal's `ucontext_t`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuContext {
+    pub pc: usize,
+    pub sp: usize,
+    pub fp: usize,
+    /// Access type of a page fault, if the platform exposes it
+    pub access: Option<FaultAccess>,
+}
+
+impl CpuContext {
+    /// Read the registers out of the third argument of an `SA_SIGINFO` handler.
+    ///
+    /// # Safety
+    /// `ucontext` must be null or point to the `ucontext_t` the kernel passed
+    /// to the signal handler.
+    pub(crate) unsafe fn from_ucontext(ucontext: *const c_void) -> Self {
+        if ucontext.is_null() {
+            return Self::default();
+        }
+        read_ucontext(&*(ucontext as *const libc::ucontext_t))
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn read_ucontext(uc: &libc::ucontext_t) -> CpuContext {
+    let gregs = &uc.uc_mcontext.gregs;
+    let trapno = gregs[libc::REG_TRAPNO as usize];
+    let err = gregs[libc::REG_ERR as usize];
+    CpuContext {
+        pc: gregs[libc::REG_RIP as usize] as usize,
+        sp: gregs[libc::REG_RSP as usize] as usize,
+        fp: gregs[libc::REG_RBP as usize] as usize,
+        access: x86_page_fault_access(trapno as u64, err as u64),
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+fn read_ucontext(uc: &libc::ucontext_t) -> CpuContext {
+    // The ESR lives in an optional record inside `__reserved`, which libc
+    // keeps private, so the access type is left to the pc heuristic.
+    CpuContext {
+        pc: uc.uc_mcontext.pc as usize,
+        sp: uc.uc_mcontext.sp as usize,
+        fp: uc.uc_mcontext.regs[29] as usize,
+        access: None,
+    }
+}
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), target_arch = "x86_64"))]
+fn read_ucontext(uc: &libc::ucontext_t) -> CpuContext {
+    if uc.uc_mcontext.is_null() {
+        return CpuContext::default();
+    }
+    let mc = unsafe { &*uc.uc_mcontext };
+    CpuContext {
+        pc: mc.__ss.__rip as usize,
+        sp: mc.__ss.__rsp as usize,
+        fp: mc.__ss.__rbp as usize,
+        access: x86_page_fault_access(mc.__es.__trapno as u64, mc.__es.__err as u64),
+    }
+}
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), target_arch = "aarch64"))]
+fn read_ucontext(uc: &libc::ucontext_t) -> CpuContext {
+    if uc.uc_mcontext.is_null() {
+        return CpuContext::default();
+    }
+    let mc = unsafe { &*uc.uc_mcontext };
+    CpuContext {
+        pc: mc.__ss.__pc as usize,
+        sp: mc.__ss.__sp as usize,
+        fp: mc.__ss.__fp as usize,
+        access: arm64_esr_access(mc.__es.__esr),
+    }
+}
+
+#[cfg(not(any(
+    all(
+        any(target_os = "linux", target_os = "macos", target_os = "ios"),
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )
+)))]
+fn read_ucontext(_uc: &libc::ucontext_t) -> CpuContext {
+    CpuContext::default()
+}
+
+/// Decode the x86 page-fault error code (only meaningful for trap 14, #PF)
+#[cfg(target_arch = "x86_64")]
+fn x86_page_fault_access(trapno: u64, err: u64) -> Option<FaultAccess> {
+    const PAGE_FAULT: u64 = 14;
+    const PF_WRITE: u64 = 1 << 1;
+    const PF_INSTR: u64 = 1 << 4;
+
+    if trapno != PAGE_FAULT {
+        return None;
+    }
+    Some(if err & PF_INSTR != 0 {
+        FaultAccess::Execute
+    } else if err & PF_WRITE != 0 {
+        FaultAccess::Write
+    } else {
+        FaultAccess::Read
+    })
+}
+
+/// Decode the exception class and WnR bit of an AArch64 ESR
+#[cfg(all(any(target_os = "macos", target_os = "ios"), target_arch = "aarch64"))]
+fn arm64_esr_access(esr: u32) -> Option<FaultAccess> {
+    const EC_IABT_LOWER: u32 = 0x20;
+    const EC_IABT_CUR: u32 = 0x21;
+    const EC_DABT_LOWER: u32 = 0x24;
+    const EC_DABT_CUR: u32 = 0x25;
+    const WNR: u32 = 1 << 6;
+
+    match esr >> 26 {
+        EC_IABT_LOWER | EC_IABT_CUR => Some(FaultAccess::Execute),
+        EC_DABT_LOWER | EC_DABT_CUR if esr & WNR != 0 => Some(FaultAccess::Write),
+        EC_DABT_LOWER | EC_DABT_CUR => Some(FaultAccess::Read),
+        _ => None,
+    }
+}
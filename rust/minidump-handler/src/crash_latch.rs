@@ -0,0 +1,160 @@
+//! Serializes crash handling when several threads fault at the same time.
+//!
+//! The first crashing thread takes the latch and writes the dump. Threads that
+//! crash while it is held record their id and signal, then block until the
+//! owner is done; normally the owner re-raises and the process dies with them
+//! still parked, so their crash never races the dump in progress.
+
+use libc::c_int;
+use serde::Serialize;
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering};
+
+/// How many concurrently crashing threads are remembered
+const MAX_RECORDED: usize = 32;
+
+/// Thread id of the latch owner, 0 when no crash is being handled
+static OWNER: AtomicU64 = AtomicU64::new(0);
+/// Signal the latch owner is handling
+static OWNER_SIGNAL: AtomicI32 = AtomicI32::new(0);
+static RECORDED: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_THREAD: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SIGNAL: AtomicI32 = AtomicI32::new(0);
+static THREADS: [AtomicU64; MAX_RECORDED] = [NO_THREAD; MAX_RECORDED];
+static SIGNALS: [AtomicI32; MAX_RECORDED] = [NO_SIGNAL; MAX_RECORDED];
+
+/// Outcome of [`acquire`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Entry {
+    /// The calling thread now owns the crash and must call [`release`]
+    Owner,
+    /// The calling thread already owns the latch: it crashed inside the
+    /// handler while handling `first_signal`
+    Reentered { first_signal: c_int },
+}
+
+/// Take the crash latch, blocking while another thread is handling a crash
+pub(crate) fn acquire(tid: u64, signal: c_int) -> Entry {
+    let mut recorded = false;
+    loop {
+        match OWNER.compare_exchange(0, tid, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => {
+                OWNER_SIGNAL.store(signal, Ordering::Release);
+                return Entry::Owner;
+            }
+            Err(owner) if owner == tid => {
+                return Entry::Reentered {
+                    first_signal: OWNER_SIGNAL.load(Ordering::Acquire),
+                }
+            }
+            Err(_) => {
+                if !recorded {
+                    record(tid, signal);
+                    recorded = true;
+                }
+                let delay = libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: 1_000_000,
+                };
+                unsafe { libc::nanosleep(&delay, std::ptr::null_mut()) };
+            }
+        }
+    }
+}
+
+/// Release the latch so a waiting thread can handle its own crash
+pub(crate) fn release() {
+    RECORDED.store(0, Ordering::Release);
+    OWNER.store(0, Ordering::Release);
+}
+
+fn record(tid: u64, signal: c_int) {
+    let index = RECORDED.fetch_add(1, Ordering::AcqRel);
+    if index < MAX_RECORDED {
+        SIGNALS[index].store(signal, Ordering::Relaxed);
+        THREADS[index].store(tid, Ordering::Release);
+    }
+}
+
+/// A thread that crashed while another thread's crash was being handled
+#[derive(Debug, Clone, Serialize)]
+pub struct AdditionalCrash {
+    pub tid: u64,
+    pub signal: c_int,
+    pub signal_name: &'static str,
+}
+
+/// Threads that crashed while the current owner held the latch
+pub(crate) fn additional_crashes() -> Vec<AdditionalCrash> {
+    let count = RECORDED.load(Ordering::Acquire).min(MAX_RECORDED);
+    (0..count)
+        .filter_map(|i| {
+            let tid = THREADS[i].load(Ordering::Acquire);
+            let signal = SIGNALS[i].load(Ordering::Relaxed);
+            (tid != 0).then(|| AdditionalCrash {
+                tid,
+                signal,
+                signal_name: crate::signal_name(signal),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{mpsc, Arc, Barrier};
+    use std::time::Duration;
+
+    #[test]
+    fn test_racing_threads_are_serialized() {
+        let barrier = Arc::new(Barrier::new(2));
+        let inside = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let (entered, entries) = mpsc::channel();
+
+        let threads: Vec<_> = [(1001u64, libc::SIGSEGV), (1002u64, libc::SIGBUS)]
+            .into_iter()
+            .map(|(tid, signal)| {
+                let (barrier, inside, done, entered) = (barrier.clone(), inside.clone(), done.clone(), entered.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    assert_eq!(acquire(tid, signal), Entry::Owner);
+                    inside.fetch_add(1, Ordering::AcqRel);
+                    entered.send(tid).unwrap();
+                    while !done.load(Ordering::Acquire) {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                    inside.fetch_sub(1, Ordering::AcqRel);
+                    release();
+                })
+            })
+            .collect();
+
+        // One thread owns the crash; the other is parked and recorded
+        let owner = entries.recv().unwrap();
+        let parked = if owner == 1001 { 1002 } else { 1001 };
+        let mut recorded = additional_crashes();
+        while recorded.is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+            recorded = additional_crashes();
+        }
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].tid, parked);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(inside.load(Ordering::Acquire), 1);
+        assert!(entries.try_recv().is_err());
+
+        // Released, the parked thread handles its own crash
+        done.store(true, Ordering::Release);
+        let next = entries.recv().unwrap();
+        assert_eq!(next, parked);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(OWNER.load(Ordering::Acquire), 0);
+    }
+}
@@ -0,0 +1,524 @@
+//! Out-of-process dumping over a Unix socket.
+//!
+//! A crashed process is a poor place to write a dump from, even through a
+//! fork: the heap may be corrupt and locks may be held. Instead the client
+//! connects to a [`CrashServer`] ahead of time. On a crash the signal handler
+//! sends a fixed-size request over that connection and blocks while the
+//! server ptraces the client and writes the dump.
+//!
+//! Wire format, all integers little-endian:
+//! - hello (client, once): `MHS1`, pid `u32`, length `u32`, annotations as JSON
+//! - request (client, per crash): `MHCR`, tid `u64`, signal `i32`, code `i32`,
+//!   address `u64`, pc `u64`, sp `u64`, fp `u64`, access `u8`, dump mode `u8`
+//!   (0 minimal, 1 normal, 2 full), has encryption key `u8`, 1 byte padding,
+//!   encryption key (32 bytes), then the raw `siginfo_t`, `ucontext_t` and, on
+//!   x86_64, the FPU state behind `uc_mcontext.fpregs`, in the client's
+//!   native layout (client and server must be built for the same target)
+//! - reply (server): status `u8` (0 = dumped), crash id (16 bytes)
+//!
+//! The socket is only accessible to its owner, and the server refuses clients
+//! running as another user.
+//!
+//! The server dumps with the client's exception record and crashing thread
+//! registers, its dump mode and encryption key. Dumps aren't signed, since
+//! the signing key stays in the client, and `DumpMode::Full` is written as
+//! `Normal`: the private memory is only collected in-process.
+
+use crate::dump_mode::Completion;
+use crate::{filename, process_context, streams, ContextCollectors, CpuContext, DumpMode, FaultAccess, HandlerConfig, IndirectMemory, HandlerError, Result, Sidecar, SignalInfo, Uuid};
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const HELLO_MAGIC: &[u8; 4] = b"MHS1";
+const REQUEST_MAGIC: &[u8; 4] = b"MHCR";
+/// Fixed fields of a request, before the raw crash context
+const REQUEST_HEADER_SIZE: usize = 88;
+const REQUEST_SIZE: usize = REQUEST_HEADER_SIZE + std::mem::size_of::<RawCrash>();
+const REPLY_SIZE: usize = 17;
+/// Largest annotations payload a server accepts
+const MAX_ANNOTATIONS_SIZE: usize = 64 * 1024;
+/// Mode of the listening socket
+const SOCKET_MODE: u32 = 0o600;
+/// How long a crashing client waits for the server before dumping itself
+const DUMP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connection to the crash server, or -1 when none is registered
+static SERVER_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// The crashing thread's state as the kernel passed it to the signal handler
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub(crate) struct RawCrash {
+    siginfo: libc::siginfo_t,
+    context: libc::ucontext_t,
+    /// x86_64 keeps the FPU registers outside `ucontext_t`
+    #[cfg(target_arch = "x86_64")]
+    float_state: libc::_libc_fpstate,
+}
+
+impl RawCrash {
+    /// Copy the arguments of an `SA_SIGINFO` handler
+    ///
+    /// # Safety
+    /// `info` and `context` must be null or the pointers the kernel passed to
+    /// the signal handler.
+    pub(crate) unsafe fn capture(info: *const libc::siginfo_t, context: *const libc::c_void) -> Self {
+        let mut raw: Self = std::mem::zeroed();
+        if !info.is_null() {
+            raw.siginfo = *info;
+        }
+        if !context.is_null() {
+            raw.context = *(context as *const libc::ucontext_t);
+            #[cfg(target_arch = "x86_64")]
+            if !raw.context.uc_mcontext.fpregs.is_null() {
+                raw.float_state = *raw.context.uc_mcontext.fpregs;
+            }
+        }
+        raw
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // SAFETY: plain C data, read as bytes
+        unsafe { std::slice::from_raw_parts(self as *const Self as *const u8, std::mem::size_of::<Self>()) }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        assert_eq!(bytes.len(), std::mem::size_of::<Self>());
+        // SAFETY: any bit pattern is a valid value of these C structs
+        unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Self) }
+    }
+
+    /// Crash context for minidump-writer: exception record and the crashing
+    /// thread's registers
+    fn crash_context(&self, pid: u32, tid: u64) -> minidump_writer::crash_context::CrashContext {
+        // SAFETY: plain C struct, all zeroes is valid
+        let mut siginfo: libc::signalfd_siginfo = unsafe { std::mem::zeroed() };
+        siginfo.ssi_signo = self.siginfo.si_signo as u32;
+        siginfo.ssi_errno = self.siginfo.si_errno;
+        siginfo.ssi_code = self.siginfo.si_code;
+        siginfo.ssi_addr = unsafe { self.siginfo.si_addr() } as u64;
+
+        // The pointer into the client's memory is meaningless here; the FPU
+        // state travels by value
+        let mut context = self.context;
+        #[cfg(target_arch = "x86_64")]
+        {
+            context.uc_mcontext.fpregs = std::ptr::null_mut();
+        }
+        minidump_writer::crash_context::CrashContext {
+            // SAFETY: crash-context mirrors the libc layouts
+            context: unsafe { std::mem::transmute::<libc::ucontext_t, minidump_writer::crash_context::ucontext_t>(context) },
+            #[cfg(target_arch = "x86_64")]
+            float_state: unsafe {
+                std::mem::transmute::<libc::_libc_fpstate, minidump_writer::crash_context::fpregset_t>(self.float_state)
+            },
+            // The FPSIMD registers sit in an optional record of `__reserved`
+            // that libc keeps private; they are left out
+            #[cfg(not(target_arch = "x86_64"))]
+            float_state: unsafe { std::mem::zeroed() },
+            siginfo,
+            pid: pid as libc::pid_t,
+            tid: tid as libc::pid_t,
+        }
+    }
+}
+
+/// What the client asks the server to dump
+struct Request {
+    signal_info: SignalInfo,
+    mode: DumpMode,
+    encryption_key: Option<[u8; 32]>,
+    crash: RawCrash,
+}
+
+/// Register this process with the crash server listening on `socket_path`.
+///
+/// Once registered, crashes are dumped by the server; the in-process writer
+/// is only used if the server can't be reached. `annotations` are stored by
+/// the server and added to every dump of this process.
+pub fn connect_crash_server(socket_path: &Path, annotations: &[(&str, &str)]) -> Result<()> {
+    let mut stream = UnixStream::connect(socket_path)?;
+    stream.set_read_timeout(Some(DUMP_TIMEOUT))?;
+
+    let annotations: BTreeMap<&str, &str> = annotations.iter().copied().collect();
+    let json = serde_json::to_vec(&annotations).map_err(std::io::Error::from)?;
+    let mut hello = Vec::with_capacity(12 + json.len());
+    hello.extend_from_slice(HELLO_MAGIC);
+    hello.extend_from_slice(&std::process::id().to_le_bytes());
+    hello.extend_from_slice(&streams::to_u32(json.len() as u64)?.to_le_bytes());
+    hello.extend_from_slice(&json);
+    stream.write_all(&hello)?;
+
+    // With Yama's ptrace_scope=1 only an ancestor may attach unless we say so
+    let server = peer_credentials(stream.as_raw_fd())?;
+    unsafe { libc::prctl(libc::PR_SET_PTRACER, server.pid as libc::c_ulong, 0, 0, 0) };
+
+    let previous = SERVER_FD.swap(stream.into_raw_fd(), Ordering::AcqRel);
+    if previous >= 0 {
+        unsafe { libc::close(previous) };
+    }
+    Ok(())
+}
+
+/// Ask the registered server to dump this process and wait until it has.
+///
+/// Called from the signal handler, so only raw `write`/`read` are used.
+/// `config` gives the dump mode and encryption key for the server to use.
+/// Returns the crash id of the dump, or `None` if no server wrote one.
+pub(crate) fn request_dump(signal_info: &SignalInfo, crash: &RawCrash, config: &HandlerConfig) -> Option<Uuid> {
+    let fd = SERVER_FD.load(Ordering::Acquire);
+    if fd < 0 {
+        return None;
+    }
+
+    let request = encode_request(signal_info, crash, config.dump_mode, config.encryption_key.as_ref());
+    if !write_all_raw(fd, &request) {
+        return None;
+    }
+    let mut reply = [0u8; REPLY_SIZE];
+    if !read_exact_raw(fd, &mut reply) || reply[0] != 0 {
+        return None;
+    }
+    Some(Uuid::from_bytes(reply[1..].try_into().unwrap()))
+}
+
+fn write_all_raw(fd: RawFd, mut buf: &[u8]) -> bool {
+    while !buf.is_empty() {
+        let n = unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if n > 0 {
+            buf = &buf[n as usize..];
+        } else if n == 0 || std::io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+            return false;
+        }
+    }
+    true
+}
+
+fn read_exact_raw(fd: RawFd, buf: &mut [u8]) -> bool {
+    let mut len = 0;
+    while len < buf.len() {
+        let n = unsafe { libc::read(fd, buf[len..].as_mut_ptr() as *mut libc::c_void, buf.len() - len) };
+        if n > 0 {
+            len += n as usize;
+        } else if n == 0 || std::io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+            return false;
+        }
+    }
+    true
+}
+
+fn encode_request(
+    signal_info: &SignalInfo,
+    crash: &RawCrash,
+    mode: DumpMode,
+    encryption_key: Option<&[u8; 32]>,
+) -> [u8; REQUEST_SIZE] {
+    let context = &signal_info.context;
+    let mut raw = [0u8; REQUEST_SIZE];
+    raw[0..4].copy_from_slice(REQUEST_MAGIC);
+    raw[4..12].copy_from_slice(&signal_info.tid.to_le_bytes());
+    raw[12..16].copy_from_slice(&signal_info.signal.to_le_bytes());
+    raw[16..20].copy_from_slice(&signal_info.code.to_le_bytes());
+    raw[20..28].copy_from_slice(&(signal_info.address as u64).to_le_bytes());
+    raw[28..36].copy_from_slice(&(context.pc as u64).to_le_bytes());
+    raw[36..44].copy_from_slice(&(context.sp as u64).to_le_bytes());
+    raw[44..52].copy_from_slice(&(context.fp as u64).to_le_bytes());
+    raw[52] = match context.access {
+        None => 0,
+        Some(FaultAccess::Read) => 1,
+        Some(FaultAccess::Write) => 2,
+        Some(FaultAccess::Execute) => 3,
+    };
+    raw[53] = match mode {
+        DumpMode::Minimal => 0,
+        DumpMode::Normal => 1,
+        DumpMode::Full => 2,
+    };
+    if let Some(key) = encryption_key {
+        raw[54] = 1;
+        raw[56..88].copy_from_slice(key);
+    }
+    raw[REQUEST_HEADER_SIZE..].copy_from_slice(crash.as_bytes());
+    raw
+}
+
+fn decode_request(raw: &[u8; REQUEST_SIZE]) -> Result<Request> {
+    if &raw[0..4] != REQUEST_MAGIC {
+        return Err(streams::invalid_data("bad crash request"));
+    }
+    let u64_at = |offset: usize| u64::from_le_bytes(raw[offset..offset + 8].try_into().unwrap());
+    let i32_at = |offset: usize| i32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+    let signal_info = SignalInfo {
+        signal: i32_at(12),
+        code: i32_at(16),
+        address: u64_at(20) as usize,
+        tid: u64_at(4),
+        context: CpuContext {
+            pc: u64_at(28) as usize,
+            sp: u64_at(36) as usize,
+            fp: u64_at(44) as usize,
+            access: match raw[52] {
+                1 => Some(FaultAccess::Read),
+                2 => Some(FaultAccess::Write),
+                3 => Some(FaultAccess::Execute),
+                _ => None,
+            },
+        },
+    };
+    Ok(Request {
+        signal_info,
+        mode: match raw[53] {
+            0 => DumpMode::Minimal,
+            2 => DumpMode::Full,
+            _ => DumpMode::Normal,
+        },
+        encryption_key: (raw[54] == 1).then(|| raw[56..88].try_into().unwrap()),
+        crash: RawCrash::from_bytes(&raw[REQUEST_HEADER_SIZE..]),
+    })
+}
+
+/// Pid and user of the process on the other end of a Unix socket
+fn peer_credentials(fd: RawFd) -> Result<libc::ucred> {
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let r = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if r != 0 {
+        return Err(HandlerError::Io(std::io::Error::last_os_error()));
+    }
+    Ok(cred)
+}
+
+/// A dump the server wrote, or tried to write, for a client
+#[derive(Debug)]
+pub struct ServedDump {
+    pub pid: u32,
+    pub signal_info: SignalInfo,
+    pub crash_id: Uuid,
+    /// Path of the dump, or why it could not be written
+    pub result: Result<PathBuf>,
+}
+
+/// Server that writes dumps for crashing clients
+pub struct CrashServer {
+    listener: UnixListener,
+    dump_directory: PathBuf,
+}
+
+impl CrashServer {
+    /// Listen on `socket_path`, replacing a stale socket left there. The
+    /// socket is made `0600`; clients of other users are refused anyway.
+    pub fn bind(socket_path: &Path, dump_directory: &Path) -> Result<Self> {
+        crate::dump_directory::prepare(dump_directory)?;
+        if let Ok(metadata) = std::fs::symlink_metadata(socket_path) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(socket_path)?;
+            }
+        }
+        let listener = UnixListener::bind(socket_path)?;
+        std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(SOCKET_MODE))?;
+        Ok(Self {
+            listener,
+            dump_directory: dump_directory.to_path_buf(),
+        })
+    }
+
+    /// Serve clients until accepting fails, one thread per client.
+    /// `on_dump` is called after every dump attempt.
+    pub fn run<F>(self, on_dump: F) -> Result<()>
+    where
+        F: Fn(&ServedDump) + Send + Sync + 'static,
+    {
+        let on_dump = Arc::new(on_dump);
+        let dump_directory = Arc::new(self.dump_directory);
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let on_dump = on_dump.clone();
+            let dump_directory = dump_directory.clone();
+            std::thread::spawn(move || {
+                let _ = serve_client(stream, &dump_directory, &*on_dump);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Handle one client until it disconnects
+fn serve_client(mut stream: UnixStream, dump_directory: &Path, on_dump: &dyn Fn(&ServedDump)) -> Result<()> {
+    // Dumps hold the client's memory: only serve our own user
+    let peer = peer_credentials(stream.as_raw_fd())?;
+    if peer.uid != unsafe { libc::geteuid() } {
+        return Err(streams::invalid_data("client runs as another user"));
+    }
+
+    let mut header = [0u8; 12];
+    stream.read_exact(&mut header)?;
+    if &header[0..4] != HELLO_MAGIC {
+        return Err(streams::invalid_data("bad client hello"));
+    }
+    let pid = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let len = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+    if len > MAX_ANNOTATIONS_SIZE {
+        return Err(streams::invalid_data("annotations too large"));
+    }
+    let mut json = vec![0u8; len];
+    stream.read_exact(&mut json)?;
+    let annotations: BTreeMap<String, String> =
+        serde_json::from_slice(&json).map_err(std::io::Error::from)?;
+
+    // Only dump the process that is actually connected
+    if peer.pid as u32 != pid {
+        return Err(streams::invalid_data("client pid does not match its credentials"));
+    }
+
+    loop {
+        let mut raw = [0u8; REQUEST_SIZE];
+        match stream.read_exact(&mut raw) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let request = decode_request(&raw)?;
+        let crash_id = Uuid::new_v4();
+        let result = dump_client(pid, &request, crash_id, &annotations, dump_directory);
+
+        let mut reply = [0u8; REPLY_SIZE];
+        reply[0] = if result.is_ok() { 0 } else { 1 };
+        reply[1..].copy_from_slice(crash_id.as_bytes());
+        stream.write_all(&reply)?;
+
+        on_dump(&ServedDump {
+            pid,
+            signal_info: request.signal_info,
+            crash_id,
+            result,
+        });
+    }
+}
+
+/// ptrace-dump a blocked client and tag the dump like the in-process path
+fn dump_client(
+    pid: u32,
+    request: &Request,
+    crash_id: Uuid,
+    annotations: &BTreeMap<String, String>,
+    dump_directory: &Path,
+) -> Result<PathBuf> {
+    use minidump_writer::minidump_writer::MinidumpWriter;
+
+    let signal_info = &request.signal_info;
+    let mut path = filename::PathBuffer::new();
+    let fields = filename::FilenameFields {
+        prefix: "crash",
+        signal: signal_info.signal_name(),
+        pid,
+        tid: signal_info.tid,
+        timestamp_ms: crate::sys::unix_time_ms(),
+        uuid: crash_id,
+    };
+    filename::reserve_dump_path(dump_directory, filename::DEFAULT_TEMPLATE, &fields, &mut path)?;
+    let path = path.as_path().to_path_buf();
+
+    let mut extra = crate::custom_streams(crash_id, false, None, None)?;
+    extra.extend(process_context::collect(Some(pid), &ContextCollectors::default()));
+    if !annotations.is_empty() {
+        let json = serde_json::to_vec(annotations).map_err(std::io::Error::from)?;
+        extra.push((streams::stream_type::ANNOTATIONS, json));
+    }
+    // Private and indirect memory are only collected in-process, so `Full`
+    // is written as `Normal`
+    let completion = Completion {
+        streams: extra,
+        mode: match request.mode {
+            DumpMode::Minimal => DumpMode::Minimal,
+            DumpMode::Normal | DumpMode::Full => DumpMode::Normal,
+        },
+        thread: signal_info.tid as u32,
+        size_limit: 0,
+        indirect: IndirectMemory::default(),
+        process: Some(pid),
+        encryption_key: request.encryption_key,
+        signing_key: None,
+        environment_allowlist: Some(ContextCollectors::default().environment_allowlist),
+    };
+
+    let mut sidecar = Sidecar::for_signal(crash_id, signal_info);
+    sidecar.annotations = (!annotations.is_empty()).then(|| annotations.clone());
+
+    let mut writer = MinidumpWriter::new(pid as libc::pid_t, signal_info.tid as libc::pid_t);
+    writer.set_crash_context(request.crash.crash_context(pid, signal_info.tid));
+    crate::write_completed(&path, &completion, sidecar, &mut writer)?;
+    Ok(match request.encryption_key {
+        Some(_) => crate::encrypted_path(&path),
+        None => path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let signal_info = SignalInfo {
+            signal: libc::SIGSEGV,
+            code: 2,
+            address: 0xdead_beef,
+            tid: 1234,
+            context: CpuContext {
+                pc: 0x5555_0000_1000,
+                sp: 0x7ffd_0000_0000,
+                fp: 0x7ffd_0000_0040,
+                access: Some(FaultAccess::Write),
+            },
+        };
+        let mut crash: RawCrash = unsafe { std::mem::zeroed() };
+        crash.siginfo.si_signo = libc::SIGSEGV;
+        crash.siginfo.si_code = 2;
+        let key = [7u8; 32];
+        let request = decode_request(&encode_request(&signal_info, &crash, DumpMode::Minimal, Some(&key))).unwrap();
+        assert_eq!(request.mode, DumpMode::Minimal);
+        assert_eq!(request.encryption_key, Some(key));
+        let context = request.crash.crash_context(42, 1234);
+        assert_eq!(context.siginfo.ssi_signo, libc::SIGSEGV as u32);
+        assert_eq!(context.siginfo.ssi_code, 2);
+        assert_eq!(context.tid, 1234);
+
+        let decoded = request.signal_info;
+        assert_eq!(decoded.tid, 1234);
+        assert_eq!(decoded.code, 2);
+        assert_eq!(decoded.address, 0xdead_beef);
+        assert_eq!(decoded.context.sp, signal_info.context.sp);
+        assert_eq!(decoded.context.access, Some(FaultAccess::Write));
+        assert_eq!(decoded.reason(), signal_info.reason());
+    }
+
+    #[test]
+    fn test_socket_is_private() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("crash.sock");
+        let _server = CrashServer::bind(&socket, &tmp.path().join("dumps")).unwrap();
+        assert_eq!(std::fs::metadata(&socket).unwrap().mode() & 0o777, 0o600);
+
+        let client = UnixStream::connect(&socket).unwrap();
+        let peer = peer_credentials(client.as_raw_fd()).unwrap();
+        assert_eq!(peer.pid as u32, std::process::id());
+        assert_eq!(peer.uid, unsafe { libc::geteuid() });
+    }
+}
@@ -0,0 +1,120 @@
+//! The dump directory and the files written into it.
+//!
+//! Dumps hold raw process memory, so the directory is created `0700` and
+//! every dump and companion file `0600`, whatever the umask. A directory that
+//! already exists must belong to us; one that is group or world accessible
+//! is tightened. Free space on the dump filesystem is checked before each
+//! dump, see [`LowDiskSpace`].
+
+use crate::{HandlerError, Result};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+/// Free space below which dumps are degraded or skipped by default
+pub const DEFAULT_MIN_FREE_DISK_SPACE: u64 = 64 * 1024 * 1024;
+
+/// Mode of the dump directory
+const DIRECTORY_MODE: u32 = 0o700;
+/// Mode of dumps and the files written next to them
+pub(crate) const FILE_MODE: u32 = 0o600;
+
+/// What to do when the dump filesystem has less than
+/// `HandlerConfig::min_free_disk_space` bytes free
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LowDiskSpace {
+    /// Write a `DumpMode::Minimal` dump without extra memory
+    #[default]
+    Minimal,
+    /// Write no dump, only the sidecar saying why
+    Skip,
+}
+
+/// Create `directory` as `0700`, or check that the existing one is ours and
+/// take away access by others
+pub(crate) fn prepare(directory: &Path) -> Result<()> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(DIRECTORY_MODE)
+        .create(directory)
+        .map_err(|source| HandlerError::CreateDirectory {
+            path: directory.to_path_buf(),
+            source,
+        })?;
+
+    let metadata = std::fs::metadata(directory)?;
+    let euid = unsafe { libc::geteuid() };
+    if metadata.uid() != euid {
+        return Err(HandlerError::UnsafeDirectory {
+            path: directory.to_path_buf(),
+            reason: format!("owned by uid {}, not {}", metadata.uid(), euid),
+        });
+    }
+    if metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(directory, std::fs::Permissions::from_mode(DIRECTORY_MODE))?;
+    }
+    Ok(())
+}
+
+/// Create or truncate `path` as a `0600` file. The mode only applies when
+/// the file is created, so an existing file is tightened afterwards.
+pub(crate) fn create_file(path: &Path) -> std::io::Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(FILE_MODE)
+        .open(path)?;
+    file.set_permissions(std::fs::Permissions::from_mode(FILE_MODE))?;
+    Ok(file)
+}
+
+/// `std::fs::write` for a `0600` file
+pub fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    use std::io::Write;
+
+    create_file(path)?.write_all(contents.as_ref())
+}
+
+/// Bytes an unprivileged process can still write to the filesystem holding
+/// `path`
+pub(crate) fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_private_directory_and_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let directory = tmp.path().join("a/dumps");
+        prepare(&directory).unwrap();
+        assert_eq!(std::fs::metadata(&directory).unwrap().mode() & 0o777, 0o700);
+
+        std::fs::set_permissions(&directory, std::fs::Permissions::from_mode(0o755)).unwrap();
+        prepare(&directory).unwrap();
+        assert_eq!(std::fs::metadata(&directory).unwrap().mode() & 0o777, 0o700);
+
+        let file = directory.join("crash.dmp");
+        write_file(&file, b"MDMP").unwrap();
+        assert_eq!(std::fs::metadata(&file).unwrap().mode() & 0o777, 0o600);
+
+        // Overwriting a file others can read takes that access away
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_file(&file, b"MDMP").unwrap();
+        assert_eq!(std::fs::metadata(&file).unwrap().mode() & 0o777, 0o600);
+
+        assert!(free_space(&directory).unwrap() > 0);
+        assert!(free_space(&directory.join("missing")).is_none());
+    }
+}
@@ -0,0 +1,420 @@
+//! Dump size profiles.
+//!
+//! The writers always produce the same set of streams. `Minimal` dumps are cut
+//! down to the crashing thread afterwards by rebuilding the file from the
+//! streams that are kept; `Full` dumps get the process's private anonymous
+//! memory appended, within a size limit.
+
+use crate::reader::standard_stream;
+use crate::streams::{self, invalid_data, to_u32, DirectoryEntry, HEADER_SIZE};
+use crate::indirect_memory::{self, IndirectMemory};
+use crate::Result;
+use serde::Serialize;
+use std::io::{Cursor, Read, Seek, Write};
+
+/// Size `DumpMode::Full` dumps are kept within by default
+pub const DEFAULT_FULL_DUMP_SIZE_LIMIT: usize = 256 * 1024 * 1024;
+
+const MISC_INFO: u32 = 15;
+const LINUX_CPU_INFO: u32 = 0x4767_0003;
+const LINUX_PROC_STATUS: u32 = 0x4767_0004;
+const LINUX_LSB_RELEASE: u32 = 0x4767_0005;
+
+const THREAD_SIZE: usize = 48;
+const MODULE_SIZE: usize = 108;
+
+/// How much of the process goes into a dump
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpMode {
+    /// Only the crashing thread's stack and registers, plus system info and
+    /// the module list needed to make sense of them
+    Minimal,
+    /// Every thread's stack and registers, as written by minidump-writer
+    #[default]
+    Normal,
+    /// `Normal` plus all readable private anonymous mappings (heap, thread
+    /// stacks, anonymous `mmap`s), up to `HandlerConfig::full_dump_size_limit`.
+    /// Only supported on Linux; elsewhere this is `Normal`.
+    Full,
+}
+
+impl DumpMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Minimal => "minimal",
+            Self::Normal => "normal",
+            Self::Full => "full",
+        }
+    }
+}
+
+/// How a freshly written dump is finished
+pub(crate) struct Completion {
+    /// Custom streams to embed
+    pub streams: Vec<(u32, Vec<u8>)>,
+    pub mode: DumpMode,
+    /// Thread kept by `Minimal` when the dump has no exception stream
+    pub thread: u32,
+    /// Size `Full` dumps stay within
+    pub size_limit: usize,
+    /// Memory around pointers to add
+    pub indirect: IndirectMemory,
+    /// Process whose memory the indirect and private memory is read from
+    /// (`None` for this process)
+    pub process: Option<u32>,
+    /// Public key buffered dumps are encrypted to
+    pub encryption_key: Option<[u8; 32]>,
+    /// Private key the dump and sidecar written to a path are signed with
+    pub signing_key: Option<[u8; 32]>,
+    /// With the environment collector on, its allowlist, which the writer's
+    /// own environment stream is filtered to
+    pub environment_allowlist: Option<Vec<String>>,
+}
+
+impl Completion {
+    /// Finish a dump already on disk or in memory: embed the streams, the
+    /// indirectly referenced memory and, for `Full`, the private memory.
+    /// `Minimal` must be applied before this.
+    pub fn append_to<F: Read + Write + Seek>(&self, file: &mut F) -> Result<()> {
+        if let Some(allowlist) = &self.environment_allowlist {
+            crate::scrub::filter_linux_environ(file, allowlist)?;
+        }
+        streams::append_streams(file, &self.streams)?;
+        indirect_memory::append(file, &self.indirect, self.thread, self.process)?;
+        if self.mode == DumpMode::Full {
+            append_private_memory(file, self.size_limit, self.process)?;
+        }
+        Ok(())
+    }
+
+    /// Finish a dump buffered in memory, including `Minimal` and encryption
+    pub fn complete_buffer(&self, buffer: &mut Cursor<Vec<u8>>) -> Result<()> {
+        self.finish_buffer(buffer)?;
+        if let Some(key) = &self.encryption_key {
+            *buffer = Cursor::new(crate::encryption::encrypt(buffer.get_ref(), key)?);
+        }
+        Ok(())
+    }
+
+    /// Finish a dump buffered in memory, including `Minimal`, leaving it in
+    /// plaintext
+    pub fn finish_buffer(&self, buffer: &mut Cursor<Vec<u8>>) -> Result<()> {
+        if self.mode == DumpMode::Minimal {
+            *buffer = Cursor::new(minimal(buffer.get_ref(), self.thread)?);
+        }
+        self.append_to(buffer)
+    }
+}
+
+/// Rebuild `dump` with only the crashing thread (the exception's, or
+/// `thread`) and the streams needed to read it. Memory lists are dropped.
+pub(crate) fn minimal(dump: &[u8], thread: u32) -> Result<Vec<u8>> {
+    let mut source = Cursor::new(dump);
+    let header = streams::read_header(&mut source)?;
+    let directory = streams::read_directory(&mut source, &header)?;
+
+    let mut rebuilt = Rebuilder {
+        source: dump,
+        out: dump[..HEADER_SIZE as usize].to_vec(),
+    };
+    let exception_thread = directory
+        .iter()
+        .find(|e| e.stream_type == standard_stream::EXCEPTION)
+        .and_then(|e| rebuilt.slice(e.rva, 4).ok())
+        .map(|raw| u32::from_le_bytes(raw.try_into().unwrap()));
+    let thread = exception_thread.unwrap_or(thread);
+
+    let mut kept = Vec::new();
+    for entry in &directory {
+        let mut data = rebuilt.slice(entry.rva, entry.data_size)?.to_vec();
+        match entry.stream_type {
+            standard_stream::THREAD_LIST => data = rebuilt.thread_list(&data, thread)?,
+            standard_stream::MODULE_LIST => {
+                for module in data.get_mut(4..).unwrap_or_default().chunks_exact_mut(MODULE_SIZE) {
+                    rebuilt.relocate_string(module, 20)?;
+                    rebuilt.relocate_location(module, 76)?;
+                    rebuilt.relocate_location(module, 84)?;
+                }
+            }
+            standard_stream::EXCEPTION => rebuilt.relocate_location(&mut data, 160)?,
+            standard_stream::SYSTEM_INFO => rebuilt.relocate_string(&mut data, 24)?,
+            MISC_INFO | LINUX_CPU_INFO | LINUX_PROC_STATUS | LINUX_LSB_RELEASE => {}
+            _ => continue,
+        }
+        let rva = rebuilt.push(&data)?;
+        kept.push(DirectoryEntry {
+            stream_type: entry.stream_type,
+            data_size: to_u32(data.len() as u64)?,
+            rva,
+        });
+    }
+
+    let directory_rva = rebuilt.push(&[])?;
+    streams::write_directory(&mut rebuilt.out, &kept)?;
+    let mut out = rebuilt.out;
+    out[8..12].copy_from_slice(&to_u32(kept.len() as u64)?.to_le_bytes());
+    out[12..16].copy_from_slice(&directory_rva.to_le_bytes());
+    Ok(out)
+}
+
+/// Copies the data a kept stream refers to into the new file
+struct Rebuilder<'a> {
+    source: &'a [u8],
+    out: Vec<u8>,
+}
+
+impl Rebuilder<'_> {
+    fn slice(&self, rva: u32, size: u32) -> Result<&[u8]> {
+        self.source
+            .get(rva as usize..)
+            .and_then(|rest| rest.get(..size as usize))
+            .ok_or_else(|| invalid_data("stream outside the dump"))
+    }
+
+    /// Append 4-byte aligned data and return its RVA
+    fn push(&mut self, data: &[u8]) -> Result<u32> {
+        self.out.resize((self.out.len() + 3) & !3, 0);
+        let rva = to_u32(self.out.len() as u64)?;
+        self.out.extend_from_slice(data);
+        Ok(rva)
+    }
+
+    /// Copy the `MINIDUMP_LOCATION_DESCRIPTOR` target at `offset` of `raw`
+    fn relocate_location(&mut self, raw: &mut [u8], offset: usize) -> Result<()> {
+        let Some(location) = raw.get(offset..offset + 8) else {
+            return Ok(());
+        };
+        let size = u32::from_le_bytes(location[0..4].try_into().unwrap());
+        let rva = u32::from_le_bytes(location[4..8].try_into().unwrap());
+        if size == 0 {
+            return Ok(());
+        }
+        let data = self.slice(rva, size)?.to_vec();
+        let new_rva = self.push(&data)?;
+        raw[offset + 4..offset + 8].copy_from_slice(&new_rva.to_le_bytes());
+        Ok(())
+    }
+
+    /// Copy the `MINIDUMP_STRING` whose RVA is at `offset` of `raw`
+    fn relocate_string(&mut self, raw: &mut [u8], offset: usize) -> Result<()> {
+        let Some(rva) = raw.get(offset..offset + 4) else {
+            return Ok(());
+        };
+        let rva = u32::from_le_bytes(rva.try_into().unwrap());
+        if rva == 0 {
+            return Ok(());
+        }
+        let length = u32::from_le_bytes(self.slice(rva, 4)?.try_into().unwrap());
+        // Length prefix, UTF-16 text and its terminator where present
+        let size = (length.saturating_add(6) as usize).min(self.source.len().saturating_sub(rva as usize));
+        let data = self.slice(rva, to_u32(size as u64)?)?.to_vec();
+        let new_rva = self.push(&data)?;
+        raw[offset..offset + 4].copy_from_slice(&new_rva.to_le_bytes());
+        Ok(())
+    }
+
+    /// Thread list with only `thread`, or the first thread if it is missing
+    fn thread_list(&mut self, data: &[u8], thread: u32) -> Result<Vec<u8>> {
+        let threads: Vec<&[u8]> = data.get(4..).unwrap_or_default().chunks_exact(THREAD_SIZE).collect();
+        let Some(&kept) = threads
+            .iter()
+            .find(|raw| u32::from_le_bytes(raw[0..4].try_into().unwrap()) == thread)
+            .or(threads.first())
+        else {
+            return Ok(0u32.to_le_bytes().to_vec());
+        };
+
+        let mut kept = kept.to_vec();
+        // Stack memory descriptor (start, then location) and thread context
+        self.relocate_location(&mut kept, 32)?;
+        self.relocate_location(&mut kept, 40)?;
+        let mut list = 1u32.to_le_bytes().to_vec();
+        list.extend_from_slice(&kept);
+        Ok(list)
+    }
+}
+
+/// A private mapping that did not fit the size limit or could not be read
+#[derive(Serialize)]
+struct Truncated {
+    start: u64,
+    size: u64,
+    captured: u64,
+}
+
+#[derive(Serialize)]
+struct Truncation {
+    limit: u64,
+    regions: Vec<Truncated>,
+}
+
+/// Append every readable private anonymous mapping of `process` (`None` for
+/// this process) to the memory list, keeping the whole dump within `limit`
+/// bytes. Mappings that were cut short are listed in the `MEMORY_TRUNCATION`
+/// stream.
+///
+/// On Linux this runs in the writer child, which reads the crashed parent
+/// through `/proc/<pid>/mem`, with the same access that let it ptrace the
+/// parent; its own allocations are not part of the dump.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn append_private_memory<F: Read + Write + Seek>(file: &mut F, limit: usize, process: Option<u32>) -> Result<()> {
+    use std::io::SeekFrom;
+    use std::os::unix::fs::FileExt;
+
+    let proc_dir = proc_dir(process);
+    let maps = std::fs::read_to_string(format!("{}/maps", proc_dir))?;
+    let mappings: Vec<(u64, u64)> = maps.lines().filter_map(private_anonymous).collect();
+    if mappings.is_empty() {
+        return Ok(());
+    }
+    let memory = std::fs::File::open(format!("{}/mem", proc_dir))?;
+
+    let header = streams::read_header(file)?;
+    let directory = streams::read_directory(file, &header)?;
+    let listed = directory
+        .iter()
+        .find(|e| e.stream_type == standard_stream::MEMORY_LIST)
+        .map_or(0, |e| (e.data_size as usize).saturating_sub(4) / streams::MEMORY_DESCRIPTOR_SIZE);
+
+    // Everything written besides the memory itself, at its largest: the
+    // merged memory list, two directories, the truncation stream and padding
+    let limit = (limit as u64).min(u32::MAX as u64);
+    let worst_truncation = Truncation {
+        limit,
+        regions: mappings
+            .iter()
+            .map(|&(start, end)| Truncated {
+                start,
+                size: end - start,
+                captured: end - start,
+            })
+            .collect(),
+    };
+    let overhead = 4
+        + (listed + mappings.len()) * streams::MEMORY_DESCRIPTOR_SIZE
+        + 2 * (directory.len() + 2) * streams::DIRECTORY_ENTRY_SIZE as usize
+        + serde_json::to_vec(&worst_truncation).map_err(std::io::Error::from)?.len()
+        + (mappings.len() + 4) * 3;
+    let end = file.seek(SeekFrom::End(0))?;
+    let mut available = limit.saturating_sub(end + overhead as u64);
+
+    let mut descriptors = Vec::new();
+    let mut truncated = Vec::new();
+    let mut chunk = vec![0u8; 1024 * 1024];
+    for (start, end) in mappings {
+        let size = end - start;
+        let wanted = size.min(available);
+        let rva = streams::align_end(file)?;
+        let mut copied = 0;
+        while copied < wanted {
+            let len = (wanted - copied).min(chunk.len() as u64) as usize;
+            match memory.read_at(&mut chunk[..len], start + copied) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    file.write_all(&chunk[..n])?;
+                    copied += n as u64;
+                }
+            }
+        }
+        if copied > 0 {
+            descriptors.push((start, to_u32(copied)?, rva));
+        }
+        available -= copied;
+        if copied < size {
+            truncated.push(Truncated {
+                start,
+                size,
+                captured: copied,
+            });
+        }
+    }
+
+    streams::append_memory(file, &descriptors)?;
+    if !truncated.is_empty() {
+        let json = serde_json::to_vec(&Truncation {
+            limit,
+            regions: truncated,
+        })
+        .map_err(std::io::Error::from)?;
+        streams::append_streams(file, &[(streams::stream_type::MEMORY_TRUNCATION, json)])?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn append_private_memory<F: Read + Write + Seek>(_file: &mut F, _limit: usize, _process: Option<u32>) -> Result<()> {
+    Ok(())
+}
+
+/// `/proc` directory of `process`, `None` for this process
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn proc_dir(process: Option<u32>) -> String {
+    match process {
+        Some(pid) => format!("/proc/{}", pid),
+        None => "/proc/self".to_string(),
+    }
+}
+
+/// (start, end) of a readable private anonymous mapping in a
+/// `/proc/<pid>/maps` line
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn private_anonymous(line: &str) -> Option<(u64, u64)> {
+    let mut fields = line.split_whitespace();
+    let (range, perms) = (fields.next()?, fields.next()?);
+    let (_offset, _device, inode) = (fields.next()?, fields.next()?, fields.next()?);
+    let path = fields.next().unwrap_or("");
+
+    let anonymous = inode == "0"
+        && (path.is_empty()
+            || path == "[heap]"
+            || path.starts_with("[stack")
+            || path.starts_with("[anon:"));
+    if !anonymous || !perms.starts_with('r') || perms.as_bytes().get(3) != Some(&b'p') {
+        return None;
+    }
+    let (start, end) = range.split_once('-')?;
+    Some((u64::from_str_radix(start, 16).ok()?, u64::from_str_radix(end, 16).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::Minidump;
+    use crate::streams::testing::TestDump;
+
+    fn thread(id: u32, stack_start: u64, stack: (u32, u32), context: (u32, u32)) -> Vec<u8> {
+        let mut raw = vec![0u8; THREAD_SIZE];
+        raw[0..4].copy_from_slice(&id.to_le_bytes());
+        raw[24..32].copy_from_slice(&stack_start.to_le_bytes());
+        raw[32..36].copy_from_slice(&stack.0.to_le_bytes());
+        raw[36..40].copy_from_slice(&stack.1.to_le_bytes());
+        raw[40..44].copy_from_slice(&context.0.to_le_bytes());
+        raw[44..48].copy_from_slice(&context.1.to_le_bytes());
+        raw
+    }
+
+    #[test]
+    fn test_minimal_keeps_crashing_thread() {
+        // Header, then two stacks and two contexts at known offsets
+        let mut list = 2u32.to_le_bytes().to_vec();
+        list.extend(thread(1, 0x1000, (16, 32), (4, 64)));
+        list.extend(thread(2, 0x2000, (16, 48), (4, 68)));
+        let original = TestDump::new()
+            .data(b"stack-one-------") // 32
+            .data(b"stack-two-------") // 48
+            .data(b"ctx1") // 64
+            .data(b"ctx2") // 68
+            .stream(standard_stream::THREAD_LIST, list)
+            .stream(standard_stream::MEMORY_LIST, streams::memory_list_stream(&[(0x9000u64, b"heap")]).unwrap())
+            .bytes();
+        let compacted = minimal(&original, 2).unwrap();
+        assert!(compacted.len() < original.len());
+
+        let dump = Minidump::from_bytes(compacted).unwrap();
+        let threads = dump.threads();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].thread_id, 2);
+        assert_eq!(dump.location(threads[0].context), Some(&b"ctx2"[..]));
+        assert!(dump.memory_regions().is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! Last-resort reporting for a crash inside the crash handler.
+//!
+//! When the handler faults (in `pre_dump_callback`, while reserving the
+//! filename or while writing), nothing it was doing can be trusted anymore.
+//! The emergency path only formats a one-line record into a stack buffer,
+//! writes it to a file descriptor opened at init time and kills the process.
+//! The record is plaintext, also with `HandlerConfig::encryption_key`.
+
+use libc::c_int;
+use std::path::Path;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// File in the dump directory that emergency records are appended to
+pub const EMERGENCY_LOG: &str = "emergency.log";
+
+/// Descriptor opened by [`open`], or -1 to fall back to stderr
+static EMERGENCY_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Open `<dump_directory>/emergency.log` ahead of any crash
+pub(crate) fn open(dump_directory: &Path) -> crate::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::IntoRawFd;
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(crate::dump_directory::FILE_MODE)
+        .open(dump_directory.join(EMERGENCY_LOG))?;
+    let previous = EMERGENCY_FD.swap(file.into_raw_fd(), Ordering::AcqRel);
+    if previous >= 0 {
+        unsafe { libc::close(previous) };
+    }
+    Ok(())
+}
+
+/// Record a crash that happened while `first_signal` was being handled, then
+/// terminate the process with `first_signal`. Never returns.
+pub(crate) fn reentered(first_signal: c_int, second: &crate::SignalInfo) -> ! {
+    let mut record = Record::new();
+    format_record(first_signal, second, &mut record);
+
+    let fd = match EMERGENCY_FD.load(Ordering::Acquire) {
+        -1 => libc::STDERR_FILENO,
+        fd => fd,
+    };
+    let bytes = record.as_bytes();
+    unsafe { libc::write(fd, bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+
+    terminate(first_signal)
+}
+
+/// Fixed format, one line:
+/// `reentrant crash pid=<pid> tid=<tid> first=<SIG>(<n>) second=<SIG>(<n>) code=<CODE> addr=0x<hex>`
+fn format_record(first_signal: c_int, second: &crate::SignalInfo, out: &mut Record) {
+    out.push(b"reentrant crash pid=");
+    out.push_u64(std::process::id() as u64, 10);
+    out.push(b" tid=");
+    out.push_u64(second.tid, 10);
+    out.push(b" first=");
+    out.push_signal(first_signal);
+    out.push(b" second=");
+    out.push_signal(second.signal);
+    out.push(b" code=");
+    out.push(second.code_name().as_bytes());
+    out.push(b" addr=0x");
+    out.push_u64(second.address as u64, 16);
+    out.push(b"\n");
+}
+
+/// Kill the process with `sig`'s default action, even if it is blocked
+/// because we are inside its handler
+fn terminate(sig: c_int) -> ! {
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, sig);
+        libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(sig);
+        // Only reached if the default action doesn't terminate
+        libc::_exit(128 + sig)
+    }
+}
+
+/// Fixed-size line buffer; output that doesn't fit is cut off
+pub(crate) struct Record {
+    buf: [u8; 256],
+    len: usize,
+}
+
+impl Record {
+    pub(crate) fn new() -> Self {
+        Self { buf: [0; 256], len: 0 }
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+    }
+
+    pub(crate) fn push_u64(&mut self, mut value: u64, radix: u64) {
+        let mut digits = [0u8; 20];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = b"0123456789abcdef"[(value % radix) as usize];
+            value /= radix;
+            if value == 0 {
+                break;
+            }
+        }
+        self.push(&digits[start..]);
+    }
+
+    pub(crate) fn push_signal(&mut self, sig: c_int) {
+        self.push(crate::signal_name(sig).as_bytes());
+        self.push(b"(");
+        self.push_u64(sig as u64, 10);
+        self.push(b")");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CpuContext, SignalInfo};
+
+    #[test]
+    fn test_record_format() {
+        let second = SignalInfo {
+            signal: libc::SIGSEGV,
+            code: 1, // SEGV_MAPERR
+            address: 0x4,
+            tid: 77,
+            context: CpuContext::default(),
+        };
+        let mut record = Record::new();
+        format_record(libc::SIGABRT, &second, &mut record);
+
+        let line = std::str::from_utf8(record.as_bytes()).unwrap();
+        let expected = format!(
+            "reentrant crash pid={} tid=77 first=SIGABRT(6) second=SIGSEGV(11) code=SEGV_MAPERR addr=0x4\n",
+            std::process::id()
+        );
+        assert_eq!(line, expected);
+    }
+}
@@ -0,0 +1,159 @@
+//! Encryption of dumps at rest.
+//!
+//! Hybrid scheme: an ephemeral X25519 key agreement with the recipient's
+//! public key, HKDF-SHA256 to derive the key, ChaCha20-Poly1305 to seal the
+//! file. Only the holder of the private key (the backend, developers) can
+//! read the result.
+//!
+//! Layout: `MHE1`, ephemeral public key (32 bytes), nonce (12 bytes), then the
+//! ciphertext with its 16-byte tag. The first 36 bytes are authenticated too.
+
+use crate::streams::invalid_data;
+use crate::Result;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+const MAGIC: &[u8; 4] = b"MHE1";
+const KEY_INFO: &[u8] = b"minidump-handler dump encryption v1";
+const HEADER_SIZE: usize = 4 + 32 + 12;
+
+/// Path of the encrypted copy of `path`: `<path>.enc`
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut encrypted = path.as_os_str().to_owned();
+    encrypted.push(".enc");
+    PathBuf::from(encrypted)
+}
+
+/// New X25519 key pair as (private, public)
+pub fn generate_encryption_keys() -> ([u8; 32], [u8; 32]) {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    (secret.to_bytes(), public.to_bytes())
+}
+
+/// Seal `plaintext` for the holder of the private key matching `recipient`
+pub fn encrypt(plaintext: &[u8], recipient: &[u8; 32]) -> Result<Vec<u8>> {
+    let recipient = PublicKey::from(*recipient);
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(&recipient);
+
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut sealed = Vec::with_capacity(HEADER_SIZE + plaintext.len() + 16);
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(ephemeral_public.as_bytes());
+    sealed.extend_from_slice(&nonce);
+
+    let cipher = cipher(shared.as_bytes(), ephemeral_public.as_bytes(), recipient.as_bytes());
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &sealed[..36],
+            },
+        )
+        .map_err(|_| invalid_data("encryption failed"))?;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Open data sealed by [`encrypt`] with the recipient's private key
+pub fn decrypt(sealed: &[u8], private_key: &[u8; 32]) -> Result<Vec<u8>> {
+    if sealed.len() < HEADER_SIZE || &sealed[..4] != MAGIC {
+        return Err(invalid_data("not an encrypted dump"));
+    }
+    let secret = StaticSecret::from(*private_key);
+    let ephemeral_public = PublicKey::from(<[u8; 32]>::try_from(&sealed[4..36]).unwrap());
+    let shared = secret.diffie_hellman(&ephemeral_public);
+
+    let cipher = cipher(
+        shared.as_bytes(),
+        ephemeral_public.as_bytes(),
+        PublicKey::from(&secret).as_bytes(),
+    );
+    cipher
+        .decrypt(
+            Nonce::from_slice(&sealed[36..HEADER_SIZE]),
+            Payload {
+                msg: &sealed[HEADER_SIZE..],
+                aad: &sealed[..36],
+            },
+        )
+        .map_err(|_| invalid_data("wrong key or corrupted encrypted dump"))
+}
+
+/// Write `plaintext` encrypted to the encrypted path of `path` and return
+/// that path; nothing is written at `path` itself
+pub(crate) fn write_sealed(path: &Path, plaintext: &[u8], recipient: &[u8; 32]) -> Result<PathBuf> {
+    let encrypted = encrypted_path(path);
+    crate::dump_directory::write_file(&encrypted, encrypt(plaintext, recipient)?)?;
+    Ok(encrypted)
+}
+
+fn cipher(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> ChaCha20Poly1305 {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral);
+    salt[32..].copy_from_slice(recipient);
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(KEY_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+/// Parse a 32-byte key written as 64 hex digits
+pub fn parse_key(hex: &str) -> Result<[u8; 32]> {
+    parse_hex(hex).ok_or_else(|| invalid_data("key must be 64 hex digits"))
+}
+
+/// Format a key as 64 hex digits
+pub fn format_key(key: &[u8; 32]) -> String {
+    format_hex(key)
+}
+
+/// Exactly `N` bytes written as hex digits
+pub(crate) fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.trim();
+    if hex.len() != 2 * N || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+pub(crate) fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let (private_key, public_key) = generate_encryption_keys();
+        let sealed = encrypt(b"MDMP dump bytes", &public_key).unwrap();
+        assert!(!sealed.windows(4).any(|w| w == b"MDMP"));
+        assert_eq!(decrypt(&sealed, &private_key).unwrap(), b"MDMP dump bytes");
+
+        let (other_key, _) = generate_encryption_keys();
+        assert!(decrypt(&sealed, &other_key).is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&tampered, &private_key).is_err());
+
+        assert_eq!(parse_key(&format_key(&public_key)).unwrap(), public_key);
+    }
+}
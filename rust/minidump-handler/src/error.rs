@@ -0,0 +1,161 @@
+use libc::c_int;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Result type used throughout the crash handler
+pub type Result<T, E = HandlerError> = std::result::Result<T, E>;
+
+/// Errors reported by the crash handler
+#[derive(Debug)]
+pub enum HandlerError {
+    /// The dump directory could not be created
+    CreateDirectory { path: PathBuf, source: io::Error },
+    /// The dump directory exists but may not be used, e.g. someone else owns it
+    UnsafeDirectory { path: PathBuf, reason: String },
+    /// The dump filesystem has less free space than
+    /// `HandlerConfig::min_free_disk_space`
+    LowDiskSpace { available: u64, required: u64 },
+    /// `init_crash_handler` was called more than once
+    AlreadyInitialized,
+    /// Installing the handler for a signal failed
+    Sigaction { signal: c_int, source: io::Error },
+    /// `HandlerConfig::signals` asks to continue after a fault signal, which
+    /// would re-execute the faulting instruction forever
+    ContinueAfterFault { signal: c_int },
+    /// Forking the writer process failed
+    Fork(io::Error),
+    /// The writer process was not allowed to ptrace the target
+    PtraceDenied(String),
+    /// minidump-writer failed to produce the dump
+    Writer(String),
+    /// Any other I/O failure (creating the dump file, waiting for the writer)
+    Io(io::Error),
+}
+
+impl HandlerError {
+    /// Stable identifier for the error kind, suitable for matching across FFI
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::CreateDirectory { .. } => "create_directory",
+            Self::UnsafeDirectory { .. } => "unsafe_directory",
+            Self::LowDiskSpace { .. } => "low_disk_space",
+            Self::AlreadyInitialized => "already_initialized",
+            Self::Sigaction { .. } => "sigaction",
+            Self::ContinueAfterFault { .. } => "continue_after_fault",
+            Self::Fork(_) => "fork",
+            Self::PtraceDenied(_) => "ptrace_denied",
+            Self::Writer(_) => "writer",
+            Self::Io(_) => "io",
+        }
+    }
+
+    /// Serialize the error so the forked writer can report it to its parent.
+    ///
+    /// Layout: one tag byte, the raw OS error as little-endian `i32`, then the
+    /// message bytes. Only the variants a writer process can produce are used.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn encode(&self, buf: &mut [u8]) -> usize {
+        let (tag, errno, message): (u8, i32, String) = match self {
+            Self::PtraceDenied(msg) => (1, 0, msg.clone()),
+            Self::Writer(msg) => (2, 0, msg.clone()),
+            Self::Io(e) => (3, e.raw_os_error().unwrap_or(0), e.to_string()),
+            other => (2, 0, other.to_string()),
+        };
+
+        if buf.len() < 5 {
+            return 0;
+        }
+        buf[0] = tag;
+        buf[1..5].copy_from_slice(&errno.to_le_bytes());
+        let len = message.len().min(buf.len() - 5);
+        buf[5..5 + len].copy_from_slice(&message.as_bytes()[..len]);
+        5 + len
+    }
+
+    /// Decode an error written by [`HandlerError::encode`]
+    #[cfg(target_os = "linux")]
+    pub(crate) fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 5 {
+            return None;
+        }
+        let errno = i32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]);
+        let message = String::from_utf8_lossy(&buf[5..]).into_owned();
+        match buf[0] {
+            1 => Some(Self::PtraceDenied(message)),
+            2 => Some(Self::Writer(message)),
+            3 if errno != 0 => Some(Self::Io(io::Error::from_raw_os_error(errno))),
+            3 => Some(Self::Io(io::Error::new(io::ErrorKind::Other, message))),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateDirectory { path, source } => {
+                write!(f, "Failed to create dump directory {:?}: {}", path, source)
+            }
+            Self::UnsafeDirectory { path, reason } => {
+                write!(f, "Refusing to use dump directory {:?}: {}", path, reason)
+            }
+            Self::LowDiskSpace { available, required } => {
+                write!(f, "Not enough disk space for a dump: {} bytes free, {} required", available, required)
+            }
+            Self::AlreadyInitialized => write!(f, "Handler already initialized"),
+            Self::Sigaction { signal, source } => {
+                write!(f, "Failed to install handler for signal {}: {}", signal, source)
+            }
+            Self::ContinueAfterFault { signal } => write!(
+                f,
+                "Cannot continue after {}: returning would re-execute the faulting instruction",
+                crate::signal_name(*signal)
+            ),
+            Self::Fork(e) => write!(f, "Failed to fork writer process: {}", e),
+            Self::PtraceDenied(msg) => write!(f, "Not permitted to ptrace the process: {}", msg),
+            Self::Writer(msg) => write!(f, "Failed to write minidump: {}", msg),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HandlerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::CreateDirectory { source, .. } | Self::Sigaction { source, .. } => Some(source),
+            Self::Fork(e) | Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HandlerError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_wire_roundtrip() {
+        let mut buf = [0u8; 256];
+
+        let len = HandlerError::PtraceDenied("EPERM".into()).encode(&mut buf);
+        let decoded = HandlerError::decode(&buf[..len]).unwrap();
+        assert_eq!(decoded.code(), "ptrace_denied");
+        assert_eq!(decoded.to_string(), "Not permitted to ptrace the process: EPERM");
+
+        let len = HandlerError::Io(io::Error::from_raw_os_error(libc::ENOSPC)).encode(&mut buf);
+        match HandlerError::decode(&buf[..len]).unwrap() {
+            HandlerError::Io(e) => assert_eq!(e.raw_os_error(), Some(libc::ENOSPC)),
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        assert!(HandlerError::decode(&[]).is_none());
+    }
+}
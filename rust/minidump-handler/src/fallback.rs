@@ -0,0 +1,509 @@
+//! Text report written when the minidump itself could not be written.
+//!
+//! The crashing thread's stack is walked through the frame-pointer chain
+//! starting at the registers from the signal's `ucontext_t`. Every frame
+//! pointer is checked against the readable parts of the address space before
+//! it is dereferenced, so a corrupt chain ends the walk instead of faulting.
+//!
+//! This runs in the signal handler after the writer failed, so nothing here
+//! allocates: the report is formatted a line at a time into a stack buffer,
+//! `/proc/self/maps` is scanned with raw `read(2)` calls for every lookup and
+//! the file is written with raw `open(2)` and `write(2)`.
+
+use crate::emergency::Record;
+use crate::filename::PathBuffer;
+use crate::{HandlerError, Result, SignalInfo};
+use libc::c_int;
+use std::path::{Path, PathBuf};
+
+/// Frames walked before giving up on a (possibly cyclic) chain
+const MAX_FRAMES: usize = 64;
+
+/// Suffix appended to the dump path for the report
+const REPORT_SUFFIX: &str = ".fallback.txt";
+
+/// Path of the fallback report standing in for `dump_path`
+pub fn fallback_report_path(dump_path: &Path) -> PathBuf {
+    let mut path = dump_path.as_os_str().to_owned();
+    path.push(REPORT_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Write the fallback report for a crash whose dump failed with `error`.
+///
+/// An empty dump file left behind by the failed writer is removed. The report
+/// is plaintext even with `HandlerConfig::encryption_key`: it holds registers
+/// and code addresses, no memory.
+pub(crate) fn write_report(dump_path: &PathBuffer, signal_info: &SignalInfo, error: &HandlerError) -> Result<()> {
+    let space = AddressSpace::load();
+    let mut frames = [0usize; MAX_FRAMES];
+    let count = walk_frames(signal_info.context.pc, signal_info.context.fp, &space, &mut frames);
+
+    let report_path = dump_path
+        .with_suffix(REPORT_SUFFIX.as_bytes())
+        .ok_or_else(|| HandlerError::Io(std::io::Error::from_raw_os_error(libc::ENAMETOOLONG)))?;
+    let fd = unsafe {
+        libc::open(
+            report_path.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+            crate::dump_directory::FILE_MODE as libc::c_uint,
+        )
+    };
+    if fd < 0 {
+        return Err(HandlerError::Io(std::io::Error::last_os_error()));
+    }
+
+    let mut written = write_header(fd, signal_info, error);
+    for (i, &address) in frames[..count].iter().enumerate() {
+        let mut line = Record::new();
+        line.push(b"  #");
+        line.push_u64(i as u64, 10);
+        line.push(if i < 10 { b"  " } else { b" " });
+        push_address(&mut line, address);
+        match space.module(address) {
+            Some((path, offset)) => {
+                let path = path.as_bytes();
+                let name = path.rsplit(|&b| b == b'/').next().unwrap_or(path);
+                line.push(b" ");
+                line.push(name);
+                line.push(b" + 0x");
+                line.push_u64(offset as u64, 16);
+            }
+            None => line.push(b" ???"),
+        }
+        line.push(b"\n");
+        written = written && write_all(fd, line.as_bytes());
+    }
+    let err = std::io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if !written {
+        return Err(HandlerError::Io(err));
+    }
+
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::stat(dump_path.as_ptr(), &mut stat) } == 0 && stat.st_size == 0 {
+        unsafe { libc::unlink(dump_path.as_ptr()) };
+    }
+    Ok(())
+}
+
+/// Everything before the backtrace; `false` if a write failed
+fn write_header(fd: c_int, signal_info: &SignalInfo, error: &HandlerError) -> bool {
+    let context = &signal_info.context;
+    let mut lines = [Record::new(), Record::new(), Record::new(), Record::new(), Record::new()];
+
+    lines[0].push(b"minidump-handler fallback report\n");
+
+    let line = &mut lines[1];
+    line.push(b"dump failed: ");
+    line.push(error.code().as_bytes());
+    match error {
+        HandlerError::PtraceDenied(message) | HandlerError::Writer(message) => {
+            line.push(b": ");
+            line.push(message.as_bytes());
+        }
+        HandlerError::Fork(e) | HandlerError::Io(e) => {
+            if let Some(errno) = e.raw_os_error() {
+                line.push(b": errno ");
+                line.push_u64(errno as u64, 10);
+            }
+        }
+        _ => {}
+    }
+    line.push(b"\n");
+
+    let line = &mut lines[2];
+    line.push(b"signal: ");
+    line.push_signal(signal_info.signal);
+    line.push(b" code=");
+    line.push(signal_info.code_name().as_bytes());
+    line.push(b" addr=");
+    push_address(line, signal_info.address);
+    line.push(b"\n");
+
+    let line = &mut lines[3];
+    line.push(b"pid: ");
+    line.push_u64(std::process::id() as u64, 10);
+    line.push(b"  tid: ");
+    line.push_u64(signal_info.tid, 10);
+    line.push(b"\n");
+
+    let line = &mut lines[4];
+    line.push(b"pc: ");
+    push_address(line, context.pc);
+    line.push(b"  sp: ");
+    push_address(line, context.sp);
+    line.push(b"  fp: ");
+    push_address(line, context.fp);
+    line.push(b"\nbacktrace (frame pointers):\n");
+
+    lines.iter().all(|line| write_all(fd, line.as_bytes()))
+}
+
+/// `0x` and 16 zero-padded hex digits
+fn push_address(line: &mut Record, address: usize) {
+    let mut digits = [b'0'; 16];
+    let mut value = address as u64;
+    for digit in digits.iter_mut().rev() {
+        *digit = b"0123456789abcdef"[(value % 16) as usize];
+        value /= 16;
+    }
+    line.push(b"0x");
+    line.push(&digits);
+}
+
+fn write_all(fd: c_int, mut bytes: &[u8]) -> bool {
+    while !bytes.is_empty() {
+        let n = unsafe { libc::write(fd, bytes.as_ptr() as *const libc::c_void, bytes.len()) };
+        if n < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+            continue;
+        }
+        if n <= 0 {
+            return false;
+        }
+        bytes = &bytes[n as usize..];
+    }
+    true
+}
+
+/// Walk the frame-pointer chain into `frames`, returning how many were found:
+/// `[fp]` holds the caller's frame pointer and `[fp + word]` the return
+/// address, on both x86_64 and AArch64.
+fn walk_frames(pc: usize, mut fp: usize, space: &AddressSpace, frames: &mut [usize; MAX_FRAMES]) -> usize {
+    const WORD: usize = std::mem::size_of::<usize>();
+
+    frames[0] = pc;
+    let mut count = 1;
+    while count < MAX_FRAMES {
+        if fp == 0 || fp % WORD != 0 || !space.is_readable(fp, 2 * WORD) {
+            break;
+        }
+        let (next_fp, return_address) = unsafe {
+            let frame = fp as *const usize;
+            (frame.read(), frame.add(1).read())
+        };
+        if return_address == 0 {
+            break;
+        }
+        frames[count] = return_address;
+        count += 1;
+        // Stacks grow down, so callers' frames are at higher addresses
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+    count
+}
+
+/// One line of `/proc/self/maps`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+struct Mapping<'a> {
+    start: usize,
+    end: usize,
+    readable: bool,
+    path: &'a [u8],
+}
+
+/// What is mapped where in this process. Nothing is kept: every lookup scans
+/// `/proc/self/maps` again.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+struct AddressSpace;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl AddressSpace {
+    fn load() -> Self {
+        Self
+    }
+
+    fn is_readable(&self, address: usize, len: usize) -> bool {
+        let Some(last) = address.checked_add(len) else {
+            return false;
+        };
+        let mut readable = false;
+        scan_mappings(|m| {
+            readable = m.readable && m.start <= address && last <= m.end;
+            !readable
+        });
+        readable
+    }
+
+    /// Path of the module containing `address` and the offset from the
+    /// module's lowest mapping
+    fn module(&self, address: usize) -> Option<(PathBuffer, usize)> {
+        let mut path = PathBuffer::new();
+        let mut found = false;
+        scan_mappings(|m| {
+            if m.start <= address && address < m.end && m.path.starts_with(b"/") {
+                found = path.push(m.path);
+                return false;
+            }
+            true
+        });
+        if !found {
+            return None;
+        }
+
+        let mut base = address;
+        scan_mappings(|m| {
+            if m.path == path.as_bytes() {
+                base = base.min(m.start);
+            }
+            true
+        });
+        Some((path, address - base))
+    }
+}
+
+/// Call `visit` with every mapping until it returns `false`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn scan_mappings(mut visit: impl FnMut(&Mapping<'_>) -> bool) {
+    let Some(mut maps) = MapsReader::open() else {
+        return;
+    };
+    let mut line = [0u8; libc::PATH_MAX as usize + 128];
+    while let Some(text) = maps.next_line(&mut line) {
+        if let Some(mapping) = parse_mapping(text) {
+            if !visit(&mapping) {
+                break;
+            }
+        }
+    }
+}
+
+/// `start-end perms offset device inode path`, the path being optional
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_mapping(line: &[u8]) -> Option<Mapping<'_>> {
+    let (range, rest) = next_field(line);
+    let dash = range.iter().position(|&b| b == b'-')?;
+    let (perms, rest) = next_field(rest);
+    let (_offset, rest) = next_field(rest);
+    let (_device, rest) = next_field(rest);
+    let (_inode, rest) = next_field(rest);
+    let path = &rest[rest.iter().position(|&b| b != b' ').unwrap_or(rest.len())..];
+    Some(Mapping {
+        start: parse_hex(&range[..dash])?,
+        end: parse_hex(&range[dash + 1..])?,
+        readable: perms.starts_with(b"r"),
+        path,
+    })
+}
+
+/// Split off the first space-separated field
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn next_field(line: &[u8]) -> (&[u8], &[u8]) {
+    let line = &line[line.iter().position(|&b| b != b' ').unwrap_or(line.len())..];
+    let end = line.iter().position(|&b| b == b' ').unwrap_or(line.len());
+    line.split_at(end)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_hex(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0usize, |value, &b| {
+        let digit = (b as char).to_digit(16)?;
+        value.checked_mul(16)?.checked_add(digit as usize)
+    })
+}
+
+/// `/proc/self/maps` read a line at a time through a stack buffer
+#[cfg(any(target_os = "linux", target_os = "android"))]
+struct MapsReader {
+    fd: c_int,
+    chunk: [u8; 4096],
+    start: usize,
+    end: usize,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl MapsReader {
+    fn open() -> Option<Self> {
+        let fd = unsafe {
+            libc::open(
+                b"/proc/self/maps\0".as_ptr() as *const libc::c_char,
+                libc::O_RDONLY | libc::O_CLOEXEC,
+            )
+        };
+        (fd >= 0).then_some(Self {
+            fd,
+            chunk: [0; 4096],
+            start: 0,
+            end: 0,
+        })
+    }
+
+    /// The next line without its newline, cut off at `line.len()` bytes
+    fn next_line<'a>(&mut self, line: &'a mut [u8]) -> Option<&'a [u8]> {
+        let mut len = 0;
+        loop {
+            if self.start == self.end {
+                let n = unsafe { libc::read(self.fd, self.chunk.as_mut_ptr() as *mut libc::c_void, self.chunk.len()) };
+                if n < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                if n <= 0 {
+                    return (len > 0).then_some(&line[..len]);
+                }
+                self.start = 0;
+                self.end = n as usize;
+            }
+
+            let available = &self.chunk[self.start..self.end];
+            let newline = available.iter().position(|&b| b == b'\n');
+            let take = newline.unwrap_or(available.len());
+            let n = take.min(line.len() - len);
+            line[len..len + n].copy_from_slice(&available[..n]);
+            len += n;
+            match newline {
+                Some(at) => {
+                    self.start += at + 1;
+                    return Some(&line[..len]);
+                }
+                None => self.start = self.end,
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Drop for MapsReader {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// What is mapped where in this process. Without `/proc`, only the crashing
+/// thread's stack is trusted and modules are resolved with `dladdr`.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+struct AddressSpace {
+    stack_low: usize,
+    stack_high: usize,
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+impl AddressSpace {
+    fn load() -> Self {
+        unsafe {
+            let thread = libc::pthread_self();
+            let stack_high = libc::pthread_get_stackaddr_np(thread) as usize;
+            let size = libc::pthread_get_stacksize_np(thread);
+            Self {
+                stack_low: stack_high.saturating_sub(size),
+                stack_high,
+            }
+        }
+    }
+
+    fn is_readable(&self, address: usize, len: usize) -> bool {
+        address >= self.stack_low && address.saturating_add(len) <= self.stack_high
+    }
+
+    fn module(&self, address: usize) -> Option<(PathBuffer, usize)> {
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        if unsafe { libc::dladdr(address as *const libc::c_void, &mut info) } == 0 || info.dli_fname.is_null() {
+            return None;
+        }
+        let mut path = PathBuffer::new();
+        if !path.push(unsafe { std::ffi::CStr::from_ptr(info.dli_fname) }.to_bytes()) {
+            return None;
+        }
+        Some((path, address - info.dli_fbase as usize))
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests {
+    use super::*;
+    use crate::CpuContext;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts the allocations of threads that set `COUNTING`
+    struct CountingAllocator;
+
+    thread_local! {
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if COUNTING.try_with(Cell::get).unwrap_or(false) {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Two fake frames: [next fp, return address]
+    fn fake_stack(stack: &mut [usize; 4]) -> usize {
+        let second = &stack[2] as *const usize as usize;
+        stack[0] = second;
+        stack[1] = 0x1111;
+        stack[3] = 0x2222;
+        stack.as_ptr() as usize
+    }
+
+    #[test]
+    fn test_walk_frame_chain() {
+        let mut stack = [0usize; 4];
+        let first = fake_stack(&mut stack);
+
+        let mut frames = [0usize; MAX_FRAMES];
+        let count = walk_frames(0x1000, first, &AddressSpace::load(), &mut frames);
+        assert_eq!(frames[..count], [0x1000, 0x1111, 0x2222]);
+
+        // A chain into unmapped memory stops instead of faulting
+        let count = walk_frames(0x1000, 0x10, &AddressSpace::load(), &mut frames);
+        assert_eq!(frames[..count], [0x1000]);
+    }
+
+    #[test]
+    fn test_report_without_heap() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let fields = crate::filename::FilenameFields::now("app", "SIGSEGV", uuid::Uuid::nil());
+        let mut dump_path = PathBuffer::new();
+        crate::filename::reserve_dump_path(temp_dir.path(), "crash.dmp", &fields, &mut dump_path).unwrap();
+
+        let mut stack = [0usize; 4];
+        let signal_info = SignalInfo {
+            signal: libc::SIGSEGV,
+            code: 1, // SEGV_MAPERR
+            address: 0x8,
+            tid: 77,
+            context: CpuContext {
+                pc: test_report_without_heap as fn() as usize,
+                fp: fake_stack(&mut stack),
+                ..CpuContext::default()
+            },
+        };
+        let error = HandlerError::Writer("ptrace failed".to_string());
+
+        COUNTING.with(|counting| counting.set(true));
+        let result = write_report(&dump_path, &signal_info, &error);
+        COUNTING.with(|counting| counting.set(false));
+        result.unwrap();
+        assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), 0);
+
+        let report = std::fs::read_to_string(fallback_report_path(dump_path.as_path())).unwrap();
+        assert!(report.contains("dump failed: writer: ptrace failed\n"));
+        assert!(report.contains("signal: SIGSEGV(11) code=SEGV_MAPERR addr=0x0000000000000008\n"));
+        assert!(report.contains("  #1  0x0000000000001111 ???\n"));
+        // The test binary itself resolves to a module
+        let first = report.lines().find(|line| line.starts_with("  #0 ")).unwrap();
+        assert!(first.contains(" + 0x"), "{}", first);
+        // The empty dump left by the failed writer is removed
+        assert!(!dump_path.as_path().exists());
+    }
+}
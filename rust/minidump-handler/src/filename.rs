@@ -0,0 +1,271 @@
+//! Rendering of dump filenames from `HandlerConfig::filename_template`.
+//!
+//! Everything here runs inside the signal handler, so paths are built in a
+//! fixed-size stack buffer and files are reserved with raw `open(2)` calls.
+
+use crate::{HandlerError, Result};
+use libc::c_int;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Template used when `filename_template` is unset and timestamps are enabled
+pub const DEFAULT_TEMPLATE: &str = "{prefix}_{signal}_{timestamp}.dmp";
+/// Template used when `filename_template` is unset and timestamps are disabled
+const DEFAULT_TEMPLATE_NO_TIMESTAMP: &str = "{prefix}_{signal}.dmp";
+
+/// How many `-N` suffixes are tried before giving up on a colliding name
+const MAX_COLLISION_SUFFIX: u64 = 9999;
+
+/// Number of dumps rendered by this process, for `{seq}`
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Values substituted into a filename template
+pub(crate) struct FilenameFields<'a> {
+    pub prefix: &'a str,
+    pub signal: &'a str,
+    pub pid: u32,
+    pub tid: u64,
+    pub timestamp_ms: u64,
+    pub uuid: uuid::Uuid,
+}
+
+impl FilenameFields<'_> {
+    /// Fields for a dump happening now on the calling thread
+    pub(crate) fn now<'a>(prefix: &'a str, signal: &'a str, uuid: uuid::Uuid) -> FilenameFields<'a> {
+        FilenameFields {
+            prefix,
+            signal,
+            pid: std::process::id(),
+            tid: crate::sys::current_tid(),
+            timestamp_ms: crate::sys::unix_time_ms(),
+            uuid,
+        }
+    }
+}
+
+/// A NUL-terminated path assembled without heap allocation
+pub(crate) struct PathBuffer {
+    buf: [u8; libc::PATH_MAX as usize],
+    len: usize,
+}
+
+impl PathBuffer {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: [0; libc::PATH_MAX as usize],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn as_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(self.as_bytes()))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// The path as a C string for raw syscalls
+    pub(crate) fn as_ptr(&self) -> *const libc::c_char {
+        self.buf.as_ptr() as *const libc::c_char
+    }
+
+    /// This path with `suffix` appended, or `None` if that doesn't fit
+    pub(crate) fn with_suffix(&self, suffix: &[u8]) -> Option<PathBuffer> {
+        let mut path = PathBuffer::new();
+        (path.push(self.as_bytes()) && path.push(suffix)).then_some(path)
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> bool {
+        // Keep one byte for the terminating NUL
+        if self.len + bytes.len() >= self.buf.len() {
+            return false;
+        }
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        self.buf[self.len] = 0;
+        true
+    }
+
+    fn push_u64(&mut self, mut value: u64) -> bool {
+        let mut digits = [0u8; 20];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        self.push(&digits[start..])
+    }
+
+    fn push_lowercase(&mut self, text: &str) -> bool {
+        text.bytes().all(|b| self.push(&[b.to_ascii_lowercase()]))
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.len = len;
+        self.buf[len] = 0;
+    }
+}
+
+/// Pick the template for a configuration
+pub(crate) fn template_for(template: Option<&str>, append_timestamp: bool) -> &str {
+    match template {
+        Some(template) => template,
+        None if append_timestamp => DEFAULT_TEMPLATE,
+        None => DEFAULT_TEMPLATE_NO_TIMESTAMP,
+    }
+}
+
+/// Append `template` to `out`, substituting the `{...}` placeholders.
+///
+/// Unknown placeholders are copied verbatim. Returns `false` if the result
+/// does not fit in the buffer.
+pub(crate) fn render(template: &str, fields: &FilenameFields<'_>, seq: u64, out: &mut PathBuffer) -> bool {
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        if !out.push(&rest.as_bytes()[..open]) {
+            return false;
+        }
+        rest = &rest[open..];
+
+        let Some(close) = rest.find('}') else {
+            break;
+        };
+        let ok = match &rest[1..close] {
+            "prefix" => out.push(fields.prefix.as_bytes()),
+            "signal" => out.push_lowercase(fields.signal),
+            "pid" => out.push_u64(fields.pid as u64),
+            "tid" => out.push_u64(fields.tid),
+            "timestamp" => out.push_u64(fields.timestamp_ms / 1000),
+            "timestamp_ms" => out.push_u64(fields.timestamp_ms),
+            "seq" => out.push_u64(seq),
+            "uuid" => {
+                let mut encoded = [0u8; uuid::fmt::Hyphenated::LENGTH];
+                out.push(fields.uuid.hyphenated().encode_lower(&mut encoded).as_bytes())
+            }
+            _ => out.push(&rest.as_bytes()[..=close]),
+        };
+        if !ok {
+            return false;
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push(rest.as_bytes())
+}
+
+/// Render `template` inside `directory` and create the file exclusively.
+///
+/// If the rendered name already exists, `-1`, `-2`, ... is inserted before the
+/// extension until an unused name is found, so a dump never overwrites another.
+/// On success `out` holds the path of the newly created, empty file.
+pub(crate) fn reserve_dump_path(
+    directory: &Path,
+    template: &str,
+    fields: &FilenameFields<'_>,
+    out: &mut PathBuffer,
+) -> Result<()> {
+    let seq = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+
+    out.truncate(0);
+    let dir = directory.as_os_str().as_bytes();
+    if !out.push(dir) || (!dir.is_empty() && !dir.ends_with(b"/") && !out.push(b"/")) {
+        return Err(name_too_long());
+    }
+    let name_start = out.len;
+    if !render(template, fields, seq, out) {
+        return Err(name_too_long());
+    }
+
+    if try_create(out)? {
+        return Ok(());
+    }
+
+    // Remember the extension so the `-N` suffix can go in front of it
+    let mut ext = [0u8; 32];
+    let name = &out.buf[name_start..out.len];
+    let ext_at = name
+        .iter()
+        .rposition(|&b| b == b'.')
+        .filter(|&i| i > 0 && name.len() - i <= ext.len())
+        .unwrap_or(name.len());
+    let ext_len = name.len() - ext_at;
+    ext[..ext_len].copy_from_slice(&name[ext_at..]);
+    let stem_len = name_start + ext_at;
+
+    for suffix in 1..=MAX_COLLISION_SUFFIX {
+        out.truncate(stem_len);
+        if !(out.push(b"-") && out.push_u64(suffix) && out.push(&ext[..ext_len])) {
+            return Err(name_too_long());
+        }
+        if try_create(out)? {
+            return Ok(());
+        }
+    }
+
+    Err(HandlerError::Io(std::io::Error::from_raw_os_error(libc::EEXIST)))
+}
+
+/// Create `path` with `O_EXCL`; `Ok(false)` means it already exists
+fn try_create(path: &PathBuffer) -> Result<bool> {
+    let fd: c_int = unsafe {
+        libc::open(
+            path.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC,
+            crate::dump_directory::FILE_MODE as libc::c_uint,
+        )
+    };
+    if fd >= 0 {
+        unsafe { libc::close(fd) };
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EEXIST) {
+        Ok(false)
+    } else {
+        Err(HandlerError::Io(err))
+    }
+}
+
+fn name_too_long() -> HandlerError {
+    HandlerError::Io(std::io::Error::from_raw_os_error(libc::ENAMETOOLONG))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_template_collision_gets_suffix() {
+        let temp_dir = TempDir::new().unwrap();
+        let fields = FilenameFields {
+            prefix: "app",
+            signal: "SIGSEGV",
+            pid: 42,
+            tid: 43,
+            timestamp_ms: 1_700_000_000_123,
+            uuid: uuid::Uuid::nil(),
+        };
+        let template = "{prefix}_{signal}_{pid}_{timestamp_ms}.dmp";
+
+        let mut first = PathBuffer::new();
+        reserve_dump_path(temp_dir.path(), template, &fields, &mut first).unwrap();
+        assert_eq!(
+            first.as_path(),
+            temp_dir.path().join("app_sigsegv_42_1700000000123.dmp")
+        );
+
+        let mut second = PathBuffer::new();
+        reserve_dump_path(temp_dir.path(), template, &fields, &mut second).unwrap();
+        assert_eq!(
+            second.as_path(),
+            temp_dir.path().join("app_sigsegv_42_1700000000123-1.dmp")
+        );
+    }
+}
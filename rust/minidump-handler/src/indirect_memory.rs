@@ -0,0 +1,281 @@
+//! Memory that registers and stack slots point to.
+//!
+//! Triage often needs the bytes behind a pointer: the object `self` refers
+//! to, the string being copied. After the dump is written, every register of
+//! the crashing thread (optionally of all threads) and every stack word that
+//! points into readable memory gets a small window around it added to the
+//! memory list.
+
+use crate::reader::{standard_stream, Minidump};
+use crate::{streams, Result};
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Bytes captured around each pointer by default
+pub const DEFAULT_INDIRECT_MEMORY_WINDOW: usize = 256;
+/// Total indirectly referenced memory added to a dump by default
+pub const DEFAULT_INDIRECT_MEMORY_LIMIT: usize = 1024 * 1024;
+
+/// Which indirectly referenced memory to capture
+#[derive(Debug, Clone)]
+pub struct IndirectMemory {
+    /// Capture around the crashing thread's registers and stack words
+    pub enabled: bool,
+    /// Do the same for every other thread, after the crashing one
+    pub all_threads: bool,
+    /// Bytes captured around each pointer, half before and half after it
+    pub window: usize,
+    /// Total bytes added to a dump
+    pub limit: usize,
+}
+
+impl Default for IndirectMemory {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            all_threads: false,
+            window: DEFAULT_INDIRECT_MEMORY_WINDOW,
+            limit: DEFAULT_INDIRECT_MEMORY_LIMIT,
+        }
+    }
+}
+
+/// Add the memory around the pointers found in the dump's threads to its
+/// memory list. `thread` is the crashing thread if the dump has no exception,
+/// `process` the dumped process (`None` for this process).
+///
+/// On Linux this runs in the writer child, which reads the crashed parent
+/// through `/proc/<pid>/mem` rather than its own copy of it.
+pub(crate) fn append<F: Read + Write + Seek>(
+    file: &mut F,
+    options: &IndirectMemory,
+    thread: u32,
+    process: Option<u32>,
+) -> Result<()> {
+    if !options.enabled || options.window == 0 {
+        return Ok(());
+    }
+
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut bytes)?;
+    let dump = Minidump::from_bytes(bytes)?;
+
+    let crashing = dump.exception().map_or(thread, |e| e.thread_id);
+    let mut threads = dump.threads();
+    threads.sort_by_key(|t| t.thread_id != crashing);
+    if !options.all_threads {
+        threads.retain(|t| t.thread_id == crashing);
+    }
+    let stacks: Vec<(u64, u64)> = dump
+        .threads()
+        .iter()
+        .map(|t| Ok((t.stack_start, t.stack_end()?)))
+        .collect::<Result<_>>()?;
+    let captured = |address: u64| {
+        stacks.iter().any(|&(start, end)| start <= address && address < end) || dump.memory_at(address).is_some()
+    };
+
+    // Crashing thread first, registers before stack words, so the limit cuts
+    // off the least interesting pointers
+    let memory = ProcessMemory::load(process);
+    let half = (options.window / 2).max(1) as u64;
+    let mut seen = HashSet::new();
+    let mut windows = Vec::new();
+    let mut total = 0;
+    'threads: for thread in &threads {
+        let registers = dump.registers(thread.context);
+        let stack_words = stack_words(dump.location(thread.stack).unwrap_or_default());
+        let pointers = registers
+            .iter()
+            .flat_map(|r| r.named.iter().map(|&(_, value)| value))
+            .chain(stack_words);
+
+        for pointer in pointers {
+            if captured(pointer) || !seen.insert(pointer / half) {
+                continue;
+            }
+            let Some((start, end)) = memory.window(pointer, half) else {
+                continue;
+            };
+            if total + (end - start) > options.limit as u64 {
+                break 'threads;
+            }
+            total += end - start;
+            windows.push((start, end));
+        }
+    }
+
+    windows.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in windows {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    let regions: Vec<(u64, Vec<u8>)> = merged
+        .into_iter()
+        .filter_map(|(start, end)| Some((start, memory.read(start, (end - start) as usize)?)))
+        .collect();
+    if regions.is_empty() {
+        return Ok(());
+    }
+    streams::append_streams(
+        file,
+        &[(standard_stream::MEMORY_LIST, streams::memory_list_stream(&regions)?)],
+    )
+}
+
+/// Words of a stack, pointer-sized for the dumped process, which runs on the
+/// same target as the handler
+fn stack_words(stack: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    const WORD: usize = std::mem::size_of::<usize>();
+    stack.chunks_exact(WORD).map(|word| {
+        let mut value = [0u8; 8];
+        value[..WORD].copy_from_slice(word);
+        u64::from_le_bytes(value)
+    })
+}
+
+/// Readable memory of a process, from `/proc/<pid>/maps` and
+/// `/proc/<pid>/mem` so unreadable addresses fail instead of faulting
+#[cfg(any(target_os = "linux", target_os = "android"))]
+struct ProcessMemory {
+    readable: Vec<(u64, u64)>,
+    mem: Option<std::fs::File>,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl ProcessMemory {
+    /// Memory of `process`, `None` for this process
+    fn load(process: Option<u32>) -> Self {
+        let proc_dir = crate::dump_mode::proc_dir(process);
+        let maps = std::fs::read_to_string(format!("{}/maps", proc_dir)).unwrap_or_default();
+        let readable = maps
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let (start, end) = fields.next()?.split_once('-')?;
+                let perms = fields.next()?;
+                // Reading these can hang or is meaningless
+                let path = fields.nth(3).unwrap_or("");
+                if !perms.starts_with('r') || path == "[vvar]" || path.starts_with("/dev/") {
+                    return None;
+                }
+                Some((u64::from_str_radix(start, 16).ok()?, u64::from_str_radix(end, 16).ok()?))
+            })
+            .collect();
+        Self {
+            readable,
+            mem: std::fs::File::open(format!("{}/mem", proc_dir)).ok(),
+        }
+    }
+
+    /// `half` bytes either side of `pointer`, kept inside its mapping
+    fn window(&self, pointer: u64, half: u64) -> Option<(u64, u64)> {
+        let &(start, end) = self.readable.iter().find(|&&(start, end)| start <= pointer && pointer < end)?;
+        Some((pointer.saturating_sub(half).max(start), pointer.saturating_add(half).min(end)))
+    }
+
+    fn read(&self, address: u64, len: usize) -> Option<Vec<u8>> {
+        use std::os::unix::fs::FileExt;
+
+        let mut bytes = vec![0u8; len];
+        self.mem.as_ref()?.read_exact_at(&mut bytes, address).ok()?;
+        Some(bytes)
+    }
+}
+
+/// Memory of this task, read with `mach_vm_read_overwrite` so unreadable
+/// addresses fail instead of faulting; there is no writer process, so the
+/// dumped process is always this one
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+struct ProcessMemory;
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+impl ProcessMemory {
+    /// Windows never leave the page of the pointer, the only memory known to
+    /// be mapped once the pointer itself is readable
+    const PAGE: u64 = 4096;
+
+    fn load(_process: Option<u32>) -> Self {
+        Self
+    }
+
+    fn window(&self, pointer: u64, half: u64) -> Option<(u64, u64)> {
+        self.read(pointer, 1)?;
+        let page = pointer & !(Self::PAGE - 1);
+        Some((pointer.saturating_sub(half).max(page), pointer.saturating_add(half).min(page + Self::PAGE)))
+    }
+
+    fn read(&self, address: u64, len: usize) -> Option<Vec<u8>> {
+        let mut bytes = vec![0u8; len];
+        let mut read = 0;
+        let result = unsafe {
+            mach2::vm::mach_vm_read_overwrite(
+                mach2::traps::mach_task_self(),
+                address,
+                len as u64,
+                bytes.as_mut_ptr() as u64,
+                &mut read,
+            )
+        };
+        (result == mach2::kern_return::KERN_SUCCESS && read == len as u64).then_some(bytes)
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_window_follows_pointer() {
+        let target = vec![0x5au8; 4096];
+        let pointer = target.as_ptr() as u64 + 2048;
+        let memory = ProcessMemory::load(None);
+
+        let (start, end) = memory.window(pointer, 64).unwrap();
+        assert_eq!((start, end), (pointer - 64, pointer + 64));
+        assert_eq!(memory.read(start, 128).unwrap(), vec![0x5au8; 128]);
+        assert!(memory.window(0x10, 64).is_none());
+
+        // Disabled by default: the dump is left alone
+        let mut dump = Cursor::new(Vec::new());
+        append(&mut dump, &IndirectMemory::default(), 0, None).unwrap();
+        assert!(dump.get_ref().is_empty());
+    }
+
+    #[test]
+    fn test_stack_words_are_pointer_sized() {
+        let stack: Vec<u8> = (1..=16).collect();
+        let words: Vec<u64> = stack_words(&stack).collect();
+        assert_eq!(words.len(), 16 / std::mem::size_of::<usize>());
+        assert_eq!(words[0] as usize, usize::from_le_bytes(stack[..std::mem::size_of::<usize>()].try_into().unwrap()));
+    }
+
+    #[test]
+    fn test_reads_the_parent_not_the_writer() {
+        // The writer child changes its copy of the buffer; reading the
+        // parent must still see the original bytes
+        let mut target = vec![0x5au8; 4096];
+        let pointer = target.as_mut_ptr();
+        match unsafe { libc::fork() } {
+            0 => {
+                unsafe { std::ptr::write_bytes(pointer, 0xa5, 4096) };
+                let parent = unsafe { libc::getppid() } as u32;
+                let memory = ProcessMemory::load(Some(parent));
+                let ok = memory.read(pointer as u64, 4096) == Some(vec![0x5au8; 4096]);
+                unsafe { libc::_exit(if ok { 0 } else { 1 }) }
+            }
+            child => {
+                let mut status = 0;
+                assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+                assert!(libc::WIFEXITED(status));
+                assert_eq!(libc::WEXITSTATUS(status), 0);
+                assert_eq!(target[0], 0x5a);
+            }
+        }
+    }
+}
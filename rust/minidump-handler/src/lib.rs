@@ -1,23 +1,137 @@
-use anyhow::{Context, Result};
-use libc::{c_int, c_void, sigaction, siginfo_t, SIGABRT, SIGBUS, SIGFPE, SIGILL, SIGSEGV, SIGTRAP};
+use libc::{c_int, c_void, sigaction, siginfo_t};
 use once_cell::sync::OnceCell;
+use std::io::{Cursor, Seek, Write};
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
+use dump_mode::Completion;
+
+mod app_memory;
+mod context;
+mod crash_latch;
+#[cfg(target_os = "linux")]
+mod crash_server;
+mod dump_directory;
+mod dump_mode;
+mod emergency;
+mod encryption;
+mod error;
+mod fallback;
+mod filename;
+mod indirect_memory;
+mod log_ring;
+mod process_context;
+pub mod reader;
+mod report;
+mod scrub;
+mod sidecar;
+mod signals;
+mod signing;
+pub mod streams;
+mod sys;
+#[cfg(feature = "tracing")]
+mod tracing_layer;
+
+pub use app_memory::{register_memory_region, unregister_memory_region, DEFAULT_APP_MEMORY_LIMIT};
+pub use context::{CpuContext, FaultAccess};
+pub use crash_latch::AdditionalCrash;
+#[cfg(target_os = "linux")]
+pub use crash_server::{connect_crash_server, CrashServer, ServedDump};
+pub use dump_directory::{write_file, LowDiskSpace, DEFAULT_MIN_FREE_DISK_SPACE};
+pub use dump_mode::{DumpMode, DEFAULT_FULL_DUMP_SIZE_LIMIT};
+pub use emergency::EMERGENCY_LOG;
+pub use encryption::{decrypt, encrypt, encrypted_path, format_key, generate_encryption_keys, parse_key};
+pub use error::{HandlerError, Result};
+pub use fallback::fallback_report_path;
+pub use filename::DEFAULT_TEMPLATE;
+pub use indirect_memory::{IndirectMemory, DEFAULT_INDIRECT_MEMORY_LIMIT, DEFAULT_INDIRECT_MEMORY_WINDOW};
+pub use log_ring::{
+    append_log, init_ring_logger, RingLogger, DEFAULT_LOG_FILE_TAIL_BYTES, DEFAULT_LOG_RING_CAPACITY,
+};
+pub use process_context::{ContextCollectors, DEFAULT_ENVIRONMENT_ALLOWLIST};
+pub use report::{text_report, text_report_path, write_text_report};
+pub use scrub::{scrub, scrub_dump, ScrubOptions, ScrubSummary, DEFAULT_SCRUB_STACK_BYTES};
+pub use sidecar::Sidecar;
+pub use signing::{generate_signing_keys, verify_signature};
+#[cfg(feature = "tracing")]
+pub use tracing_layer::{CrashContextLayer, DEFAULT_BREADCRUMBS};
+pub use uuid::Uuid;
+pub use signals::{
+    classify_address, code_name, signal_name, AddressClass, SignalAction, DEFAULT_SIGNALS,
+    FAULT_SIGNALS, SUPPORTED_SIGNALS,
+};
+
 /// Global configuration for crash handling
 static HANDLER_CONFIG: OnceCell<Mutex<HandlerConfig>> = OnceCell::new();
 
 /// Configuration for the crash handler
 #[derive(Clone)]
 pub struct HandlerConfig {
-    /// Directory where minidumps will be saved
+    /// Directory where minidumps will be saved. Created `0700`; an existing
+    /// directory must be owned by the effective user.
     pub dump_directory: PathBuf,
     /// Prefix for minidump filenames
     pub filename_prefix: String,
     /// Whether to append timestamp to filenames
     pub append_timestamp: bool,
+    /// Filename template overriding `filename_prefix`/`append_timestamp`.
+    ///
+    /// Supports `{prefix}`, `{signal}`, `{pid}`, `{tid}`, `{timestamp}`,
+    /// `{timestamp_ms}`, `{seq}` and `{uuid}`. If the rendered name already
+    /// exists, `-1`, `-2`, ... is inserted before the extension.
+    pub filename_template: Option<String>,
     /// Custom callback to run before writing minidump (optional)
-    pub pre_dump_callback: Option<fn()>,
+    pub pre_dump_callback: Option<fn(&SignalInfo)>,
+    /// Signals to handle and what to do for each (see `SUPPORTED_SIGNALS`)
+    pub signals: Vec<(c_int, SignalAction)>,
+    /// Descriptor, opened before the crash, to write crash dumps into instead
+    /// of a new file in `dump_directory`.
+    ///
+    /// The finished dump is written sequentially, so pipes and sockets work as
+    /// well as files. The descriptor stays owned by the caller, and no sidecar
+    /// is written for such dumps.
+    pub dump_fd: Option<RawFd>,
+    /// Process context (open files, limits, environment, ...) embedded in
+    /// each dump as extra streams
+    pub context_collectors: ContextCollectors,
+    /// Size of the in-memory log ring fed by `append_log` and `RingLogger`,
+    /// copied into each dump; 0 disables it
+    pub log_ring_capacity: usize,
+    /// Application log file whose end is copied into each dump
+    pub log_file: Option<PathBuf>,
+    /// How many bytes from the end of `log_file` are copied
+    pub log_file_tail_bytes: usize,
+    /// Total bytes of memory registered with `register_memory_region` copied
+    /// into each dump
+    pub app_memory_limit: usize,
+    /// How much of the process each dump holds
+    pub dump_mode: DumpMode,
+    /// Size `DumpMode::Full` dumps are kept within; private memory that does
+    /// not fit is left out and listed in the dump
+    pub full_dump_size_limit: usize,
+    /// Memory around pointers found in registers and on stacks, added to
+    /// each dump (off by default)
+    pub indirect_memory: IndirectMemory,
+    /// X25519 public key to encrypt finished dumps to. The dump, its sidecar
+    /// (also the sidecar-only crash record of `LowDiskSpace::Skip`) and text
+    /// report are then only written as `<file>.enc`, sealed in memory, so no
+    /// plaintext copy reaches the disk; dumps written to `dump_fd` are
+    /// encrypted as a whole.
+    ///
+    /// `<dump>.fallback.txt` and `emergency.log` stay plaintext: they are
+    /// written from the crashed process when the writer failed or the handler
+    /// crashed, and hold registers and code addresses but no memory.
+    pub encryption_key: Option<[u8; 32]>,
+    /// Ed25519 private key held by the device. Each dump written to a file is
+    /// signed together with its sidecar, which stores the signature.
+    pub signing_key: Option<[u8; 32]>,
+    /// Free space the dump filesystem must have for a dump as configured;
+    /// 0 disables the check
+    pub min_free_disk_space: u64,
+    /// What to do below `min_free_disk_space`. The reason is recorded in the
+    /// sidecar either way.
+    pub low_disk_space: LowDiskSpace,
 }
 
 impl Default for HandlerConfig {
@@ -26,7 +140,22 @@ impl Default for HandlerConfig {
             dump_directory: PathBuf::from("./dumps"),
             filename_prefix: "crash".to_string(),
             append_timestamp: true,
+            filename_template: None,
             pre_dump_callback: None,
+            signals: DEFAULT_SIGNALS.to_vec(),
+            dump_fd: None,
+            context_collectors: ContextCollectors::default(),
+            log_ring_capacity: DEFAULT_LOG_RING_CAPACITY,
+            log_file: None,
+            log_file_tail_bytes: DEFAULT_LOG_FILE_TAIL_BYTES,
+            app_memory_limit: DEFAULT_APP_MEMORY_LIMIT,
+            dump_mode: DumpMode::Normal,
+            full_dump_size_limit: DEFAULT_FULL_DUMP_SIZE_LIMIT,
+            indirect_memory: IndirectMemory::default(),
+            encryption_key: None,
+            signing_key: None,
+            min_free_disk_space: DEFAULT_MIN_FREE_DISK_SPACE,
+            low_disk_space: LowDiskSpace::Minimal,
         }
     }
 }
@@ -37,72 +166,211 @@ pub struct SignalInfo {
     pub signal: c_int,
     pub code: c_int,
     pub address: usize,
+    /// Kernel id of the thread that received the signal
+    pub tid: u64,
+    /// Registers of the crashing thread at the time of the signal
+    pub context: CpuContext,
 }
 
 impl SignalInfo {
-    fn from_siginfo(sig: c_int, info: *const siginfo_t) -> Self {
+    fn from_siginfo(sig: c_int, info: *const siginfo_t, ucontext: *const c_void) -> Self {
         unsafe {
+            let context = CpuContext::from_ucontext(ucontext);
+            let tid = sys::current_tid();
             if info.is_null() {
                 Self {
                     signal: sig,
                     code: 0,
                     address: 0,
+                    tid,
+                    context,
                 }
             } else {
                 Self {
                     signal: sig,
                     code: (*info).si_code,
                     address: (*info).si_addr() as usize,
+                    tid,
+                    context,
                 }
             }
         }
     }
 
-    fn signal_name(&self) -> &'static str {
-        match self.signal {
-            SIGSEGV => "SIGSEGV",
-            SIGBUS => "SIGBUS",
-            SIGABRT => "SIGABRT",
-            SIGFPE => "SIGFPE",
-            SIGILL => "SIGILL",
-            SIGTRAP => "SIGTRAP",
-            _ => "UNKNOWN",
+    pub fn signal_name(&self) -> &'static str {
+        signal_name(self.signal)
+    }
+
+    /// Decoded `si_code`, e.g. `"SEGV_MAPERR"` or `"SI_TKILL"`
+    pub fn code_name(&self) -> &'static str {
+        code_name(self.signal, self.code)
+    }
+
+    /// Whether the signal was sent by a process rather than caused by a fault
+    pub fn is_sent(&self) -> bool {
+        signals::is_sent(self.code)
+    }
+
+    /// Classification of the fault address, or `None` when the signal carries
+    /// no meaningful address (sent signals, `SIGABRT`, resource limits,
+    /// general protection faults)
+    pub fn address_class(&self) -> Option<AddressClass> {
+        use libc::{SIGBUS, SIGFPE, SIGILL, SIGSEGV, SIGTRAP};
+
+        if self.is_sent()
+            || signals::is_general_protection(self.signal, self.code)
+            || ![SIGSEGV, SIGBUS, SIGILL, SIGFPE, SIGTRAP].contains(&self.signal)
+        {
+            return None;
         }
+        Some(classify_address(self.address, self.context.sp))
+    }
+
+    /// How the faulting instruction accessed `address`, if known
+    pub fn access(&self) -> Option<FaultAccess> {
+        // A fault on the instruction pointer itself is a bad jump or call
+        let captured = self.context.sp != 0;
+        self.context
+            .access
+            .or_else(|| (captured && self.context.pc == self.address).then_some(FaultAccess::Execute))
+    }
+
+    /// Human-readable cause, e.g. `"null pointer read at 0x8"`
+    pub fn reason(&self) -> String {
+        if self.signal == libc::SIGABRT {
+            return "abort".to_string();
+        }
+        if signals::is_general_protection(self.signal, self.code) {
+            return "general protection fault (non-canonical or kernel address)".to_string();
+        }
+        match self.address_class() {
+            Some(class) => format!(
+                "{} at {:#x}",
+                signals::describe_fault(self.signal, self.code, class, self.access()),
+                self.address
+            ),
+            None if self.is_sent() => {
+                format!("{} sent via {}", self.signal_name(), self.code_name())
+            }
+            None => signals::describe_fault(self.signal, self.code, AddressClass::User, None),
+        }
+    }
+}
+
+impl std::fmt::Display for SignalInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.signal_name(), self.code_name(), self.reason())
     }
 }
 
 /// Initialize the crash handler with the given configuration
 pub fn init_crash_handler(config: HandlerConfig) -> Result<()> {
-    // Ensure dump directory exists
-    std::fs::create_dir_all(&config.dump_directory)
-        .with_context(|| format!("Failed to create dump directory: {:?}", config.dump_directory))?;
+    signals::check_actions(&config.signals)?;
+
+    // Ensure dump directory exists and is private
+    dump_directory::prepare(&config.dump_directory)?;
+
+    // Opened now: a crash inside the handler can't safely open files
+    emergency::open(&config.dump_directory)?;
+
+    log_ring::set_capacity(config.log_ring_capacity);
+
+    let signals = config.signals.clone();
 
     // Store configuration
     HANDLER_CONFIG
         .set(Mutex::new(config))
-        .map_err(|_| anyhow::anyhow!("Handler already initialized"))?;
+        .map_err(|_| HandlerError::AlreadyInitialized)?;
 
     // Install signal handlers
-    install_signal_handlers()?;
+    prepare_current_thread()?;
+    install_signal_handlers(&signals)?;
 
     Ok(())
 }
 
-/// Install signal handlers for common crash signals
-fn install_signal_handlers() -> Result<()> {
+/// Size of the alternate signal stack; the handler formats paths and, on
+/// Linux, forks a writer that runs on a copy of this stack.
+const ALTERNATE_STACK_SIZE: usize = 256 * 1024;
+
+thread_local! {
+    /// The alternate stack `prepare_current_thread` gave this thread
+    static ALTERNATE_STACK: std::cell::RefCell<Option<AlternateStack>> = const { std::cell::RefCell::new(None) };
+}
+
+/// An alternate signal stack owned by its thread, uninstalled and freed when
+/// the thread exits
+struct AlternateStack(Box<[u8]>);
+
+impl Drop for AlternateStack {
+    fn drop(&mut self) {
+        if current_alternate_stack().is_ok_and(|current| current.ss_sp == self.0.as_mut_ptr() as *mut c_void) {
+            let disable = libc::stack_t {
+                ss_sp: std::ptr::null_mut(),
+                ss_flags: libc::SS_DISABLE,
+                ss_size: 0,
+            };
+            unsafe { libc::sigaltstack(&disable, std::ptr::null_mut()) };
+        }
+    }
+}
+
+fn current_alternate_stack() -> Result<libc::stack_t> {
+    let mut current: libc::stack_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sigaltstack(std::ptr::null(), &mut current) } != 0 {
+        return Err(HandlerError::Io(std::io::Error::last_os_error()));
+    }
+    Ok(current)
+}
+
+/// Give the calling thread an alternate signal stack, so a stack overflow can
+/// still be handled and reported instead of killing the process silently.
+///
+/// `init_crash_handler` does this for the thread calling it; other threads
+/// that may crash should call it once after they start. An alternate stack
+/// of at least 256 KiB that is already installed, by the application or an
+/// earlier call, is kept; a smaller one is replaced. Each thread gets at most
+/// one stack from here, freed when it exits.
+pub fn prepare_current_thread() -> Result<()> {
+    let current = current_alternate_stack()?;
+    if current.ss_flags & libc::SS_DISABLE == 0 && current.ss_size >= ALTERNATE_STACK_SIZE {
+        return Ok(());
+    }
+
+    ALTERNATE_STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let stack = stack.get_or_insert_with(|| AlternateStack(vec![0u8; ALTERNATE_STACK_SIZE].into_boxed_slice()));
+        let ss = libc::stack_t {
+            ss_sp: stack.0.as_mut_ptr() as *mut c_void,
+            ss_flags: 0,
+            ss_size: ALTERNATE_STACK_SIZE,
+        };
+        if unsafe { libc::sigaltstack(&ss, std::ptr::null_mut()) } != 0 {
+            return Err(HandlerError::Io(std::io::Error::last_os_error()));
+        }
+        Ok(())
+    })
+}
+
+/// Install signal handlers for every configured signal that isn't ignored
+fn install_signal_handlers(signals: &[(c_int, SignalAction)]) -> Result<()> {
     unsafe {
         let mut sa: sigaction = std::mem::zeroed();
-        sa.sa_sigaction = signal_handler as usize;
-        sa.sa_flags = libc::SA_SIGINFO;
-
-        let signals = [SIGSEGV, SIGBUS, SIGABRT, SIGFPE, SIGILL, SIGTRAP];
+        sa.sa_sigaction = signal_handler as *const () as usize;
+        // SA_NODEFER: a fault of the same kind inside the handler must reach
+        // it again to be reported, instead of killing the process while the
+        // signal is blocked
+        sa.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK | libc::SA_NODEFER;
 
-        for &sig in &signals {
+        for &(sig, action) in signals {
+            if action == SignalAction::Ignore {
+                continue;
+            }
             if sigaction(sig, &sa, std::ptr::null_mut()) != 0 {
-                return Err(anyhow::anyhow!(
-                    "Failed to install handler for signal {}",
-                    sig
-                ));
+                return Err(HandlerError::Sigaction {
+                    signal: sig,
+                    source: std::io::Error::last_os_error(),
+                });
             }
         }
     }
@@ -111,67 +379,230 @@ fn install_signal_handlers() -> Result<()> {
 }
 
 /// Signal handler that generates minidump on crash
-extern "C" fn signal_handler(sig: c_int, info: *mut siginfo_t, _context: *mut c_void) {
-    // This runs in signal context - must be signal-safe!
-    let signal_info = SignalInfo::from_siginfo(sig, info);
-    
+extern "C" fn signal_handler(sig: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    // This runs in signal context - must be signal-safe! Everything that
+    // allocates or reads files (custom streams, signing, encryption) is left
+    // to the writer; on Linux that is a forked child.
+    let signal_info = SignalInfo::from_siginfo(sig, info, context);
+
+    // Only one thread dumps at a time; others crashing meanwhile wait here
+    // and are listed in the dump. A crash inside the handler itself (callback,
+    // filename, writer) only leaves a one-line record and terminates.
+    if let crash_latch::Entry::Reentered { first_signal } = crash_latch::acquire(signal_info.tid, sig) {
+        emergency::reentered(first_signal, &signal_info);
+    }
+
+    let mut action = SignalAction::DumpAndReraise;
+
     // Try to get handler configuration
     if let Some(config_cell) = HANDLER_CONFIG.get() {
         if let Ok(config) = config_cell.try_lock() {
+            action = config
+                .signals
+                .iter()
+                .find(|&&(s, _)| s == sig)
+                .map_or(SignalAction::DumpAndReraise, |&(_, a)| a);
+
             // Run pre-dump callback if configured
             if let Some(callback) = config.pre_dump_callback {
-                callback();
+                callback(&signal_info);
             }
 
-            // Generate filename
-            let filename = generate_filename(&config, &signal_info);
-            let dump_path = config.dump_directory.join(filename);
+            let job = DumpJob {
+                crash_id: Uuid::new_v4(),
+                signal_info: Some(&signal_info),
+                config: &config,
+                thread: signal_info.tid as u32,
+            };
+
+            if server_dumped(&signal_info, info, context, &config) {
+                // A registered crash server wrote the dump from outside
+            } else if let Some(fd) = config.dump_fd {
+                let _ = write_minidump_for_signal(DumpTarget::Fd(fd, job), &signal_info);
+            } else {
+                // Reserve a unique filename
+                let mut dump_path = filename::PathBuffer::new();
+                let template = filename::template_for(
+                    config.filename_template.as_deref(),
+                    config.append_timestamp,
+                );
+                let fields = filename::FilenameFields::now(
+                    &config.filename_prefix,
+                    signal_info.signal_name(),
+                    job.crash_id,
+                );
 
-            // Write minidump
-            let _ = write_minidump_for_signal(&dump_path, &signal_info);
+                if filename::reserve_dump_path(&config.dump_directory, template, &fields, &mut dump_path).is_ok() {
+                    // The writer puts the sidecar and report next to the
+                    // dump. If it fails, leave at least a frame-pointer
+                    // backtrace.
+                    let target = DumpTarget::Path(dump_path.as_path(), job);
+                    if let Err(e) = write_minidump_for_signal(target, &signal_info) {
+                        let _ = fallback::write_report(&dump_path, &signal_info, &e);
+                    }
+                }
+            }
         }
     }
 
-    // Re-raise the signal to trigger default behavior
+    if action == SignalAction::DumpAndContinue {
+        crash_latch::release();
+        return;
+    }
+
+    // Keep the latch: threads parked behind it die with the process
+    reraise(sig);
+}
+
+/// Ask a registered crash server to dump this process, blocking until done.
+/// `info` and `context` are the signal handler's arguments.
+fn server_dumped(signal_info: &SignalInfo, info: *const siginfo_t, context: *const c_void, config: &HandlerConfig) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let crash = unsafe { crash_server::RawCrash::capture(info, context) };
+        crash_server::request_dump(signal_info, &crash, config).is_some()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (signal_info, info, context, config);
+        false
+    }
+}
+
+/// Re-raise the signal to trigger default behavior
+fn reraise(sig: c_int) {
     unsafe {
         libc::signal(sig, libc::SIG_DFL);
         libc::raise(sig);
     }
 }
 
-/// Generate a filename for the minidump
-fn generate_filename(config: &HandlerConfig, signal_info: &SignalInfo) -> String {
-    let mut filename = format!("{}_{}", config.filename_prefix, signal_info.signal_name().to_lowercase());
-    
-    if config.append_timestamp {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        filename.push_str(&format!("_{}", timestamp));
+/// A dump to be written, as handed from the handler to the writer. Making
+/// one doesn't allocate; the writer collects the custom streams.
+#[derive(Clone, Copy)]
+struct DumpJob<'a> {
+    crash_id: Uuid,
+    /// The crash being dumped, `None` for dumps written on request
+    signal_info: Option<&'a SignalInfo>,
+    config: &'a HandlerConfig,
+    /// Thread kept by `DumpMode::Minimal` when there is no exception
+    thread: u32,
+}
+
+impl DumpJob<'_> {
+    /// How to finish the dump, with custom streams read from `process`
+    /// (`None` for this process)
+    fn completion(&self, process: Option<u32>) -> Result<Completion> {
+        let streams = custom_streams(self.crash_id, self.signal_info.is_some(), Some(self.config), process);
+        let collectors = &self.config.context_collectors;
+        Ok(Completion {
+            // A crash is dumped even if its extras can't be collected
+            streams: match self.signal_info {
+                Some(_) => streams.unwrap_or_default(),
+                None => streams?,
+            },
+            mode: self.config.dump_mode,
+            thread: self.thread,
+            size_limit: self.config.full_dump_size_limit,
+            indirect: self.config.indirect_memory.clone(),
+            process,
+            encryption_key: self.config.encryption_key,
+            signing_key: self.config.signing_key,
+            environment_allowlist: collectors
+                .environment
+                .then(|| collectors.environment_allowlist.clone()),
+        })
+    }
+
+    fn sidecar(&self) -> Sidecar {
+        match self.signal_info {
+            Some(signal_info) => Sidecar::for_signal(self.crash_id, signal_info),
+            None => Sidecar::new(self.crash_id),
+        }
+    }
+}
+
+/// Where a dump is written
+#[derive(Clone, Copy)]
+enum DumpTarget<'a> {
+    /// A file at this path, with its sidecar and text report next to it
+    Path(&'a Path, DumpJob<'a>),
+    /// A caller-owned descriptor. The dump is buffered, completed and then
+    /// written out in one go.
+    Fd(RawFd, DumpJob<'a>),
+}
+
+/// A platform writer producing the raw dump that is then completed
+trait RawDump {
+    fn write_raw<W: Write + Seek>(&mut self, out: &mut W) -> Result<()>;
+}
+
+#[cfg(target_os = "macos")]
+impl RawDump for minidump_writer::minidump_writer::MinidumpWriter {
+    fn write_raw<W: Write + Seek>(&mut self, out: &mut W) -> Result<()> {
+        self.dump(out).map(|_| ()).map_err(|e| HandlerError::Writer(e.to_string()))
+    }
+}
+
+#[cfg(target_os = "ios")]
+impl RawDump for minidump_writer::apple::ios::MinidumpWriter {
+    fn write_raw<W: Write + Seek>(&mut self, out: &mut W) -> Result<()> {
+        self.dump(out).map(|_| ()).map_err(|e| HandlerError::Writer(e.to_string()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl RawDump for minidump_writer::minidump_writer::MinidumpWriter {
+    fn write_raw<W: Write + Seek>(&mut self, out: &mut W) -> Result<()> {
+        self.dump(out).map(|_| ()).map_err(|e| {
+            if is_ptrace_denied(&e) {
+                HandlerError::PtraceDenied(e.to_string())
+            } else {
+                HandlerError::Writer(e.to_string())
+            }
+        })
     }
-    
-    filename.push_str(".dmp");
-    filename
+}
+
+/// Write and complete the dump of `target`, reading the dumped process's
+/// extras from `process` (`None` for this process)
+fn write_target(target: DumpTarget<'_>, process: Option<u32>, writer: &mut impl RawDump) -> Result<()> {
+    match target {
+        DumpTarget::Path(path, job) => write_dump_files(path, &job, process, writer),
+        DumpTarget::Fd(fd, job) => {
+            let completion = job.completion(process)?;
+            let mut buffer = Cursor::new(Vec::new());
+            writer.write_raw(&mut buffer)?;
+            flush_to_fd(&mut buffer, fd, &completion)
+        }
+    }
+}
+
+/// Complete a dump buffered in memory and write it to `fd`
+fn flush_to_fd(buffer: &mut Cursor<Vec<u8>>, fd: RawFd, completion: &Completion) -> Result<()> {
+    use std::os::unix::io::FromRawFd;
+
+    completion.complete_buffer(buffer)?;
+    // Borrow the descriptor: it must stay open after this returns
+    let mut out = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+    out.write_all(buffer.get_ref())?;
+    Ok(())
 }
 
 /// Platform-specific minidump writing
 #[cfg(target_os = "macos")]
-fn write_minidump_for_signal(path: &Path, _signal_info: &SignalInfo) -> Result<()> {
+fn write_minidump_for_signal(target: DumpTarget<'_>, _signal_info: &SignalInfo) -> Result<()> {
     use minidump_writer::minidump_writer::MinidumpWriter;
     
-    // Create the writer with current task and thread
+    // Create the writer with current task and thread. Without a separate
+    // writer process, the dump is finished here.
     let mut writer = MinidumpWriter::new(None, None);
-    
-    // Write the minidump
-    writer.dump(&mut std::fs::File::create(path)?)
-        .map_err(|e| anyhow::anyhow!("Failed to write minidump: {}", e))?;
-    
-    Ok(())
+    write_target(target, None, &mut writer)
 }
 
 #[cfg(target_os = "ios")]
-fn write_minidump_for_signal(path: &Path, signal_info: &SignalInfo) -> Result<()> {
+fn write_minidump_for_signal(target: DumpTarget<'_>, signal_info: &SignalInfo) -> Result<()> {
     use minidump_writer::apple::ios::{MinidumpWriter, IosCrashContext, IosExceptionInfo};
     
     // Get current thread state
@@ -194,128 +625,403 @@ fn write_minidump_for_signal(path: &Path, signal_info: &SignalInfo) -> Result<()
     let mut writer = MinidumpWriter::new();
     writer.set_crash_context(crash_context);
     
-    // Write the minidump
-    writer.dump(&mut std::fs::File::create(path)?)
-        .map_err(|e| anyhow::anyhow!("Failed to write minidump: {}", e))?;
-    
-    Ok(())
+    // Write the minidump; iOS can't fork, so it is finished here
+    write_target(target, None, &mut writer)
 }
 
 #[cfg(target_os = "linux")]
-fn write_minidump_for_signal(path: &Path, _signal_info: &SignalInfo) -> Result<()> {
-    use minidump_writer::minidump_writer::MinidumpWriter;
-    
+fn write_minidump_for_signal(target: DumpTarget<'_>, signal_info: &SignalInfo) -> Result<()> {
     // In signal handler context, we need to fork to write minidump
+    dump_in_child(target, Some(signal_info.tid as libc::pid_t), None)
+}
+
+/// Fork a child that ptraces this process and writes the minidump.
+///
+/// The child also collects the custom streams, reading this process through
+/// `/proc`, and signs, seals and writes the files next to the dump. It
+/// reports why it failed over a pipe, so the parent gets a real
+/// `HandlerError` instead of just an exit code. Only async-signal-safe calls
+/// are made in the parent, so this is also used from the signal handler.
+#[cfg(target_os = "linux")]
+fn dump_in_child(
+    target: DumpTarget<'_>,
+    blamed_thread: Option<libc::pid_t>,
+    settle: Option<std::time::Duration>,
+) -> Result<()> {
+    use minidump_writer::minidump_writer::MinidumpWriter;
+
+    let mut fds = [0 as c_int; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(HandlerError::Io(std::io::Error::last_os_error()));
+    }
+    let [read_fd, write_fd] = fds;
+
     match unsafe { libc::fork() } {
         -1 => {
-            // Fork failed, can't write minidump
-            return Err(anyhow::anyhow!("Failed to fork in signal handler"));
+            let err = std::io::Error::last_os_error();
+            unsafe {
+                libc::close(read_fd);
+                libc::close(write_fd);
+            }
+            Err(HandlerError::Fork(err))
         }
         0 => {
-            // Child process - write minidump of parent
+            // Child process - write minidump of parent. The child inherits
+            // the handlers and a latch it can never take, so a crash in the
+            // writer must just kill it.
+            unsafe { libc::close(read_fd) };
+            for &sig in &SUPPORTED_SIGNALS {
+                unsafe { libc::signal(sig, libc::SIG_DFL) };
+            }
             let ppid = unsafe { libc::getppid() };
-            let parent_tid = ppid; // Assume main thread for now
-            
+            let parent_tid = blamed_thread.unwrap_or(ppid);
+
+            // Give parent time to continue
+            if let Some(delay) = settle {
+                std::thread::sleep(delay);
+            }
+
             let mut writer = MinidumpWriter::new(ppid, parent_tid);
-            match std::fs::File::create(path) {
-                Ok(mut file) => {
-                    if let Err(_) = writer.dump(&mut file) {
-                        std::process::exit(1);
-                    }
+            let result = write_target(target, Some(ppid as u32), &mut writer);
+
+            let exit_code = match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    let mut buf = [0u8; 512];
+                    let len = e.encode(&mut buf);
+                    unsafe { libc::write(write_fd, buf.as_ptr() as *const c_void, len) };
+                    1
+                }
+            };
+
+            unsafe { libc::_exit(exit_code) }
+        }
+        child_pid => {
+            // Parent process - collect the child's report, then reap it
+            unsafe { libc::close(write_fd) };
+
+            let mut buf = [0u8; 512];
+            let mut len = 0;
+            while len < buf.len() {
+                let n = unsafe {
+                    libc::read(read_fd, buf[len..].as_mut_ptr() as *mut c_void, buf.len() - len)
+                };
+                if n > 0 {
+                    len += n as usize;
+                } else if n == 0 || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+                    break;
                 }
-                Err(_) => std::process::exit(1),
             }
-            std::process::exit(0);
+            unsafe { libc::close(read_fd) };
+
+            let mut status = 0;
+            let result = loop {
+                let r = unsafe { libc::waitpid(child_pid, &mut status, 0) };
+                if r != -1 || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+                    break r;
+                }
+            };
+
+            if result == -1 {
+                return Err(HandlerError::Io(std::io::Error::last_os_error()));
+            }
+
+            if libc::WIFEXITED(status) {
+                match libc::WEXITSTATUS(status) {
+                    0 => Ok(()),
+                    code => Err(HandlerError::decode(&buf[..len]).unwrap_or_else(|| {
+                        HandlerError::Writer(format!("writer process failed with exit code {}", code))
+                    })),
+                }
+            } else {
+                Err(HandlerError::Writer("writer process terminated abnormally".into()))
+            }
         }
-        _child_pid => {
-            // Parent process in signal handler - just wait briefly
-            // We can't do much in signal handler context
-            std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+/// Whether a writer error was caused by the kernel refusing `ptrace`.
+///
+/// minidump-writer wraps the errno from `ptrace(PTRACE_ATTACH)` several layers
+/// deep and with its own `nix` version, so the source chain is matched by text.
+#[cfg(target_os = "linux")]
+fn is_ptrace_denied(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(e) = current {
+        let message = e.to_string();
+        if message.contains("EPERM") || message.contains("Operation not permitted") {
+            return true;
         }
+        current = e.source();
     }
-    
+    false
+}
+
+/// Custom streams added to every dump: the crash id and, for crashes, the
+/// threads that crashed while it was being handled. With a `config` for the
+/// dumped process, also its context, logs and registered memory, read from
+/// `process` (`None` for this process).
+///
+/// The writer child calls this for its parent: the in-memory state (crash
+/// latch, log ring, tracing context, memory registry) is its copy of the
+/// parent's, everything else comes from `/proc/<process>`.
+fn custom_streams(
+    crash_id: Uuid,
+    crashed: bool,
+    config: Option<&HandlerConfig>,
+    process: Option<u32>,
+) -> Result<Vec<(u32, Vec<u8>)>> {
+    let mut encoded = [0u8; uuid::fmt::Hyphenated::LENGTH];
+    let crash_id_text = crash_id.hyphenated().encode_lower(&mut encoded);
+
+    let mut streams = vec![(streams::stream_type::CRASH_ID, crash_id_text.as_bytes().to_vec())];
+    if crashed {
+        let additional = crash_latch::additional_crashes();
+        if !additional.is_empty() {
+            let json = serde_json::to_vec(&additional).map_err(std::io::Error::from)?;
+            streams.push((streams::stream_type::ADDITIONAL_CRASHES, json));
+        }
+    }
+    if let Some(config) = config {
+        streams.extend(process_context::collect(process, &config.context_collectors));
+        if let Some(ring) = log_ring::snapshot() {
+            streams.push((streams::stream_type::LOG_RING, ring));
+        }
+        if let Some(tail) = config
+            .log_file
+            .as_deref()
+            .and_then(|path| log_ring::file_tail(path, config.log_file_tail_bytes))
+        {
+            streams.push((streams::stream_type::LOG_FILE_TAIL, tail));
+        }
+        if let Some(captured) = app_memory::capture(config.app_memory_limit, process) {
+            streams.push((reader::standard_stream::MEMORY_LIST, streams::memory_list_stream(&captured.memory)?));
+            streams.push((streams::stream_type::APP_MEMORY_NAMES, captured.names));
+        }
+        #[cfg(feature = "tracing")]
+        if let Some(context) = tracing_layer::snapshot() {
+            streams.push((streams::stream_type::TRACING_CONTEXT, context));
+        }
+    }
+    Ok(streams)
+}
+
+/// Run `f` with the installed configuration, or the default one
+fn with_config<T>(f: impl FnOnce(&HandlerConfig) -> T) -> T {
+    match HANDLER_CONFIG.get().map(|config| config.lock()) {
+        Some(Ok(config)) => f(&config),
+        _ => f(&HandlerConfig::default()),
+    }
+}
+
+/// Write the dump at `path` and the files next to it: the sidecar, signed
+/// with a signing key, and the text report. With an encryption key, all of
+/// them are sealed before they are written, so no plaintext reaches the disk.
+///
+/// Below the free space threshold, the dump is shrunk, or for crashes under
+/// `LowDiskSpace::Skip` only the sidecar is written.
+///
+/// On Linux this runs in the writer child, off the crashed process.
+fn write_dump_files(path: &Path, job: &DumpJob<'_>, process: Option<u32>, writer: &mut impl RawDump) -> Result<()> {
+    let mut completion = job.completion(process)?;
+    let mut sidecar = job.sidecar();
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match guard_disk_space(job.config, directory, &mut completion) {
+        Ok(degraded) => sidecar.degraded = degraded,
+        Err(e) if job.signal_info.is_some() => {
+            let _ = std::fs::remove_file(path);
+            sidecar.degraded = Some(e.to_string());
+            return write_sidecar(path, &sidecar, &completion);
+        }
+        Err(e) => return Err(e),
+    }
+    write_completed(path, &completion, sidecar, writer)
+}
+
+/// Write the dump at `path` from `writer`, finished by `completion`, with the
+/// sidecar and text report, signed and sealed as `completion` says
+fn write_completed(path: &Path, completion: &Completion, mut sidecar: Sidecar, writer: &mut impl RawDump) -> Result<()> {
+    let Some(key) = &completion.encryption_key else {
+        writer.write_raw(&mut dump_directory::create_file(path)?)?;
+        complete_dump_file(path, completion)?;
+        if let Some(signing_key) = &completion.signing_key {
+            signing::sign(&std::fs::read(path)?, &mut sidecar, signing_key)?;
+        }
+        return sidecar.write(path);
+    };
+
+    let mut buffer = Cursor::new(Vec::new());
+    writer.write_raw(&mut buffer)?;
+    completion.finish_buffer(&mut buffer)?;
+    let dump = buffer.into_inner();
+    if let Some(signing_key) = &completion.signing_key {
+        signing::sign(&dump, &mut sidecar, signing_key)?;
+    }
+    encryption::write_sealed(path, &dump, key)?;
+    // Only the reservation, which is empty
+    let _ = std::fs::remove_file(path);
+    write_sidecar(path, &sidecar, completion)?;
+
+    // The report is a convenience; the dump is complete without it
+    if let Ok(minidump) = reader::Minidump::from_bytes(dump) {
+        let report = text_report(&minidump, &report::title(path));
+        let _ = encryption::write_sealed(&text_report_path(path), report.as_bytes(), key);
+    }
+    Ok(())
+}
+
+/// Write the sidecar of the dump at `path`, sealed with an encryption key
+fn write_sidecar(path: &Path, sidecar: &Sidecar, completion: &Completion) -> Result<()> {
+    match &completion.encryption_key {
+        Some(key) => {
+            encryption::write_sealed(&Sidecar::path_for(path), &sidecar.to_json()?, key)?;
+            Ok(())
+        }
+        None => sidecar.write(path),
+    }
+}
+
+/// Shape a freshly written dump to its mode, embed the custom streams and put
+/// the text report next to it.
+fn complete_dump_file(path: &Path, completion: &Completion) -> Result<()> {
+    if completion.mode == DumpMode::Minimal {
+        let dump = std::fs::read(path)?;
+        dump_directory::write_file(path, dump_mode::minimal(&dump, completion.thread)?)?;
+    }
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+    completion.append_to(&mut file)?;
+    drop(file);
+
+    // The report is a convenience; the dump is complete without it
+    let _ = write_text_report(path);
     Ok(())
 }
 
-/// Manually write a minidump for the current process (no crash)
-pub fn write_minidump(path: &Path) -> Result<()> {
+/// Manually write a minidump for the current process (no crash).
+///
+/// Returns the crash id embedded in the dump and its sidecar.
+///
+/// With an encryption key configured, the dump, sidecar and report are only
+/// written encrypted, as `<file>.enc`.
+pub fn write_minidump(path: &Path) -> Result<Uuid> {
+    // A copy, so a crash meanwhile still finds the configuration unlocked
+    let config = with_config(HandlerConfig::clone);
+    let job = DumpJob {
+        crash_id: Uuid::new_v4(),
+        signal_info: None,
+        config: &config,
+        thread: sys::current_tid() as u32,
+    };
+
     #[cfg(target_os = "macos")]
     {
         use minidump_writer::minidump_writer::MinidumpWriter;
         
-        let mut writer = MinidumpWriter::new(None, None);
-        
-        writer.dump(&mut std::fs::File::create(path)?)
-            .map_err(|e| anyhow::anyhow!("Failed to write minidump: {}", e))?;
+        write_target(DumpTarget::Path(path, job), None, &mut MinidumpWriter::new(None, None))?;
     }
     
     #[cfg(target_os = "ios")]
     {
         use minidump_writer::apple::ios::MinidumpWriter;
         
-        let mut writer = MinidumpWriter::new();
-        writer.dump(&mut std::fs::File::create(path)?)
-            .map_err(|e| anyhow::anyhow!("Failed to write minidump: {}", e))?;
+        write_target(DumpTarget::Path(path, job), None, &mut MinidumpWriter::new())?;
     }
     
     #[cfg(target_os = "linux")]
     {
-        use minidump_writer::minidump_writer::MinidumpWriter;
-        
         // Use fork to allow ptrace of the parent process
-        match unsafe { libc::fork() } {
-            -1 => {
-                return Err(anyhow::anyhow!("Failed to fork: {}", std::io::Error::last_os_error()));
-            }
-            0 => {
-                // Child process - write minidump of parent
-                let ppid = unsafe { libc::getppid() };
-                let parent_tid = ppid; // For the main thread
-                
-                // Give parent time to continue
-                std::thread::sleep(std::time::Duration::from_millis(50));
-                
-                let mut writer = MinidumpWriter::new(ppid, parent_tid);
-                let exit_code = match std::fs::File::create(path) {
-                    Ok(mut file) => {
-                        match writer.dump(&mut file) {
-                            Ok(_) => 0,
-                            Err(e) => {
-                                eprintln!("Failed to write minidump: {}", e);
-                                1
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to create file: {}", e);
-                        1
-                    }
-                };
-                
-                std::process::exit(exit_code);
-            }
-            child_pid => {
-                // Parent process - wait for child to complete
-                let mut status = 0;
-                let result = unsafe { libc::waitpid(child_pid, &mut status, 0) };
-                
-                if result == -1 {
-                    return Err(anyhow::anyhow!("Failed to wait for child: {}", std::io::Error::last_os_error()));
-                }
-                
-                if libc::WIFEXITED(status) {
-                    let exit_code = libc::WEXITSTATUS(status);
-                    if exit_code != 0 {
-                        return Err(anyhow::anyhow!("Child process failed with exit code {}", exit_code));
-                    }
-                } else {
-                    return Err(anyhow::anyhow!("Child process terminated abnormally"));
-                }
-            }
-        }
+        dump_in_child(
+            DumpTarget::Path(path, job),
+            None,
+            Some(std::time::Duration::from_millis(50)),
+        )?;
     }
     
-    Ok(())
+    Ok(job.crash_id)
+}
+
+/// Check the free space where a dump is about to be written into `directory`.
+///
+/// Below `min_free_disk_space` the dump is either shrunk to `Minimal`, without
+/// extra memory, in which case the reason is returned, or refused.
+fn guard_disk_space(config: &HandlerConfig, directory: &Path, completion: &mut Completion) -> Result<Option<String>> {
+    if config.min_free_disk_space == 0 {
+        return Ok(None);
+    }
+    let Some(available) = dump_directory::free_space(directory) else {
+        return Ok(None);
+    };
+    if available >= config.min_free_disk_space {
+        return Ok(None);
+    }
+
+    let low = HandlerError::LowDiskSpace {
+        available,
+        required: config.min_free_disk_space,
+    };
+    match config.low_disk_space {
+        LowDiskSpace::Skip => Err(low),
+        LowDiskSpace::Minimal => {
+            completion.mode = DumpMode::Minimal;
+            completion.indirect.enabled = false;
+            completion.streams.retain(|&(stream_type, _)| stream_type != reader::standard_stream::MEMORY_LIST);
+            Ok(Some(format!("{}; wrote a minimal dump", low)))
+        }
+    }
+}
+
+/// Manually write a minidump for the current process into `sink`, e.g. a
+/// `Cursor<Vec<u8>>` in tests.
+///
+/// The dump includes the crash id stream; no sidecar is written. With an
+/// encryption key configured, what is written to `sink` is the encrypted
+/// dump, to be opened with [`decrypt`]. Returns the crash id.
+pub fn write_minidump_to<W: Write + Seek>(sink: &mut W) -> Result<Uuid> {
+    let config = with_config(HandlerConfig::clone);
+    let job = DumpJob {
+        crash_id: Uuid::new_v4(),
+        signal_info: None,
+        config: &config,
+        thread: sys::current_tid() as u32,
+    };
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        let completion = job.completion(None)?;
+        let mut buffer = Cursor::new(Vec::new());
+        #[cfg(target_os = "macos")]
+        minidump_writer::minidump_writer::MinidumpWriter::new(None, None).write_raw(&mut buffer)?;
+        #[cfg(target_os = "ios")]
+        minidump_writer::apple::ios::MinidumpWriter::new().write_raw(&mut buffer)?;
+        completion.complete_buffer(&mut buffer)?;
+        sink.write_all(buffer.get_ref())?;
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::FromRawFd;
+
+        // The forked writer can't hand back memory, so it fills an anonymous
+        // file that is then copied into the sink
+        let fd = unsafe { libc::memfd_create(b"minidump\0".as_ptr().cast(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(HandlerError::Io(std::io::Error::last_os_error()));
+        }
+        let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+        dump_in_child(
+            DumpTarget::Fd(fd, job),
+            None,
+            Some(std::time::Duration::from_millis(50)),
+        )?;
+        file.seek(std::io::SeekFrom::Start(0))?;
+        std::io::copy(&mut file, sink)?;
+    }
+
+    sink.flush()?;
+    Ok(job.crash_id)
 }
 
 /// Trigger various types of crashes for testing
@@ -325,8 +1031,10 @@ pub mod crash_triggers {
     /// Trigger a segmentation fault
     pub fn trigger_segfault() {
         unsafe {
-            let null_ptr: *mut i32 = ptr::null_mut();
-            *null_ptr = 42;
+            // Offset into the null page: debug builds turn a write through an
+            // exactly-null pointer into a panic instead of a SIGSEGV
+            let null_ptr: *mut i32 = ptr::null_mut::<i32>().wrapping_add(1);
+            ptr::write_volatile(null_ptr, 42);
         }
     }
 
@@ -398,6 +1106,33 @@ pub mod crash_triggers {
         }
         recurse(0);
     }
+
+    /// `pre_dump_callback` that crashes, to exercise the handler's
+    /// reentrancy protection
+    pub fn crashing_callback(_signal_info: &crate::SignalInfo) {
+        trigger_segfault();
+    }
+
+    /// Crash several threads at the same moment
+    pub fn trigger_multi_thread_crash() {
+        const THREADS: usize = 4;
+
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(THREADS));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let _ = crate::prepare_current_thread();
+                    barrier.wait();
+                    trigger_segfault();
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
 }
 
 // Public API for FFI
@@ -411,6 +1146,7 @@ pub fn install_handlers(dump_path: &str) -> Result<()> {
             .to_string(),
         append_timestamp: true,
         pre_dump_callback: None,
+        ..Default::default()
     };
     init_crash_handler(config)
 }
@@ -436,16 +1172,126 @@ mod tests {
         assert!(init_crash_handler(config).is_ok());
     }
 
+    #[test]
+    fn test_alternate_stack_is_reused() {
+        std::thread::spawn(|| {
+            prepare_current_thread().unwrap();
+            let first = current_alternate_stack().unwrap();
+            assert!(first.ss_size >= ALTERNATE_STACK_SIZE);
+
+            prepare_current_thread().unwrap();
+            assert_eq!(current_alternate_stack().unwrap().ss_sp, first.ss_sp);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_supported_signal_names() {
+        for &sig in &SUPPORTED_SIGNALS {
+            assert_ne!(signal_name(sig), "UNKNOWN", "signal {} has no name", sig);
+        }
+    }
+
+    #[test]
+    fn test_signal_reason() {
+        let sp = 0x7fff_0000_0000;
+        let mut info = SignalInfo {
+            signal: libc::SIGSEGV,
+            code: 1, // SEGV_MAPERR
+            address: 0x8,
+            tid: 1,
+            context: CpuContext {
+                sp,
+                access: Some(FaultAccess::Read),
+                ..Default::default()
+            },
+        };
+        assert_eq!(info.address_class(), Some(AddressClass::NullPage));
+        assert_eq!(info.reason(), "null pointer read at 0x8");
+
+        info.address = sp - 0x40;
+        info.context.access = Some(FaultAccess::Write);
+        assert_eq!(info.address_class(), Some(AddressClass::NearStackGuard));
+        assert!(info.reason().starts_with("stack overflow"));
+
+        // A general protection fault reports si_addr 0, which isn't a null
+        // pointer access
+        #[cfg(target_os = "linux")]
+        {
+            info.code = 0x80; // SI_KERNEL
+            info.address = 0;
+            assert_eq!(info.code_name(), "SI_KERNEL");
+            assert!(!info.is_sent());
+            assert_eq!(info.address_class(), None);
+            assert_eq!(info.reason(), "general protection fault (non-canonical or kernel address)");
+        }
+    }
+
     #[test]
     fn test_manual_minidump() {
         let temp_dir = TempDir::new().unwrap();
         let dump_path = temp_dir.path().join("test.dmp");
         
-        assert!(write_minidump(&dump_path).is_ok());
+        let crash_id = write_minidump(&dump_path).unwrap();
         assert!(dump_path.exists());
+
+        let sidecar = Sidecar::read(&dump_path).unwrap();
+        assert_eq!(sidecar.crash_id, Some(crash_id.to_string()));
         
         // Verify file is not empty
         let metadata = fs::metadata(&dump_path).unwrap();
         assert!(metadata.len() > 0);
+
+        let report = fs::read_to_string(text_report_path(&dump_path)).unwrap();
+        assert!(report.contains(&crash_id.to_string()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sealed_dump_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let dump_path = temp_dir.path().join("sealed.dmp");
+        let (private_key, public_key) = generate_encryption_keys();
+        let config = HandlerConfig {
+            dump_directory: temp_dir.path().to_path_buf(),
+            encryption_key: Some(public_key),
+            ..Default::default()
+        };
+        let job = DumpJob {
+            crash_id: Uuid::new_v4(),
+            signal_info: None,
+            config: &config,
+            thread: sys::current_tid() as u32,
+        };
+        dump_in_child(DumpTarget::Path(&dump_path, job), None, None).unwrap();
+
+        // Only the sealed files are left
+        assert!(!dump_path.exists());
+        assert!(!Sidecar::path_for(&dump_path).exists());
+        assert!(!text_report_path(&dump_path).exists());
+
+        let dump = decrypt(&fs::read(encrypted_path(&dump_path)).unwrap(), &private_key).unwrap();
+        assert!(reader::Minidump::from_bytes(dump).is_ok());
+        let sidecar = decrypt(&fs::read(encrypted_path(&Sidecar::path_for(&dump_path))).unwrap(), &private_key).unwrap();
+        let sidecar: Sidecar = serde_json::from_slice(&sidecar).unwrap();
+        assert_eq!(sidecar.crash_id, Some(job.crash_id.to_string()));
+        let report = decrypt(&fs::read(encrypted_path(&text_report_path(&dump_path))).unwrap(), &private_key).unwrap();
+        assert!(String::from_utf8(report).unwrap().contains(&job.crash_id.to_string()));
+    }
+
+    #[test]
+    fn test_minidump_to_memory() {
+        let mut sink = Cursor::new(Vec::new());
+        let crash_id = write_minidump_to(&mut sink).unwrap();
+
+        let header = streams::read_header(&mut sink).unwrap();
+        let directory = streams::read_directory(&mut sink, &header).unwrap();
+        let entry = directory
+            .iter()
+            .find(|e| e.stream_type == streams::stream_type::CRASH_ID)
+            .expect("crash id stream");
+        let data = &sink.get_ref()[entry.rva as usize..][..entry.data_size as usize];
+        assert_eq!(data, crash_id.to_string().as_bytes());
     }
 }
\ No newline at end of file
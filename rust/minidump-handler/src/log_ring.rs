@@ -0,0 +1,178 @@
+//! In-memory ring of the most recent log lines, copied into every dump.
+//!
+//! The buffer is allocated once (at `init_crash_handler`, or on the first
+//! line logged before that) and then overwritten in place, so it never grows
+//! and the crash handler only has to copy it out.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+/// Ring size used when lines are logged before the handler is initialized
+pub const DEFAULT_LOG_RING_CAPACITY: usize = 64 * 1024;
+/// How much of `HandlerConfig::log_file` is copied by default
+pub const DEFAULT_LOG_FILE_TAIL_BYTES: usize = 32 * 1024;
+
+static RING: Mutex<Ring> = Mutex::new(Ring::unconfigured());
+
+struct Ring {
+    buf: Vec<u8>,
+    /// Index of the oldest byte
+    start: usize,
+    len: usize,
+    configured: bool,
+}
+
+impl Ring {
+    const fn unconfigured() -> Self {
+        Self {
+            buf: Vec::new(),
+            start: 0,
+            len: 0,
+            configured: false,
+        }
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: vec![0; capacity],
+            start: 0,
+            len: 0,
+            configured: true,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let capacity = self.buf.len();
+        if capacity == 0 {
+            return;
+        }
+        // Only the end of an oversized line fits
+        let bytes = &bytes[bytes.len().saturating_sub(capacity)..];
+        for &byte in bytes {
+            let end = (self.start + self.len) % capacity;
+            self.buf[end] = byte;
+            if self.len == capacity {
+                self.start = (self.start + 1) % capacity;
+            } else {
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Contents oldest first, starting at a line boundary once the ring has
+    /// wrapped
+    fn contents(&self) -> Vec<u8> {
+        let capacity = self.buf.len();
+        let mut out: Vec<u8> = (0..self.len).map(|i| self.buf[(self.start + i) % capacity]).collect();
+        if self.len == capacity {
+            if let Some(newline) = out.iter().position(|&b| b == b'\n') {
+                out.drain(..=newline);
+            }
+        }
+        out
+    }
+}
+
+fn lock() -> MutexGuard<'static, Ring> {
+    RING.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Size the ring; existing lines are kept as far as they fit. 0 disables it.
+pub(crate) fn set_capacity(capacity: usize) {
+    let mut ring = lock();
+    let previous = ring.contents();
+    *ring = Ring::with_capacity(capacity);
+    ring.push(&previous);
+}
+
+/// Add one line to the log ring that is copied into every dump
+pub fn append_log(line: &str) {
+    let mut ring = lock();
+    if !ring.configured {
+        *ring = Ring::with_capacity(DEFAULT_LOG_RING_CAPACITY);
+    }
+    ring.push(line.as_bytes());
+    if !line.ends_with('\n') {
+        ring.push(b"\n");
+    }
+}
+
+/// Copy of the ring for a dump. Gives up rather than wait if the ring is
+/// locked, e.g. because the crashing thread was in the middle of logging.
+pub(crate) fn snapshot() -> Option<Vec<u8>> {
+    let ring = RING.try_lock().ok()?;
+    let contents = ring.contents();
+    (!contents.is_empty()).then_some(contents)
+}
+
+/// The last `max_bytes` of a log file, starting at a line boundary
+pub(crate) fn file_tail(path: &Path, max_bytes: usize) -> Option<Vec<u8>> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let size = file.seek(SeekFrom::End(0)).ok()?;
+    let start = size.saturating_sub(max_bytes as u64);
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let mut tail = Vec::with_capacity((size - start) as usize);
+    file.read_to_end(&mut tail).ok()?;
+    if start > 0 {
+        let newline = tail.iter().position(|&b| b == b'\n')?;
+        tail.drain(..=newline);
+    }
+    (!tail.is_empty()).then_some(tail)
+}
+
+/// `log` logger that feeds the ring
+pub struct RingLogger {
+    level: log::LevelFilter,
+}
+
+static LOGGER: once_cell::sync::OnceCell<RingLogger> = once_cell::sync::OnceCell::new();
+
+/// Install a `log` logger writing records up to `level` into the log ring
+pub fn init_ring_logger(level: log::LevelFilter) -> Result<(), log::SetLoggerError> {
+    let logger = LOGGER.get_or_init(|| RingLogger { level });
+    log::set_logger(logger)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+impl log::Log for RingLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        append_log(&format!(
+            "{} {:<5} {}: {}",
+            crate::sys::unix_time_ms(),
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_keeps_latest_lines() {
+        let mut ring = Ring::with_capacity(16);
+        ring.push(b"first line\n");
+        ring.push(b"second\n");
+        ring.push(b"third\n");
+
+        // "first line" was partly overwritten, so what is left of it is dropped
+        assert_eq!(ring.contents(), b"second\nthird\n");
+
+        let mut ring = Ring::with_capacity(64);
+        ring.push(b"a\nb\n");
+        assert_eq!(ring.contents(), b"a\nb\n");
+    }
+}
@@ -0,0 +1,345 @@
+//! Process context embedded in dumps as custom JSON streams.
+//!
+//! Each collector reads one kind of information from `/proc` (or the matching
+//! syscall) and yields one stream. Collectors that fail are skipped, so a
+//! missing `/proc` or cgroup file never costs the dump. Collection only does
+//! something on Linux.
+
+use crate::streams::stream_type;
+
+/// Environment variables captured by default; anything else may hold secrets
+pub const DEFAULT_ENVIRONMENT_ALLOWLIST: &[&str] = &[
+    "LANG",
+    "LC_ALL",
+    "TZ",
+    "RUST_BACKTRACE",
+    "RUST_LOG",
+    "RUST_MIN_STACK",
+];
+
+/// Which process context to capture with each dump
+#[derive(Debug, Clone)]
+pub struct ContextCollectors {
+    /// Targets of `/proc/self/fd/*`
+    pub open_files: bool,
+    /// Soft and hard resource limits
+    pub rlimits: bool,
+    /// `getrusage` of the process; for another process, the same figures
+    /// from its `/proc` entries
+    pub rusage: bool,
+    /// Memory limit and usage of the process's cgroup (v1 or v2)
+    pub cgroup_memory: bool,
+    /// Environment variables named in `environment_allowlist`. While on, the
+    /// writer's own full environment stream is cut down to the same list.
+    pub environment: bool,
+    pub environment_allowlist: Vec<String>,
+    /// Arguments from `/proc/self/cmdline`
+    pub command_line: bool,
+    pub working_directory: bool,
+    /// Names from `/proc/self/task/*/comm`
+    pub thread_names: bool,
+}
+
+impl Default for ContextCollectors {
+    fn default() -> Self {
+        Self {
+            open_files: true,
+            rlimits: true,
+            rusage: true,
+            cgroup_memory: true,
+            environment: true,
+            environment_allowlist: DEFAULT_ENVIRONMENT_ALLOWLIST.iter().map(|s| s.to_string()).collect(),
+            command_line: true,
+            working_directory: true,
+            thread_names: true,
+        }
+    }
+}
+
+impl ContextCollectors {
+    /// Capture nothing
+    pub fn none() -> Self {
+        Self {
+            open_files: false,
+            rlimits: false,
+            rusage: false,
+            cgroup_memory: false,
+            environment: false,
+            environment_allowlist: Vec::new(),
+            command_line: false,
+            working_directory: false,
+            thread_names: false,
+        }
+    }
+}
+
+/// Run the enabled collectors against `pid` (`None` for this process) and
+/// return one `(stream type, JSON)` pair per collector that succeeded
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn collect(pid: Option<u32>, collectors: &ContextCollectors) -> Vec<(u32, Vec<u8>)> {
+    let proc = match pid {
+        Some(pid) => format!("/proc/{}", pid),
+        None => "/proc/self".to_string(),
+    };
+
+    let mut streams = Vec::new();
+    let mut add = |kind: u32, value: Option<serde_json::Value>| {
+        if let Some(json) = value.and_then(|v| serde_json::to_vec(&v).ok()) {
+            streams.push((kind, json));
+        }
+    };
+
+    if collectors.open_files {
+        add(stream_type::OPEN_FILES, linux::open_files(&proc));
+    }
+    if collectors.rlimits {
+        add(stream_type::RLIMITS, linux::rlimits(pid));
+    }
+    if collectors.rusage {
+        add(
+            stream_type::RUSAGE,
+            if pid.is_some() { linux::rusage_of(&proc) } else { linux::rusage() },
+        );
+    }
+    if collectors.cgroup_memory {
+        add(stream_type::CGROUP_MEMORY, linux::cgroup_memory(&proc));
+    }
+    if collectors.environment {
+        add(
+            stream_type::ENVIRONMENT,
+            linux::environment(&proc, &collectors.environment_allowlist),
+        );
+    }
+    if collectors.command_line {
+        add(stream_type::COMMAND_LINE, linux::command_line(&proc));
+    }
+    if collectors.working_directory {
+        add(stream_type::WORKING_DIRECTORY, linux::working_directory(&proc));
+    }
+    if collectors.thread_names {
+        add(stream_type::THREAD_NAMES, linux::thread_names(&proc));
+    }
+    streams
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn collect(_pid: Option<u32>, _collectors: &ContextCollectors) -> Vec<(u32, Vec<u8>)> {
+    Vec::new()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod linux {
+    use serde_json::{json, Map, Value};
+    use std::fs;
+
+    pub(super) fn open_files(proc: &str) -> Option<Value> {
+        let mut files: Vec<(u64, String)> = fs::read_dir(format!("{}/fd", proc))
+            .ok()?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let fd = entry.file_name().to_str()?.parse().ok()?;
+                let target = fs::read_link(entry.path()).ok()?;
+                Some((fd, target.to_string_lossy().into_owned()))
+            })
+            .collect();
+        files.sort();
+        Some(Value::Array(
+            files
+                .into_iter()
+                .map(|(fd, target)| json!({ "fd": fd, "target": target }))
+                .collect(),
+        ))
+    }
+
+    pub(super) fn rlimits(pid: Option<u32>) -> Option<Value> {
+        let resources = [
+            ("cpu", libc::RLIMIT_CPU),
+            ("fsize", libc::RLIMIT_FSIZE),
+            ("data", libc::RLIMIT_DATA),
+            ("stack", libc::RLIMIT_STACK),
+            ("core", libc::RLIMIT_CORE),
+            ("rss", libc::RLIMIT_RSS),
+            ("nproc", libc::RLIMIT_NPROC),
+            ("nofile", libc::RLIMIT_NOFILE),
+            ("memlock", libc::RLIMIT_MEMLOCK),
+            ("as", libc::RLIMIT_AS),
+        ];
+        // `None` means unlimited
+        let limit = |value: libc::rlim_t| (value != libc::RLIM_INFINITY).then_some(value);
+
+        let mut limits = Map::new();
+        for (name, resource) in resources {
+            let mut current: libc::rlimit = unsafe { std::mem::zeroed() };
+            let r = unsafe {
+                libc::prlimit(
+                    pid.unwrap_or(0) as libc::pid_t,
+                    resource,
+                    std::ptr::null(),
+                    &mut current,
+                )
+            };
+            if r == 0 {
+                limits.insert(
+                    name.to_string(),
+                    json!({ "soft": limit(current.rlim_cur), "hard": limit(current.rlim_max) }),
+                );
+            }
+        }
+        (!limits.is_empty()).then_some(Value::Object(limits))
+    }
+
+    pub(super) fn rusage() -> Option<Value> {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+            return None;
+        }
+        // `time_t`/`suseconds_t` are 32-bit on some targets
+        #[allow(clippy::unnecessary_cast)]
+        let micros = |tv: libc::timeval| tv.tv_sec as i64 * 1_000_000 + tv.tv_usec as i64;
+        Some(json!({
+            "user_time_us": micros(usage.ru_utime),
+            "system_time_us": micros(usage.ru_stime),
+            "max_rss_kb": usage.ru_maxrss,
+            "minor_faults": usage.ru_minflt,
+            "major_faults": usage.ru_majflt,
+            "voluntary_context_switches": usage.ru_nvcsw,
+            "involuntary_context_switches": usage.ru_nivcsw,
+        }))
+    }
+
+    /// `rusage` of another process, from `stat` and `status`
+    pub(super) fn rusage_of(proc: &str) -> Option<Value> {
+        let stat = fs::read_to_string(format!("{}/stat", proc)).ok()?;
+        // Fields after the command name, which may contain spaces; the first
+        // one is field 3, the state
+        let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+        let field = |n: usize| fields.get(n - 3)?.parse::<u64>().ok();
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+
+        let status = fs::read_to_string(format!("{}/status", proc)).ok()?;
+        let status_value = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+        };
+        Some(json!({
+            "user_time_us": field(14)? * 1_000_000 / ticks,
+            "system_time_us": field(15)? * 1_000_000 / ticks,
+            "max_rss_kb": status_value("VmHWM"),
+            "minor_faults": field(10)?,
+            "major_faults": field(12)?,
+            "voluntary_context_switches": status_value("voluntary_ctxt_switches"),
+            "involuntary_context_switches": status_value("nonvoluntary_ctxt_switches"),
+        }))
+    }
+
+    /// Memory files of the process's cgroup. Values are kept as the kernel
+    /// writes them (`max` means unlimited on v2).
+    pub(super) fn cgroup_memory(proc: &str) -> Option<Value> {
+        let cgroups = fs::read_to_string(format!("{}/cgroup", proc)).ok()?;
+        let read = |path: String| fs::read_to_string(path).ok().map(|v| v.trim().to_string());
+
+        // v1: "4:memory:/path", v2: "0::/path"
+        let v1 = cgroups.lines().find_map(|line| {
+            let mut parts = line.splitn(3, ':');
+            let (_, controllers, path) = (parts.next()?, parts.next()?, parts.next()?);
+            controllers.split(',').any(|c| c == "memory").then(|| path.to_string())
+        });
+        if let Some(path) = v1 {
+            let dir = format!("/sys/fs/cgroup/memory{}", path);
+            return Some(json!({
+                "version": 1,
+                "cgroup": path,
+                "limit": read(format!("{}/memory.limit_in_bytes", dir)),
+                "usage": read(format!("{}/memory.usage_in_bytes", dir)),
+                "max_usage": read(format!("{}/memory.max_usage_in_bytes", dir)),
+            }));
+        }
+
+        let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?.to_string();
+        let dir = format!("/sys/fs/cgroup{}", path);
+        Some(json!({
+            "version": 2,
+            "cgroup": path,
+            "limit": read(format!("{}/memory.max", dir)),
+            "high": read(format!("{}/memory.high", dir)),
+            "usage": read(format!("{}/memory.current", dir)),
+            "swap_limit": read(format!("{}/memory.swap.max", dir)),
+        }))
+    }
+
+    pub(super) fn environment(proc: &str, allowlist: &[String]) -> Option<Value> {
+        let environ = fs::read(format!("{}/environ", proc)).ok()?;
+        let variables = environ
+            .split(|&b| b == 0)
+            .filter_map(|entry| {
+                let entry = String::from_utf8_lossy(entry);
+                let (name, value) = entry.split_once('=')?;
+                allowlist
+                    .iter()
+                    .any(|allowed| allowed == name)
+                    .then(|| (name.to_string(), Value::String(value.to_string())))
+            })
+            .collect::<Map<_, _>>();
+        Some(Value::Object(variables))
+    }
+
+    pub(super) fn command_line(proc: &str) -> Option<Value> {
+        let cmdline = fs::read(format!("{}/cmdline", proc)).ok()?;
+        Some(Value::Array(
+            cmdline
+                .split(|&b| b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| Value::String(String::from_utf8_lossy(arg).into_owned()))
+                .collect(),
+        ))
+    }
+
+    pub(super) fn working_directory(proc: &str) -> Option<Value> {
+        let cwd = fs::read_link(format!("{}/cwd", proc)).ok()?;
+        Some(Value::String(cwd.to_string_lossy().into_owned()))
+    }
+
+    pub(super) fn thread_names(proc: &str) -> Option<Value> {
+        let mut threads: Vec<(u64, String)> = fs::read_dir(format!("{}/task", proc))
+            .ok()?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let tid = entry.file_name().to_str()?.parse().ok()?;
+                let name = fs::read_to_string(entry.path().join("comm")).ok()?;
+                Some((tid, name.trim_end().to_string()))
+            })
+            .collect();
+        threads.sort();
+        Some(Value::Array(
+            threads
+                .into_iter()
+                .map(|(tid, name)| json!({ "tid": tid, "name": name }))
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collectors_toggle() {
+        let only_cwd = ContextCollectors {
+            working_directory: true,
+            ..ContextCollectors::none()
+        };
+        let streams = collect(None, &only_cwd);
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].0, stream_type::WORKING_DIRECTORY);
+
+        let cwd = std::env::current_dir().unwrap();
+        let json: String = serde_json::from_slice(&streams[0].1).unwrap();
+        assert_eq!(json, cwd.to_string_lossy());
+
+        let all = collect(None, &ContextCollectors::default());
+        assert!(all.iter().any(|(kind, _)| *kind == stream_type::THREAD_NAMES));
+    }
+}
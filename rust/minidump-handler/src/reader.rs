@@ -0,0 +1,579 @@
+//! Reading back the dumps this crate writes.
+//!
+//! Only the parts needed for reports and inspection are decoded: system info,
+//! exception, threads with their registers, modules and memory.
+
+use crate::streams::{self, DirectoryEntry, Header};
+use crate::Result;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Standard stream types
+pub mod standard_stream {
+    pub const THREAD_LIST: u32 = 3;
+    pub const MODULE_LIST: u32 = 4;
+    pub const MEMORY_LIST: u32 = 5;
+    pub const EXCEPTION: u32 = 6;
+    pub const SYSTEM_INFO: u32 = 7;
+    pub const MEMORY64_LIST: u32 = 9;
+}
+
+/// Name of a stream type, for the standard, Breakpad and this crate's streams
+pub fn stream_name(stream_type: u32) -> Option<&'static str> {
+    use streams::stream_type::*;
+
+    Some(match stream_type {
+        0 => "unused",
+        standard_stream::THREAD_LIST => "thread_list",
+        standard_stream::MODULE_LIST => "module_list",
+        standard_stream::MEMORY_LIST => "memory_list",
+        standard_stream::EXCEPTION => "exception",
+        standard_stream::SYSTEM_INFO => "system_info",
+        8 => "thread_ex_list",
+        standard_stream::MEMORY64_LIST => "memory64_list",
+        10 => "comment_a",
+        11 => "comment_w",
+        12 => "handle_data",
+        14 => "unloaded_module_list",
+        15 => "misc_info",
+        16 => "memory_info_list",
+        17 => "thread_info_list",
+        21 => "system_memory_info",
+        22 => "process_vm_counters",
+        24 => "thread_names",
+        0x4767_0001 => "breakpad_info",
+        0x4767_0002 => "assertion_info",
+        0x4767_0003 => "linux_cpu_info",
+        0x4767_0004 => "linux_proc_status",
+        0x4767_0005 => "linux_lsb_release",
+        0x4767_0006 => "linux_cmd_line",
+        0x4767_0007 => "linux_environ",
+        0x4767_0008 => "linux_auxv",
+        0x4767_0009 => "linux_maps",
+        0x4767_000a => "linux_dso_debug",
+        CRASH_ID => "crash_id",
+        ADDITIONAL_CRASHES => "additional_crashes",
+        ANNOTATIONS => "annotations",
+        OPEN_FILES => "open_files",
+        RLIMITS => "rlimits",
+        RUSAGE => "rusage",
+        CGROUP_MEMORY => "cgroup_memory",
+        ENVIRONMENT => "environment",
+        COMMAND_LINE => "command_line",
+        WORKING_DIRECTORY => "working_directory",
+        THREAD_NAMES => "handler_thread_names",
+        LOG_RING => "log_ring",
+        LOG_FILE_TAIL => "log_file_tail",
+        TRACING_CONTEXT => "tracing_context",
+        APP_MEMORY_NAMES => "app_memory_names",
+        MEMORY_TRUNCATION => "memory_truncation",
+        _ => return None,
+    })
+}
+
+/// Name of a Mach exception type (`EXC_*`)
+pub fn mach_exception_name(kind: u32) -> &'static str {
+    match kind {
+        1 => "EXC_BAD_ACCESS",
+        2 => "EXC_BAD_INSTRUCTION",
+        3 => "EXC_ARITHMETIC",
+        4 => "EXC_EMULATION",
+        5 => "EXC_SOFTWARE",
+        6 => "EXC_BREAKPOINT",
+        10 => "EXC_CRASH",
+        11 => "EXC_RESOURCE",
+        12 => "EXC_GUARD",
+        _ => "UNKNOWN_EXCEPTION",
+    }
+}
+
+const THREAD_SIZE: usize = 48;
+const MODULE_SIZE: usize = 108;
+const MEMORY_DESCRIPTOR_SIZE: usize = 16;
+
+/// Low half of `MINIDUMP_HEADER::Version`
+const MINIDUMP_VERSION: u32 = 0xa793;
+
+/// CodeView signature of an ELF build id record (`BpEL`)
+const CV_SIGNATURE_ELF: u32 = 0x4270_454c;
+/// CodeView signature of a PDB 7.0 record (`RSDS`), used for Mach-O UUIDs
+const CV_SIGNATURE_RSDS: u32 = 0x5344_5352;
+
+/// Processor architecture from the system info stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cpu {
+    X86_64,
+    Arm64,
+    Other(u16),
+}
+
+impl Cpu {
+    fn from_raw(raw: u16) -> Self {
+        match raw {
+            9 => Self::X86_64,
+            // 0x8003 is Breakpad's pre-standard ARM64 value
+            12 | 0x8003 => Self::Arm64,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "amd64",
+            Self::Arm64 => "arm64",
+            Self::Other(_) => "unknown",
+        }
+    }
+}
+
+/// Operating system from the system info stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Os {
+    Linux,
+    Android,
+    MacOs,
+    Ios,
+    Other(u32),
+}
+
+impl Os {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            0x8201 => Self::Linux,
+            0x8203 => Self::Android,
+            0x8101 => Self::MacOs,
+            0x8102 => Self::Ios,
+            other => Self::Other(other),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Linux => "Linux",
+            Self::Android => "Android",
+            Self::MacOs => "macOS",
+            Self::Ios => "iOS",
+            Self::Other(_) => "unknown",
+        }
+    }
+
+    /// Whether exceptions are recorded as POSIX signal and `si_code`
+    pub fn uses_signals(&self) -> bool {
+        matches!(self, Self::Linux | Self::Android)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SystemInfo {
+    pub cpu: Cpu,
+    pub cpu_count: u8,
+    pub os: Os,
+    pub os_version: (u32, u32, u32),
+}
+
+/// Where a blob lives in the dump
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Location {
+    pub data_size: u32,
+    pub rva: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Exception {
+    pub thread_id: u32,
+    /// Signal number on Linux, `EXC_*` kind on Apple platforms
+    pub code: u32,
+    /// `si_code` on Linux, the Mach exception code on Apple platforms
+    pub flags: u32,
+    pub address: u64,
+    pub context: Location,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Thread {
+    pub thread_id: u32,
+    pub stack_start: u64,
+    pub stack: Location,
+    pub context: Location,
+}
+
+impl Thread {
+    /// End address of the captured stack
+    pub fn stack_end(&self) -> Result<u64> {
+        self.stack_start
+            .checked_add(self.stack.data_size as u64)
+            .ok_or_else(|| streams::invalid_data("thread stack beyond the address space"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub base: u64,
+    pub size: u32,
+    pub name: String,
+    /// Hex build id (ELF) or UUID (Mach-O), when the dump records one
+    pub build_id: Option<String>,
+}
+
+impl Module {
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.base && address - self.base < self.size as u64
+    }
+
+    /// File name without its directory
+    pub fn file_name(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&self.name)
+    }
+
+    /// Breakpad debug id, which symbol files are stored under
+    pub fn debug_id(&self) -> Option<String> {
+        let hex = self.build_id.as_deref()?;
+        let bytes: Vec<u8> = (0..hex.len() / 2)
+            .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16))
+            .collect::<std::result::Result<_, _>>()
+            .ok()?;
+        Some(breakpad_debug_id(&bytes))
+    }
+}
+
+/// Breakpad debug id of an ELF build id or Mach-O UUID: the first 16 bytes
+/// read as a GUID with little-endian fields, then an age of 0
+pub fn breakpad_debug_id(build_id: &[u8]) -> String {
+    let mut guid = [0u8; 16];
+    let len = build_id.len().min(16);
+    guid[..len].copy_from_slice(&build_id[..len]);
+    guid[..4].reverse();
+    guid[4..6].reverse();
+    guid[6..8].reverse();
+    let mut id: String = guid.iter().map(|b| format!("{:02X}", b)).collect();
+    id.push('0');
+    id
+}
+
+/// A block of captured memory
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub size: u64,
+    /// Offset of the bytes in the dump
+    pub rva: u64,
+}
+
+/// Registers of one thread
+#[derive(Debug, Clone)]
+pub struct Registers {
+    pub pc: u64,
+    pub sp: u64,
+    pub fp: u64,
+    /// All general-purpose registers in the architecture's usual order
+    pub named: Vec<(&'static str, u64)>,
+}
+
+const AMD64_REGISTERS: [(&str, usize); 17] = [
+    ("rax", 120),
+    ("rbx", 144),
+    ("rcx", 128),
+    ("rdx", 136),
+    ("rsi", 168),
+    ("rdi", 176),
+    ("rbp", 160),
+    ("rsp", 152),
+    ("r8", 184),
+    ("r9", 192),
+    ("r10", 200),
+    ("r11", 208),
+    ("r12", 216),
+    ("r13", 224),
+    ("r14", 232),
+    ("r15", 240),
+    ("rip", 248),
+];
+
+const ARM64_REGISTER_NAMES: [&str; 29] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28",
+];
+
+/// A minidump loaded into memory
+pub struct Minidump {
+    data: Vec<u8>,
+    pub header: Header,
+    pub directory: Vec<DirectoryEntry>,
+}
+
+impl Minidump {
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::from_bytes(std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let header = streams::read_header(&mut cursor)?;
+        let directory = streams::read_directory(&mut cursor, &header)?;
+        let dump = Self {
+            data: cursor.into_inner(),
+            header,
+            directory,
+        };
+        dump.memory64_regions()?;
+        Ok(dump)
+    }
+
+    /// The raw dump
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Data of the first stream of type `stream_type`
+    pub fn stream(&self, stream_type: u32) -> Option<&[u8]> {
+        let entry = self.directory.iter().find(|e| e.stream_type == stream_type)?;
+        self.slice(entry.rva as u64, entry.data_size as u64)
+    }
+
+    /// Bytes at `location`
+    pub fn location(&self, location: Location) -> Option<&[u8]> {
+        self.slice(location.rva as u64, location.data_size as u64)
+    }
+
+    fn slice(&self, offset: u64, len: u64) -> Option<&[u8]> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(usize::try_from(len).ok()?)?;
+        self.data.get(start..end)
+    }
+
+    /// The crash id embedded by this crate
+    pub fn crash_id(&self) -> Option<&str> {
+        std::str::from_utf8(self.stream(streams::stream_type::CRASH_ID)?).ok()
+    }
+
+    pub fn system_info(&self) -> Option<SystemInfo> {
+        let raw = self.stream(standard_stream::SYSTEM_INFO)?;
+        Some(SystemInfo {
+            cpu: Cpu::from_raw(u16_at(raw, 0)?),
+            cpu_count: *raw.get(6)?,
+            os_version: (u32_at(raw, 8)?, u32_at(raw, 12)?, u32_at(raw, 16)?),
+            os: Os::from_raw(u32_at(raw, 20)?),
+        })
+    }
+
+    pub fn exception(&self) -> Option<Exception> {
+        let raw = self.stream(standard_stream::EXCEPTION)?;
+        Some(Exception {
+            thread_id: u32_at(raw, 0)?,
+            code: u32_at(raw, 8)?,
+            flags: u32_at(raw, 12)?,
+            address: u64_at(raw, 24)?,
+            context: location_at(raw, 160)?,
+        })
+    }
+
+    pub fn threads(&self) -> Vec<Thread> {
+        self.list(standard_stream::THREAD_LIST, THREAD_SIZE)
+            .filter_map(|raw| {
+                Some(Thread {
+                    thread_id: u32_at(raw, 0)?,
+                    stack_start: u64_at(raw, 24)?,
+                    stack: location_at(raw, 32)?,
+                    context: location_at(raw, 40)?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn modules(&self) -> Vec<Module> {
+        self.list(standard_stream::MODULE_LIST, MODULE_SIZE)
+            .filter_map(|raw| {
+                let name_rva = u32_at(raw, 20)?;
+                let cv_record = location_at(raw, 76)?;
+                Some(Module {
+                    base: u64_at(raw, 0)?,
+                    size: u32_at(raw, 8)?,
+                    name: self.string_at(name_rva).unwrap_or_default(),
+                    build_id: self.location(cv_record).and_then(build_id),
+                })
+            })
+            .collect()
+    }
+
+    pub fn memory_regions(&self) -> Vec<MemoryRegion> {
+        let mut regions: Vec<MemoryRegion> = self
+            .list(standard_stream::MEMORY_LIST, MEMORY_DESCRIPTOR_SIZE)
+            .filter_map(|raw| {
+                let location = location_at(raw, 8)?;
+                Some(MemoryRegion {
+                    start: u64_at(raw, 0)?,
+                    size: location.data_size as u64,
+                    rva: location.rva as u64,
+                })
+            })
+            .collect();
+
+        // `from_bytes` rejected a list whose RVAs overflow
+        regions.extend(self.memory64_regions().unwrap_or_default());
+        regions
+    }
+
+    /// Regions of a full-memory dump, kept back to back after one base RVA
+    fn memory64_regions(&self) -> Result<Vec<MemoryRegion>> {
+        let mut regions = Vec::new();
+        if let Some(raw) = self.stream(standard_stream::MEMORY64_LIST) {
+            let count = u64_at(raw, 0).unwrap_or(0) as usize;
+            let mut rva = u64_at(raw, 8).unwrap_or(0);
+            for i in 0..count {
+                let (Some(start), Some(size)) = (u64_at(raw, 16 + i * 16), u64_at(raw, 24 + i * 16)) else {
+                    break;
+                };
+                regions.push(MemoryRegion { start, size, rva });
+                rva = rva
+                    .checked_add(size)
+                    .ok_or_else(|| streams::invalid_data("memory64 list beyond the end of the address space"))?;
+            }
+        }
+        Ok(regions)
+    }
+
+    /// Captured memory starting at `address`, up to the end of its region
+    pub fn memory_at(&self, address: u64) -> Option<&[u8]> {
+        self.memory_regions().into_iter().find_map(|region| {
+            let offset = address.checked_sub(region.start)?;
+            (offset < region.size).then(|| self.slice(region.rva + offset, region.size - offset))?
+        })
+    }
+
+    /// Inconsistencies a truncated or corrupted dump shows: a wrong version,
+    /// streams, lists or the memory they point to reaching past the end of
+    /// the file. Empty for a well-formed dump.
+    pub fn structural_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.header.version & 0xffff != MINIDUMP_VERSION {
+            problems.push(format!("unexpected version {:#x}", self.header.version));
+        }
+        for entry in &self.directory {
+            if entry.stream_type != 0 && self.slice(entry.rva as u64, entry.data_size as u64).is_none() {
+                problems.push(format!(
+                    "stream {:#x} at {:#x}+{:#x} is past the end of the file",
+                    entry.stream_type, entry.rva, entry.data_size
+                ));
+            }
+        }
+
+        let lists = [
+            ("thread", standard_stream::THREAD_LIST, THREAD_SIZE),
+            ("module", standard_stream::MODULE_LIST, MODULE_SIZE),
+            ("memory", standard_stream::MEMORY_LIST, MEMORY_DESCRIPTOR_SIZE),
+        ];
+        for (name, stream_type, entry_size) in lists {
+            let Some(raw) = self.stream(stream_type) else {
+                continue;
+            };
+            let count = u32_at(raw, 0).unwrap_or(0) as usize;
+            if raw.len() < 4 + count * entry_size {
+                problems.push(format!("{} list claims {} entries but holds fewer", name, count));
+            }
+        }
+
+        for thread in self.threads() {
+            for (what, location) in [("stack", thread.stack), ("context", thread.context)] {
+                if self.location(location).is_none() {
+                    problems.push(format!("thread {} {} is past the end of the file", thread.thread_id, what));
+                }
+            }
+        }
+        if let Some(exception) = self.exception() {
+            if self.location(exception.context).is_none() {
+                problems.push("exception context is past the end of the file".to_string());
+            }
+        }
+        for region in self.memory_regions() {
+            if self.slice(region.rva, region.size).is_none() {
+                problems.push(format!(
+                    "memory {:#x}+{:#x} is past the end of the file",
+                    region.start, region.size
+                ));
+            }
+        }
+        problems
+    }
+
+    /// Decode a thread context for the dump's CPU
+    pub fn registers(&self, context: Location) -> Option<Registers> {
+        let raw = self.location(context)?;
+        match self.system_info()?.cpu {
+            Cpu::X86_64 => {
+                let named = AMD64_REGISTERS
+                    .iter()
+                    .map(|&(name, offset)| Some((name, u64_at(raw, offset)?)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Registers {
+                    pc: u64_at(raw, 248)?,
+                    sp: u64_at(raw, 152)?,
+                    fp: u64_at(raw, 160)?,
+                    named,
+                })
+            }
+            // Both ARM64 layouts keep x0..x30, sp and pc at the same offsets
+            Cpu::Arm64 => {
+                let mut named = ARM64_REGISTER_NAMES
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &name)| Some((name, u64_at(raw, 8 + i * 8)?)))
+                    .collect::<Option<Vec<_>>>()?;
+                let (fp, lr, sp, pc) = (u64_at(raw, 240)?, u64_at(raw, 248)?, u64_at(raw, 256)?, u64_at(raw, 264)?);
+                named.extend([("fp", fp), ("lr", lr), ("sp", sp), ("pc", pc)]);
+                Some(Registers { pc, sp, fp, named })
+            }
+            Cpu::Other(_) => None,
+        }
+    }
+
+    /// Entries of a list stream that starts with a `u32` count
+    fn list(&self, stream_type: u32, entry_size: usize) -> impl Iterator<Item = &[u8]> {
+        let raw = self.stream(stream_type).unwrap_or(&[]);
+        let count = u32_at(raw, 0).unwrap_or(0) as usize;
+        raw.get(4..)
+            .unwrap_or(&[])
+            .chunks_exact(entry_size)
+            .take(count)
+    }
+
+    /// `MINIDUMP_STRING`: byte length, then UTF-16LE
+    fn string_at(&self, rva: u32) -> Option<String> {
+        let len = u32_at(&self.data, rva as usize)?;
+        let raw = self.slice(rva as u64 + 4, len as u64)?;
+        let units: Vec<u16> = raw.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        Some(String::from_utf16_lossy(&units))
+    }
+}
+
+/// Build id or UUID from a CodeView record
+fn build_id(record: &[u8]) -> Option<String> {
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    match u32_at(record, 0)? {
+        CV_SIGNATURE_ELF if record.len() > 4 => Some(hex(&record[4..])),
+        CV_SIGNATURE_RSDS => Some(hex(record.get(4..20)?)),
+        _ => None,
+    }
+}
+
+fn u16_at(raw: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(raw.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(raw: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(raw.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn u64_at(raw: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(raw.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+fn location_at(raw: &[u8], offset: usize) -> Option<Location> {
+    Some(Location {
+        data_size: u32_at(raw, offset)?,
+        rva: u32_at(raw, offset + 4)?,
+    })
+}
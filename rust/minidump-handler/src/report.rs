@@ -0,0 +1,189 @@
+//! Human-readable `<dump>.txt` report, generated from the dump itself.
+
+use crate::reader::{mach_exception_name, Minidump, Module};
+use crate::{streams, CpuContext, Result, SignalInfo};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Words of the crashing thread's stack examined by the stack scan
+const STACK_SCAN_WORDS: usize = 2048;
+/// Candidate return addresses listed at most
+const STACK_SCAN_MAX_ENTRIES: usize = 48;
+
+/// Path of the text report belonging to `dump_path`
+pub fn text_report_path(dump_path: &Path) -> PathBuf {
+    let mut path = dump_path.as_os_str().to_owned();
+    path.push(".txt");
+    PathBuf::from(path)
+}
+
+/// Read `dump_path` and write its report next to it
+pub fn write_text_report(dump_path: &Path) -> Result<PathBuf> {
+    let dump = Minidump::read(dump_path)?;
+    let path = text_report_path(dump_path);
+    crate::dump_directory::write_file(&path, text_report(&dump, &title(dump_path)))?;
+    Ok(path)
+}
+
+/// Report title for the dump at `dump_path`: its file name
+pub(crate) fn title(dump_path: &Path) -> String {
+    dump_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Render the report: crash, crashing thread registers, an unsymbolicated
+/// stack scan and the loaded modules
+pub fn text_report(dump: &Minidump, title: &str) -> String {
+    let mut out = String::new();
+    let modules = dump.modules();
+    let system = dump.system_info();
+
+    let _ = writeln!(out, "Crash report: {}", title);
+    if let Some(crash_id) = dump.crash_id() {
+        let _ = writeln!(out, "Crash ID:     {}", crash_id);
+    }
+    let _ = writeln!(out, "Time:         {} (unix)", dump.header.time_date_stamp);
+    if let Some(system) = system {
+        let (major, minor, build) = system.os_version;
+        let _ = writeln!(
+            out,
+            "System:       {} {}.{}.{} ({}, {} CPUs)",
+            system.os.as_str(),
+            major,
+            minor,
+            build,
+            system.cpu.as_str(),
+            system.cpu_count
+        );
+    }
+
+    let Some(exception) = dump.exception() else {
+        let _ = writeln!(out, "\nNo exception recorded (dump written without a crash)");
+        write_modules(&mut out, &modules);
+        return out;
+    };
+    let registers = dump.registers(exception.context).or_else(|| {
+        let thread = dump.threads().into_iter().find(|t| t.thread_id == exception.thread_id)?;
+        dump.registers(thread.context)
+    });
+
+    let _ = writeln!(out);
+    match system.map(|s| s.os) {
+        Some(os) if os.uses_signals() => {
+            let signal_info = SignalInfo {
+                signal: exception.code as i32,
+                code: exception.flags as i32,
+                address: exception.address as usize,
+                tid: exception.thread_id as u64,
+                context: registers
+                    .as_ref()
+                    .map(|r| CpuContext {
+                        pc: r.pc as usize,
+                        sp: r.sp as usize,
+                        fp: r.fp as usize,
+                        access: None,
+                    })
+                    .unwrap_or_default(),
+            };
+            let _ = writeln!(out, "Crash:        {}", signal_info);
+        }
+        _ => {
+            let _ = writeln!(
+                out,
+                "Crash:        {} (code {:#x}) at {:#x}",
+                mach_exception_name(exception.code),
+                exception.flags,
+                exception.address
+            );
+        }
+    }
+    let _ = writeln!(out, "Thread:       {}", exception.thread_id);
+
+    if let Some(registers) = &registers {
+        let _ = writeln!(out, "\nRegisters:");
+        for row in registers.named.chunks(3) {
+            let line: Vec<String> = row.iter().map(|(name, value)| format!("{:>4} {:#018x}", name, value)).collect();
+            let _ = writeln!(out, "  {}", line.join("  "));
+        }
+
+        let _ = writeln!(out, "\nStack scan (thread {}, unsymbolicated):", exception.thread_id);
+        let _ = writeln!(out, "  pc         {:#018x}  {}", registers.pc, locate(&modules, registers.pc));
+        for (offset, value) in stack_scan(dump, registers.sp, &modules) {
+            let _ = writeln!(out, "  sp+{:<#7x} {:#018x}  {}", offset, value, locate(&modules, value));
+        }
+    }
+
+    if let Some(json) = dump.stream(streams::stream_type::ADDITIONAL_CRASHES) {
+        let _ = writeln!(out, "\nOther crashing threads: {}", String::from_utf8_lossy(json));
+    }
+    if let Some(json) = dump.stream(streams::stream_type::ANNOTATIONS) {
+        let _ = writeln!(out, "\nAnnotations: {}", String::from_utf8_lossy(json));
+    }
+
+    write_modules(&mut out, &modules);
+    out
+}
+
+/// Words on the stack that point into a module, as (offset from sp, value)
+fn stack_scan(dump: &Minidump, sp: u64, modules: &[Module]) -> Vec<(u64, u64)> {
+    let Some(stack) = dump.memory_at(sp) else {
+        return Vec::new();
+    };
+    stack
+        .chunks_exact(8)
+        .take(STACK_SCAN_WORDS)
+        .enumerate()
+        .map(|(i, word)| (i as u64 * 8, u64::from_le_bytes(word.try_into().unwrap())))
+        .filter(|&(_, value)| modules.iter().any(|m| m.contains(value)))
+        .take(STACK_SCAN_MAX_ENTRIES)
+        .collect()
+}
+
+fn locate(modules: &[Module], address: u64) -> String {
+    match modules.iter().find(|m| m.contains(address)) {
+        Some(module) => format!("{} + {:#x}", module.file_name(), address - module.base),
+        None => "???".to_string(),
+    }
+}
+
+fn write_modules(out: &mut String, modules: &[Module]) {
+    let _ = writeln!(out, "\nModules ({}):", modules.len());
+    for module in modules {
+        let _ = writeln!(
+            out,
+            "  {:#018x}-{:#018x}  {}  {}",
+            module.base,
+            module.base + module.size as u64,
+            module.file_name(),
+            module.build_id.as_deref().unwrap_or("(no build id)")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::standard_stream;
+    use crate::streams::testing::TestDump;
+
+    #[test]
+    fn test_report_from_dump() {
+        let mut exception = vec![0u8; 168];
+        exception[0..4].copy_from_slice(&42u32.to_le_bytes());
+        exception[8..12].copy_from_slice(&(libc::SIGSEGV as u32).to_le_bytes());
+        exception[12..16].copy_from_slice(&1u32.to_le_bytes()); // SEGV_MAPERR
+        exception[24..32].copy_from_slice(&0x10u64.to_le_bytes());
+        let dump = TestDump::new()
+            .amd64_linux()
+            .stream(standard_stream::EXCEPTION, exception)
+            .stream(streams::stream_type::CRASH_ID, &b"abc"[..])
+            .build();
+        let report = text_report(&dump, "test.dmp");
+        assert!(report.contains("Crash ID:     abc"));
+        assert!(report.contains("System:       Linux 0.0.0 (amd64, 0 CPUs)"));
+        assert!(report.contains("Crash:        SIGSEGV (SEGV_MAPERR): null pointer"));
+        assert!(report.contains("Thread:       42"));
+    }
+}
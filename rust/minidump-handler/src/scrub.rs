@@ -0,0 +1,321 @@
+//! Privacy scrubbing of finished dumps.
+//!
+//! Stack memory, the captured environment and annotations can hold tokens and
+//! personal data. The scrubber rewrites a dump in place: bytes are zeroed and
+//! filtered streams are shortened where they are, so every RVA stays valid
+//! and nothing that was removed is left behind as unreferenced bytes.
+
+use crate::process_context::DEFAULT_ENVIRONMENT_ALLOWLIST;
+use crate::reader::{standard_stream, Minidump};
+use crate::streams::{self, stream_type, DirectoryEntry, DIRECTORY_ENTRY_SIZE, MEMORY_DESCRIPTOR_SIZE};
+use crate::Result;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Stack bytes kept above the stack pointer by default
+pub const DEFAULT_SCRUB_STACK_BYTES: usize = 4096;
+
+/// `KEY=value` pairs separated by NUL, as written by minidump-writer on Linux
+const LINUX_ENVIRON: u32 = 0x4767_0007;
+
+/// What the scrubber removes
+#[derive(Debug, Clone)]
+pub struct ScrubOptions {
+    /// Stack bytes kept from the stack pointer upwards. Everything below the
+    /// stack pointer and beyond this distance is zeroed.
+    pub stack_bytes: usize,
+    /// Environment variables that are kept
+    pub environment_allowlist: Vec<String>,
+    /// Annotation keys that are kept
+    pub annotation_allowlist: Vec<String>,
+    /// Remove memory regions outside thread stacks and modules: registered
+    /// application memory, memory behind pointers, full-dump heap
+    pub remove_heap: bool,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> Self {
+        Self {
+            stack_bytes: DEFAULT_SCRUB_STACK_BYTES,
+            environment_allowlist: DEFAULT_ENVIRONMENT_ALLOWLIST.iter().map(|s| s.to_string()).collect(),
+            annotation_allowlist: Vec::new(),
+            remove_heap: true,
+        }
+    }
+}
+
+/// What a scrub changed
+#[derive(Debug, Clone, Default)]
+pub struct ScrubSummary {
+    pub stack_bytes_zeroed: u64,
+    pub regions_removed: usize,
+    pub environment_removed: usize,
+    pub annotations_removed: usize,
+}
+
+/// Scrub the dump at `input` and write the result to `output`
+pub fn scrub_dump(input: &Path, output: &Path, options: &ScrubOptions) -> Result<ScrubSummary> {
+    let (scrubbed, summary) = scrub(Minidump::read(input)?, options)?;
+    crate::dump_directory::write_file(output, scrubbed)?;
+    Ok(summary)
+}
+
+/// Scrub a loaded dump and return its bytes
+pub fn scrub(dump: Minidump, options: &ScrubOptions) -> Result<(Vec<u8>, ScrubSummary)> {
+    let mut summary = ScrubSummary::default();
+
+    // (start, end, sp) of each thread's stack; the exception context holds
+    // the crashing thread's registers at the time of the crash
+    let exception = dump.exception();
+    let threads = dump.threads();
+    let stacks: Vec<(u64, u64, Option<u64>)> = threads
+        .iter()
+        .map(|thread| {
+            let context = match &exception {
+                Some(e) if e.thread_id == thread.thread_id => e.context,
+                _ => thread.context,
+            };
+            let sp = dump.registers(context).map(|r| r.sp);
+            Ok((thread.stack_start, thread.stack_end()?, sp))
+        })
+        .collect::<Result<_>>()?;
+    let stack_of = |address: u64| stacks.iter().find(|&&(start, end, _)| start <= address && address < end);
+    let modules = dump.modules();
+
+    // (offset in the file, length) of bytes to zero
+    let mut zero: Vec<(u64, u64)> = Vec::new();
+    for (thread, &(start, end, sp)) in threads.iter().zip(&stacks) {
+        let ranges = stack_outside_window(start, end, sp, options.stack_bytes as u64);
+        summary.stack_bytes_zeroed += push_ranges(&mut zero, &ranges, start, thread.stack.rva as u64);
+    }
+
+    // Memory list: stack copies get the stack rule, heap is dropped
+    let mut memory_list = None;
+    if let Some(entry) = dump.directory.iter().find(|e| e.stream_type == standard_stream::MEMORY_LIST) {
+        let raw = dump.stream(standard_stream::MEMORY_LIST).unwrap_or_default();
+        let count = raw.get(0..4).map_or(0, |c| u32::from_le_bytes(c.try_into().unwrap()) as usize);
+        let mut kept = Vec::new();
+        for descriptor in raw.get(4..).unwrap_or_default().chunks_exact(MEMORY_DESCRIPTOR_SIZE).take(count) {
+            let start = u64::from_le_bytes(descriptor[0..8].try_into().unwrap());
+            let size = u32::from_le_bytes(descriptor[8..12].try_into().unwrap()) as u64;
+            let rva = u32::from_le_bytes(descriptor[12..16].try_into().unwrap()) as u64;
+            if let Some(&(_, _, sp)) = stack_of(start) {
+                let ranges = stack_outside_window(start, start + size, sp, options.stack_bytes as u64);
+                summary.stack_bytes_zeroed += push_ranges(&mut zero, &ranges, start, rva);
+                kept.extend_from_slice(descriptor);
+            } else if options.remove_heap && !modules.iter().any(|m| m.contains(start)) {
+                zero.push((rva, size));
+                summary.regions_removed += 1;
+            } else {
+                kept.extend_from_slice(descriptor);
+            }
+        }
+        let mut list = ((kept.len() / MEMORY_DESCRIPTOR_SIZE) as u32).to_le_bytes().to_vec();
+        list.extend_from_slice(&kept);
+        memory_list = Some((*entry, list));
+    }
+    let memory64 = dump
+        .directory
+        .iter()
+        .find(|e| e.stream_type == standard_stream::MEMORY64_LIST)
+        .copied();
+
+    let mut replacements: Vec<(DirectoryEntry, Option<Vec<u8>>)> = Vec::new();
+    if let Some((entry, list)) = memory_list {
+        replacements.push((entry, Some(list)));
+    }
+    if let (true, Some(entry)) = (options.remove_heap, memory64) {
+        // Full-memory ranges sit back to back after the list's base RVA
+        let raw = dump.stream(standard_stream::MEMORY64_LIST).unwrap_or_default();
+        let count = raw.get(0..8).map_or(0, |c| u64::from_le_bytes(c.try_into().unwrap()));
+        let base = raw.get(8..16).map_or(0, |c| u64::from_le_bytes(c.try_into().unwrap()));
+        let total: u64 = (0..count as usize)
+            .filter_map(|i| raw.get(24 + i * 16..32 + i * 16))
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .sum();
+        zero.push((base, total));
+        summary.regions_removed += count as usize;
+        replacements.push((entry, None));
+    }
+    for entry in &dump.directory {
+        match entry.stream_type {
+            stream_type::APP_MEMORY_NAMES if options.remove_heap => replacements.push((*entry, None)),
+            stream_type::ENVIRONMENT => {
+                let raw = dump.stream(entry.stream_type).unwrap_or_default();
+                let Ok(serde_json::Value::Object(mut variables)) = serde_json::from_slice(raw) else {
+                    replacements.push((*entry, None));
+                    continue;
+                };
+                let before = variables.len();
+                variables.retain(|name, _| options.environment_allowlist.contains(name));
+                summary.environment_removed += before - variables.len();
+                replacements.push((*entry, serde_json::to_vec(&variables).ok()));
+            }
+            stream_type::ANNOTATIONS => {
+                let raw = dump.stream(entry.stream_type).unwrap_or_default();
+                let Ok(serde_json::Value::Object(mut annotations)) = serde_json::from_slice(raw) else {
+                    replacements.push((*entry, None));
+                    continue;
+                };
+                let before = annotations.len();
+                annotations.retain(|key, _| options.annotation_allowlist.contains(key));
+                summary.annotations_removed += before - annotations.len();
+                replacements.push((*entry, serde_json::to_vec(&annotations).ok()));
+            }
+            LINUX_ENVIRON => {
+                let raw = dump.stream(entry.stream_type).unwrap_or_default();
+                let (kept, removed) = allowed_environ(raw, &options.environment_allowlist);
+                summary.environment_removed += removed;
+                replacements.push((*entry, Some(kept)));
+            }
+            _ => {}
+        }
+    }
+
+    let directory_rva = dump.header.stream_directory_rva as usize;
+    let mut directory = dump.directory.clone();
+    let mut data = dump.into_bytes();
+    for (offset, len) in zero {
+        if let Some(bytes) = data.get_mut(offset as usize..).and_then(|rest| rest.get_mut(..len as usize)) {
+            bytes.fill(0);
+        }
+    }
+
+    // Shorter data is written over the old, the rest zeroed. Removed streams
+    // stay in the directory as unused entries so its size doesn't change.
+    for (entry, replacement) in replacements {
+        let Some(old) = data
+            .get_mut(entry.rva as usize..)
+            .and_then(|rest| rest.get_mut(..entry.data_size as usize))
+        else {
+            continue;
+        };
+        let new = replacement.filter(|new| new.len() <= old.len());
+        let new_size = new.as_ref().map_or(0, |new| new.len());
+        old.fill(0);
+        if let Some(new) = &new {
+            old[..new.len()].copy_from_slice(new);
+        }
+        for listed in directory.iter_mut().filter(|e| e.rva == entry.rva && e.stream_type == entry.stream_type) {
+            if new.is_none() {
+                listed.stream_type = 0;
+                listed.rva = 0;
+            }
+            listed.data_size = streams::to_u32(new_size as u64)?;
+        }
+    }
+    let mut raw_directory = Vec::with_capacity(directory.len() * DIRECTORY_ENTRY_SIZE as usize);
+    streams::write_directory(&mut raw_directory, &directory)?;
+    data.get_mut(directory_rva..directory_rva + raw_directory.len())
+        .ok_or_else(|| streams::invalid_data("stream directory outside the dump"))?
+        .copy_from_slice(&raw_directory);
+
+    Ok((data, summary))
+}
+
+/// Filter the `LINUX_ENVIRON` stream of a freshly written dump to
+/// `allowlist`, in place like `scrub`. minidump-writer copies the whole
+/// environment; this keeps it in line with the environment collector.
+pub(crate) fn filter_linux_environ<F: Read + Write + Seek>(file: &mut F, allowlist: &[String]) -> Result<()> {
+    let header = streams::read_header(file)?;
+    let mut directory = streams::read_directory(file, &header)?;
+    let Some(entry) = directory.iter_mut().find(|e| e.stream_type == LINUX_ENVIRON) else {
+        return Ok(());
+    };
+    let mut raw = streams::read_stream(file, entry)?;
+    let (kept, _) = allowed_environ(&raw, allowlist);
+    raw.fill(0);
+    raw[..kept.len()].copy_from_slice(&kept);
+    file.seek(SeekFrom::Start(entry.rva as u64))?;
+    file.write_all(&raw)?;
+    entry.data_size = streams::to_u32(kept.len() as u64)?;
+
+    let mut raw_directory = Vec::with_capacity(directory.len() * DIRECTORY_ENTRY_SIZE as usize);
+    streams::write_directory(&mut raw_directory, &directory)?;
+    file.seek(SeekFrom::Start(header.stream_directory_rva as u64))?;
+    file.write_all(&raw_directory)?;
+    Ok(())
+}
+
+/// The NUL-separated `KEY=value` pairs of `raw` named in `allowlist`, and
+/// how many were left out
+fn allowed_environ(raw: &[u8], allowlist: &[String]) -> (Vec<u8>, usize) {
+    let mut kept = Vec::new();
+    let mut removed = 0;
+    for variable in raw.split(|&b| b == 0).filter(|v| !v.is_empty()) {
+        let name = variable.split(|&b| b == b'=').next().unwrap_or_default();
+        if allowlist.iter().any(|allowed| allowed.as_bytes() == name) {
+            kept.extend_from_slice(variable);
+            kept.push(0);
+        } else {
+            removed += 1;
+        }
+    }
+    (kept, removed)
+}
+
+/// Parts of the stack `[start, end)` outside `stack_bytes` above `sp`; all of
+/// it when the stack pointer is unknown
+fn stack_outside_window(start: u64, end: u64, sp: Option<u64>, stack_bytes: u64) -> [(u64, u64); 2] {
+    match sp {
+        Some(sp) => {
+            let keep_start = sp.clamp(start, end);
+            let keep_end = sp.saturating_add(stack_bytes).clamp(keep_start, end);
+            [(start, keep_start), (keep_end, end)]
+        }
+        None => [(start, end), (end, end)],
+    }
+}
+
+/// Queue the non-empty `ranges` of memory starting at `start` (stored at
+/// `rva`) for zeroing and return how many bytes that is
+fn push_ranges(zero: &mut Vec<(u64, u64)>, ranges: &[(u64, u64)], start: u64, rva: u64) -> u64 {
+    let mut total = 0;
+    for &(from, to) in ranges.iter().filter(|(from, to)| from < to) {
+        zero.push((rva + (from - start), to - from));
+        total += to - from;
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streams::testing::TestDump;
+
+    #[test]
+    fn test_filter_linux_environ() {
+        let mut dump = TestDump::new()
+            .stream(LINUX_ENVIRON, &b"LANG=C\0API_KEY=hunter2\0"[..])
+            .cursor();
+
+        filter_linux_environ(&mut dump, &["LANG".to_string()]).unwrap();
+        let raw = dump.into_inner();
+        assert!(!raw.windows(7).any(|w| w == b"hunter2"));
+        let dump = Minidump::from_bytes(raw).unwrap();
+        assert_eq!(dump.stream(LINUX_ENVIRON), Some(&b"LANG=C\0"[..]));
+    }
+
+    #[test]
+    fn test_scrub_removes_heap_and_environment() {
+        let dump = TestDump::new()
+            .stream(
+                standard_stream::MEMORY_LIST,
+                streams::memory_list_stream(&[(0x5000u64, b"secret-token")]).unwrap(),
+            )
+            .stream(stream_type::ENVIRONMENT, &br#"{"LANG":"C","API_KEY":"hunter2"}"#[..])
+            .stream(stream_type::ANNOTATIONS, &br#"{"user":"alice"}"#[..])
+            .build();
+        let (scrubbed, summary) = scrub(dump, &ScrubOptions::default()).unwrap();
+        assert_eq!(summary.regions_removed, 1);
+        assert_eq!(summary.environment_removed, 1);
+        assert_eq!(summary.annotations_removed, 1);
+
+        for secret in [&b"secret-token"[..], b"hunter2", b"alice"] {
+            assert!(!scrubbed.windows(secret.len()).any(|w| w == secret));
+        }
+        let dump = Minidump::from_bytes(scrubbed).unwrap();
+        assert!(dump.memory_regions().is_empty());
+        assert_eq!(dump.stream(stream_type::ENVIRONMENT), Some(&br#"{"LANG":"C"}"#[..]));
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{Result, SignalInfo, Uuid};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Metadata written next to each dump as `<dump>.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Sidecar {
+    /// Unique id of this dump, also embedded in the dump's crash id stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crash_id: Option<String>,
+    /// Signal name, e.g. `SIGSEGV`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<String>,
+    /// Raw signal number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal_number: Option<i32>,
+    /// Decoded `si_code`, e.g. `SEGV_MAPERR`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Fault address as hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Fault address classification, e.g. `null_page`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_class: Option<String>,
+    /// Human-readable cause, e.g. `null pointer read`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Annotations a client registered with the crash server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
+    /// Hex Ed25519 signature over the dump and the rest of this sidecar
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Hex Ed25519 public key `signature` was made with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+    /// Why less than the configured dump was written, e.g. low disk space
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub degraded: Option<String>,
+}
+
+impl Sidecar {
+    /// Sidecar for a dump written without a crash
+    pub fn new(crash_id: Uuid) -> Self {
+        Self {
+            crash_id: Some(crash_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Sidecar describing a crash caused by `signal_info`
+    pub fn for_signal(crash_id: Uuid, signal_info: &SignalInfo) -> Self {
+        Self {
+            crash_id: Some(crash_id.to_string()),
+            signal: Some(signal_info.signal_name().to_string()),
+            signal_number: Some(signal_info.signal),
+            code: Some(signal_info.code_name().to_string()),
+            address: signal_info
+                .address_class()
+                .map(|_| format!("{:#x}", signal_info.address)),
+            address_class: signal_info.address_class().map(|c| c.as_str().to_string()),
+            reason: Some(signal_info.reason()),
+            annotations: None,
+            signature: None,
+            signing_key: None,
+            degraded: None,
+        }
+    }
+
+    /// Path of the sidecar belonging to `dump_path`
+    pub fn path_for(dump_path: &Path) -> PathBuf {
+        let mut path = dump_path.as_os_str().to_owned();
+        path.push(".json");
+        PathBuf::from(path)
+    }
+
+    /// Write the sidecar next to `dump_path`
+    pub fn write(&self, dump_path: &Path) -> Result<()> {
+        crate::dump_directory::write_file(&Self::path_for(dump_path), self.to_json()?)?;
+        Ok(())
+    }
+
+    /// The sidecar as written to disk
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?)
+    }
+
+    /// Read the sidecar belonging to `dump_path`
+    pub fn read(dump_path: &Path) -> Result<Self> {
+        let json = std::fs::read(Self::path_for(dump_path))?;
+        Ok(serde_json::from_slice(&json).map_err(std::io::Error::from)?)
+    }
+}
@@ -0,0 +1,394 @@
+use crate::context::FaultAccess;
+use crate::{HandlerError, Result};
+use libc::{
+    c_int, SIGABRT, SIGBUS, SIGFPE, SIGILL, SIGSEGV, SIGSYS, SIGTRAP, SIGXCPU, SIGXFSZ,
+};
+
+/// What the crash handler does when a configured signal arrives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    /// Write a minidump, then re-raise the signal with its default disposition
+    DumpAndReraise,
+    /// Write a minidump and return to the interrupted code.
+    ///
+    /// Only for signals that are not caused by the faulting instruction
+    /// itself (e.g. `SIGXCPU`, `SIGXFSZ`, or a `SIGTRAP` sent by another process);
+    /// returning from a hardware `SIGSEGV` re-executes the fault, so
+    /// `init_crash_handler` rejects it for [`FAULT_SIGNALS`].
+    DumpAndContinue,
+    /// Leave the signal's existing disposition untouched
+    Ignore,
+}
+
+/// Signals handled when `HandlerConfig::signals` is left at its default
+pub const DEFAULT_SIGNALS: [(c_int, SignalAction); 6] = [
+    (SIGSEGV, SignalAction::DumpAndReraise),
+    (SIGBUS, SignalAction::DumpAndReraise),
+    (SIGABRT, SignalAction::DumpAndReraise),
+    (SIGFPE, SignalAction::DumpAndReraise),
+    (SIGILL, SignalAction::DumpAndReraise),
+    (SIGTRAP, SignalAction::DumpAndReraise),
+];
+
+/// Every signal `HandlerConfig::signals` may list
+pub const SUPPORTED_SIGNALS: [c_int; 9] = [
+    SIGSEGV, SIGBUS, SIGABRT, SIGFPE, SIGILL, SIGTRAP, SIGSYS, SIGXCPU, SIGXFSZ,
+];
+
+/// Signals raised by the faulting instruction itself; returning from their
+/// handler runs the instruction again and faults forever
+pub const FAULT_SIGNALS: [c_int; 4] = [SIGSEGV, SIGBUS, SIGILL, SIGFPE];
+
+/// Reject configured actions that can't work, before any handler is installed
+pub(crate) fn check_actions(signals: &[(c_int, SignalAction)]) -> Result<()> {
+    match signals
+        .iter()
+        .find(|&&(signal, action)| action == SignalAction::DumpAndContinue && FAULT_SIGNALS.contains(&signal))
+    {
+        Some(&(signal, _)) => Err(HandlerError::ContinueAfterFault { signal }),
+        None => Ok(()),
+    }
+}
+
+/// Name of a signal, e.g. `"SIGSEGV"`
+pub fn signal_name(signal: c_int) -> &'static str {
+    match signal {
+        SIGSEGV => "SIGSEGV",
+        SIGBUS => "SIGBUS",
+        SIGABRT => "SIGABRT",
+        SIGFPE => "SIGFPE",
+        SIGILL => "SIGILL",
+        SIGTRAP => "SIGTRAP",
+        SIGSYS => "SIGSYS",
+        SIGXCPU => "SIGXCPU",
+        SIGXFSZ => "SIGXFSZ",
+        _ => "UNKNOWN",
+    }
+}
+
+/// `si_code` values. Linux and Darwin number the FPE and `SI_*` codes
+/// differently and libc only exports some of them, so they are spelled out.
+mod si {
+    use libc::c_int;
+
+    pub const SEGV_MAPERR: c_int = 1;
+    pub const SEGV_ACCERR: c_int = 2;
+
+    pub const BUS_ADRALN: c_int = 1;
+    pub const BUS_ADRERR: c_int = 2;
+    pub const BUS_OBJERR: c_int = 3;
+
+    pub const ILL_ILLOPC: c_int = 1;
+    pub const ILL_ILLOPN: c_int = 2;
+    pub const ILL_ILLADR: c_int = 3;
+    pub const ILL_ILLTRP: c_int = 4;
+    pub const ILL_PRVOPC: c_int = 5;
+    pub const ILL_PRVREG: c_int = 6;
+    pub const ILL_COPROC: c_int = 7;
+    pub const ILL_BADSTK: c_int = 8;
+
+    pub const TRAP_BRKPT: c_int = 1;
+    pub const TRAP_TRACE: c_int = 2;
+
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    pub use self::linux::*;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    pub use self::darwin::*;
+
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    mod linux {
+        use libc::c_int;
+
+        pub const FPE_INTDIV: c_int = 1;
+        pub const FPE_INTOVF: c_int = 2;
+        pub const FPE_FLTDIV: c_int = 3;
+        pub const FPE_FLTOVF: c_int = 4;
+        pub const FPE_FLTUND: c_int = 5;
+        pub const FPE_FLTRES: c_int = 6;
+        pub const FPE_FLTINV: c_int = 7;
+        pub const FPE_FLTSUB: c_int = 8;
+
+        pub const SYS_SECCOMP: c_int = 1;
+
+        /// Name of a code that says who sent the signal rather than why
+        pub fn sender_code_name(code: c_int) -> Option<&'static str> {
+            Some(match code {
+                0 => "SI_USER",
+                0x80 => "SI_KERNEL",
+                -1 => "SI_QUEUE",
+                -2 => "SI_TIMER",
+                -3 => "SI_MESGQ",
+                -4 => "SI_ASYNCIO",
+                -5 => "SI_SIGIO",
+                -6 => "SI_TKILL",
+                _ => return None,
+            })
+        }
+
+        /// `SI_USER` and every negative code come from userspace
+        pub fn is_sent(code: c_int) -> bool {
+            code <= 0
+        }
+
+        /// A `SIGSEGV` raised by the kernel itself rather than by a page
+        /// fault: on x86_64 a general protection fault, whose `si_addr` is
+        /// always 0 and says nothing about the access
+        pub fn is_general_protection(signal: c_int, code: c_int) -> bool {
+            signal == libc::SIGSEGV && code == 0x80
+        }
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    mod darwin {
+        use libc::c_int;
+
+        pub const FPE_FLTDIV: c_int = 1;
+        pub const FPE_FLTOVF: c_int = 2;
+        pub const FPE_FLTUND: c_int = 3;
+        pub const FPE_FLTRES: c_int = 4;
+        pub const FPE_FLTINV: c_int = 5;
+        pub const FPE_FLTSUB: c_int = 6;
+        pub const FPE_INTDIV: c_int = 7;
+        pub const FPE_INTOVF: c_int = 8;
+
+        /// Name of a code that says who sent the signal rather than why
+        pub fn sender_code_name(code: c_int) -> Option<&'static str> {
+            Some(match code {
+                0x10001 => "SI_USER",
+                0x10002 => "SI_QUEUE",
+                0x10003 => "SI_TIMER",
+                0x10004 => "SI_ASYNCIO",
+                0x10005 => "SI_MESGQ",
+                _ => return None,
+            })
+        }
+
+        /// Darwin reports `kill`/`raise`/`abort` as `SI_USER` or `SI_QUEUE`
+        pub fn is_sent(code: c_int) -> bool {
+            code == 0x10001 || code == 0x10002
+        }
+
+        /// Darwin has no `SI_KERNEL`; protection faults come as `SEGV_*` or
+        /// `SIGBUS` with the real address
+        pub fn is_general_protection(_signal: c_int, _code: c_int) -> bool {
+            false
+        }
+    }
+}
+
+/// Name of the `si_code` for the given signal, e.g. `"SEGV_MAPERR"`
+pub fn code_name(signal: c_int, code: c_int) -> &'static str {
+    use si::*;
+
+    // Codes describing who sent the signal are shared by all signals
+    if let Some(name) = sender_code_name(code) {
+        return name;
+    }
+
+    match (signal, code) {
+        (SIGSEGV, SEGV_MAPERR) => "SEGV_MAPERR",
+        (SIGSEGV, SEGV_ACCERR) => "SEGV_ACCERR",
+        (SIGBUS, BUS_ADRALN) => "BUS_ADRALN",
+        (SIGBUS, BUS_ADRERR) => "BUS_ADRERR",
+        (SIGBUS, BUS_OBJERR) => "BUS_OBJERR",
+        (SIGILL, ILL_ILLOPC) => "ILL_ILLOPC",
+        (SIGILL, ILL_ILLOPN) => "ILL_ILLOPN",
+        (SIGILL, ILL_ILLADR) => "ILL_ILLADR",
+        (SIGILL, ILL_ILLTRP) => "ILL_ILLTRP",
+        (SIGILL, ILL_PRVOPC) => "ILL_PRVOPC",
+        (SIGILL, ILL_PRVREG) => "ILL_PRVREG",
+        (SIGILL, ILL_COPROC) => "ILL_COPROC",
+        (SIGILL, ILL_BADSTK) => "ILL_BADSTK",
+        (SIGFPE, FPE_INTDIV) => "FPE_INTDIV",
+        (SIGFPE, FPE_INTOVF) => "FPE_INTOVF",
+        (SIGFPE, FPE_FLTDIV) => "FPE_FLTDIV",
+        (SIGFPE, FPE_FLTOVF) => "FPE_FLTOVF",
+        (SIGFPE, FPE_FLTUND) => "FPE_FLTUND",
+        (SIGFPE, FPE_FLTRES) => "FPE_FLTRES",
+        (SIGFPE, FPE_FLTINV) => "FPE_FLTINV",
+        (SIGFPE, FPE_FLTSUB) => "FPE_FLTSUB",
+        (SIGTRAP, TRAP_BRKPT) => "TRAP_BRKPT",
+        (SIGTRAP, TRAP_TRACE) => "TRAP_TRACE",
+        #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+        (SIGSYS, SYS_SECCOMP) => "SYS_SECCOMP",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Whether the signal was sent by a process (`kill`, `raise`, `abort`, ...)
+/// rather than generated by a fault, in which case `si_addr` is meaningless.
+pub fn is_sent(code: c_int) -> bool {
+    si::is_sent(code)
+}
+
+/// Whether the signal is a general protection fault (`SIGSEGV` with
+/// `SI_KERNEL`), e.g. a non-canonical address on x86_64. The fault address
+/// isn't reported for these.
+pub fn is_general_protection(signal: c_int, code: c_int) -> bool {
+    si::is_general_protection(signal, code)
+}
+
+/// Short human-readable cause of a fault, without the address
+pub(crate) fn describe_fault(
+    signal: c_int,
+    code: c_int,
+    class: AddressClass,
+    access: Option<FaultAccess>,
+) -> String {
+    use si::*;
+
+    let access = access.map(|a| a.as_str()).unwrap_or("access");
+    match (signal, class) {
+        (SIGSEGV | SIGBUS, AddressClass::NearStackGuard) => "stack overflow".to_string(),
+        (SIGSEGV, AddressClass::NullPage) if access == "execute" => {
+            "null function pointer call".to_string()
+        }
+        (SIGSEGV, AddressClass::NullPage) => format!("null pointer {}", access),
+        (SIGSEGV, AddressClass::Kernel) => format!("{} of kernel address", access),
+        (SIGSEGV, AddressClass::NonCanonical) => {
+            format!("{} of non-canonical address (corrupted pointer?)", access)
+        }
+        (SIGSEGV, _) if code == SEGV_ACCERR => match access {
+            "write" => "write to read-only memory".to_string(),
+            "execute" => "execution of non-executable memory".to_string(),
+            _ => "access to protected memory".to_string(),
+        },
+        (SIGSEGV, _) => format!("{} of unmapped memory", access),
+        (SIGBUS, _) => match code {
+            BUS_ADRALN => "misaligned memory access".to_string(),
+            BUS_ADRERR => "access to nonexistent physical address".to_string(),
+            BUS_OBJERR => "object-specific hardware error (truncated mapped file?)".to_string(),
+            _ => "bus error".to_string(),
+        },
+        (SIGFPE, _) => match code {
+            FPE_INTDIV => "integer divide by zero",
+            FPE_INTOVF => "integer overflow",
+            FPE_FLTDIV => "floating-point divide by zero",
+            FPE_FLTOVF => "floating-point overflow",
+            FPE_FLTUND => "floating-point underflow",
+            FPE_FLTRES => "floating-point inexact result",
+            FPE_FLTINV => "invalid floating-point operation",
+            FPE_FLTSUB => "subscript out of range",
+            _ => "arithmetic exception",
+        }
+        .to_string(),
+        (SIGILL, _) => match code {
+            ILL_ILLOPC => "illegal opcode",
+            ILL_ILLOPN => "illegal operand",
+            ILL_ILLADR => "illegal addressing mode",
+            ILL_ILLTRP => "illegal trap",
+            ILL_PRVOPC => "privileged opcode",
+            ILL_PRVREG => "privileged register",
+            ILL_COPROC => "coprocessor error",
+            ILL_BADSTK => "internal stack error",
+            _ => "illegal instruction",
+        }
+        .to_string(),
+        (SIGTRAP, _) => match code {
+            TRAP_BRKPT => "breakpoint trap",
+            TRAP_TRACE => "trace trap",
+            _ => "trap",
+        }
+        .to_string(),
+        (SIGABRT, _) => "abort".to_string(),
+        (SIGSYS, _) => "bad system call".to_string(),
+        (SIGXCPU, _) => "CPU time limit exceeded".to_string(),
+        (SIGXFSZ, _) => "file size limit exceeded".to_string(),
+        _ => "unknown signal".to_string(),
+    }
+}
+
+/// Coarse classification of a fault address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressClass {
+    /// Within the first page: a null pointer, possibly plus a small offset
+    NullPage,
+    /// Just below the stack pointer: the thread ran into its stack guard
+    NearStackGuard,
+    /// In the kernel half of the address space
+    Kernel,
+    /// Not a valid virtual address for this CPU (usually a corrupted pointer)
+    NonCanonical,
+    /// An ordinary user-space address
+    User,
+}
+
+impl AddressClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NullPage => "null_page",
+            Self::NearStackGuard => "near_stack_guard",
+            Self::Kernel => "kernel",
+            Self::NonCanonical => "non_canonical",
+            Self::User => "user",
+        }
+    }
+}
+
+/// Size of the first page; anything below it is treated as a null dereference
+const NULL_PAGE_SIZE: usize = 4096;
+/// How far below the stack pointer a fault still counts as a stack overflow
+const STACK_GUARD_WINDOW: usize = 64 * 1024;
+
+/// Classify `address` relative to the crashing thread's stack pointer `sp`
+pub fn classify_address(address: usize, sp: usize) -> AddressClass {
+    if address < NULL_PAGE_SIZE {
+        return AddressClass::NullPage;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        // 48-bit virtual addresses: bits 47..63 must all be equal
+        if address >= 0xffff_8000_0000_0000 {
+            return AddressClass::Kernel;
+        }
+        if address >= 0x0000_8000_0000_0000 {
+            return AddressClass::NonCanonical;
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if address >= 0xffff_0000_0000_0000 {
+            return AddressClass::Kernel;
+        }
+        // The top byte may carry a pointer tag (TBI), the rest must fit 48 bits
+        if (address & 0x00ff_ffff_ffff_ffff) >> 48 != 0 {
+            return AddressClass::NonCanonical;
+        }
+    }
+
+    // Stack probes and pushes fault slightly below SP, never far above it
+    if sp != 0 && address <= sp.saturating_add(256) && address.saturating_add(STACK_GUARD_WINDOW) >= sp {
+        return AddressClass::NearStackGuard;
+    }
+
+    AddressClass::User
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_continue_rejected_for_fault_signals() {
+        for signal in FAULT_SIGNALS {
+            let err = check_actions(&[(SIGABRT, SignalAction::DumpAndReraise), (signal, SignalAction::DumpAndContinue)])
+                .unwrap_err();
+            assert_eq!(err.code(), "continue_after_fault");
+            assert!(err.to_string().contains(signal_name(signal)));
+        }
+    }
+
+    #[test]
+    fn test_continue_allowed_for_other_signals() {
+        assert!(check_actions(&DEFAULT_SIGNALS).is_ok());
+        assert!(check_actions(&[
+            (SIGSEGV, SignalAction::Ignore),
+            (SIGTRAP, SignalAction::DumpAndContinue),
+            (SIGXCPU, SignalAction::DumpAndContinue),
+            (SIGXFSZ, SignalAction::DumpAndContinue),
+        ])
+        .is_ok());
+    }
+}
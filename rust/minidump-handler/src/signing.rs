@@ -0,0 +1,97 @@
+//! Signatures over finished dumps.
+//!
+//! A device-held Ed25519 key signs the dump together with its sidecar, so the
+//! server can tell tampered or truncated uploads from genuine ones. The
+//! signature and the signer's public key are stored in the sidecar; the
+//! signed message is a domain tag, the SHA-256 of the dump and the SHA-256 of
+//! the sidecar's JSON without those two fields.
+
+use crate::encryption::{format_hex, parse_hex};
+use crate::sidecar::Sidecar;
+use crate::streams::invalid_data;
+use crate::Result;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+const DOMAIN: &[u8] = b"minidump-handler dump signature v1";
+
+/// New Ed25519 key pair as (private, public)
+pub fn generate_signing_keys() -> ([u8; 32], [u8; 32]) {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let public = SigningKey::from_bytes(&secret).verifying_key().to_bytes();
+    (secret, public)
+}
+
+/// Store the signature of `dump` and `sidecar` in the sidecar
+pub(crate) fn sign(dump: &[u8], sidecar: &mut Sidecar, private_key: &[u8; 32]) -> Result<()> {
+    let key = SigningKey::from_bytes(private_key);
+    let message = message(dump, sidecar)?;
+    sidecar.signing_key = Some(format_hex(key.verifying_key().as_bytes()));
+    sidecar.signature = Some(format_hex(&key.sign(&message).to_bytes()));
+    Ok(())
+}
+
+/// Check the signature in `sidecar` over `dump` and the sidecar itself.
+///
+/// The dump must have been signed by `trusted`. The public key recorded in
+/// the sidecar is never trusted on its own: whoever altered the dump could
+/// have re-signed it with a key of their own.
+pub fn verify_signature(dump: &[u8], sidecar: &Sidecar, trusted: &[u8; 32]) -> Result<()> {
+    let (Some(signature), Some(signing_key)) = (&sidecar.signature, &sidecar.signing_key) else {
+        return Err(invalid_data("sidecar has no signature"));
+    };
+    let signature = parse_hex::<64>(signature).ok_or_else(|| invalid_data("malformed signature"))?;
+    let signer = parse_hex::<32>(signing_key).ok_or_else(|| invalid_data("malformed signing key"))?;
+    if *trusted != signer {
+        return Err(invalid_data("signed by a different key"));
+    }
+
+    let key = VerifyingKey::from_bytes(&signer).map_err(|_| invalid_data("malformed signing key"))?;
+    key.verify(&message(dump, sidecar)?, &Signature::from_bytes(&signature))
+        .map_err(|_| invalid_data("signature does not match the dump and sidecar"))
+}
+
+fn message(dump: &[u8], sidecar: &Sidecar) -> Result<Vec<u8>> {
+    let unsigned = Sidecar {
+        signature: None,
+        signing_key: None,
+        ..sidecar.clone()
+    };
+    let json = serde_json::to_vec(&unsigned).map_err(std::io::Error::from)?;
+
+    let mut message = DOMAIN.to_vec();
+    message.extend_from_slice(&Sha256::digest(dump));
+    message.extend_from_slice(&Sha256::digest(json));
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Uuid;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (private_key, public_key) = generate_signing_keys();
+        let mut sidecar = Sidecar::new(Uuid::new_v4());
+        sign(b"MDMP dump bytes", &mut sidecar, &private_key).unwrap();
+        assert!(verify_signature(b"MDMP dump bytes", &sidecar, &public_key).is_ok());
+
+        // Dump, sidecar and signer are all covered
+        assert!(verify_signature(b"MDMP dump bytez", &sidecar, &public_key).is_err());
+        let mut tampered = sidecar.clone();
+        tampered.reason = Some("edited".to_string());
+        assert!(verify_signature(b"MDMP dump bytes", &tampered, &public_key).is_err());
+        let (other_private, other_key) = generate_signing_keys();
+        assert!(verify_signature(b"MDMP dump bytes", &sidecar, &other_key).is_err());
+        assert!(verify_signature(b"MDMP dump bytes", &Sidecar::default(), &public_key).is_err());
+
+        // A tampered dump re-signed with another key carries a valid
+        // signature, but not by the trusted key
+        sign(b"MDMP dump bytez", &mut tampered, &other_private).unwrap();
+        assert!(verify_signature(b"MDMP dump bytez", &tampered, &other_key).is_ok());
+        assert!(verify_signature(b"MDMP dump bytez", &tampered, &public_key).is_err());
+    }
+}
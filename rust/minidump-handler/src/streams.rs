@@ -0,0 +1,436 @@
+//! Appending custom streams to a finished minidump.
+//!
+//! The writers on each platform only produce the standard streams, so extra
+//! data (crash id, logs, ...) is added afterwards: the stream data and a new,
+//! larger stream directory are appended and the header is pointed at it.
+//! Extra memory is the exception: it is merged into the existing memory list.
+
+use crate::Result;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// `MDMP` in little-endian
+pub const MINIDUMP_SIGNATURE: u32 = 0x504d_444d;
+/// Size of `MINIDUMP_HEADER`
+pub const HEADER_SIZE: u64 = 32;
+/// Size of `MINIDUMP_DIRECTORY`
+pub const DIRECTORY_ENTRY_SIZE: u64 = 12;
+/// Size of `MINIDUMP_MEMORY_DESCRIPTOR`
+pub const MEMORY_DESCRIPTOR_SIZE: usize = 16;
+/// Standard memory list stream, which `append_streams` merges into
+const MEMORY_LIST: u32 = crate::reader::standard_stream::MEMORY_LIST;
+
+/// Stream types used by this crate. Anything above `0xffff` is a user
+/// stream; these live in the `0x4d48xxxx` ("MH") range.
+pub mod stream_type {
+    /// UTF-8 crash id (hyphenated UUID)
+    pub const CRASH_ID: u32 = 0x4d48_0001;
+    /// JSON list of threads that crashed while the dump was being written
+    pub const ADDITIONAL_CRASHES: u32 = 0x4d48_0002;
+    /// JSON object of annotations a client registered with the crash server
+    pub const ANNOTATIONS: u32 = 0x4d48_0003;
+    /// JSON list of open file descriptors and their targets
+    pub const OPEN_FILES: u32 = 0x4d48_0004;
+    /// JSON object of soft/hard resource limits
+    pub const RLIMITS: u32 = 0x4d48_0005;
+    /// JSON object from `getrusage`
+    pub const RUSAGE: u32 = 0x4d48_0006;
+    /// JSON object with the cgroup's memory limit and usage
+    pub const CGROUP_MEMORY: u32 = 0x4d48_0007;
+    /// JSON object of allowlisted environment variables
+    pub const ENVIRONMENT: u32 = 0x4d48_0008;
+    /// JSON list of command line arguments
+    pub const COMMAND_LINE: u32 = 0x4d48_0009;
+    /// JSON string with the working directory
+    pub const WORKING_DIRECTORY: u32 = 0x4d48_000a;
+    /// JSON list of thread ids and names
+    pub const THREAD_NAMES: u32 = 0x4d48_000b;
+    /// UTF-8 text of the in-memory log ring, oldest line first
+    pub const LOG_RING: u32 = 0x4d48_000c;
+    /// UTF-8 text from the end of `HandlerConfig::log_file`
+    pub const LOG_FILE_TAIL: u32 = 0x4d48_000d;
+    /// JSON of recent `tracing` events and each thread's entered spans
+    pub const TRACING_CONTEXT: u32 = 0x4d48_000e;
+    /// JSON list of the application-registered memory regions in the dump's
+    /// memory list, as `{ address, size, name }`
+    pub const APP_MEMORY_NAMES: u32 = 0x4d48_000f;
+    /// JSON of the private mappings a full-memory dump holds only in part,
+    /// as `{ limit, regions: [{ start, size, captured }] }`
+    pub const MEMORY_TRUNCATION: u32 = 0x4d48_0010;
+}
+
+/// One entry of the stream directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub stream_type: u32,
+    pub data_size: u32,
+    pub rva: u32,
+}
+
+/// Header fields needed to edit a dump
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub version: u32,
+    pub stream_count: u32,
+    pub stream_directory_rva: u32,
+    /// Creation time, seconds since the Unix epoch
+    pub time_date_stamp: u32,
+}
+
+/// Read and validate the minidump header
+pub fn read_header<R: Read + Seek>(file: &mut R) -> Result<Header> {
+    let mut raw = [0u8; HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut raw)?;
+
+    let field = |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+    if field(0) != MINIDUMP_SIGNATURE {
+        return Err(invalid_data("not a minidump (bad signature)"));
+    }
+    Ok(Header {
+        version: field(4),
+        stream_count: field(8),
+        stream_directory_rva: field(12),
+        time_date_stamp: field(20),
+    })
+}
+
+/// Read the stream directory
+pub fn read_directory<R: Read + Seek>(file: &mut R, header: &Header) -> Result<Vec<DirectoryEntry>> {
+    let size = header.stream_count as u64 * DIRECTORY_ENTRY_SIZE;
+    check_within(file, header.stream_directory_rva as u64, size, "stream directory outside the dump")?;
+    let mut raw = vec![0u8; size as usize];
+    file.seek(SeekFrom::Start(header.stream_directory_rva as u64))?;
+    file.read_exact(&mut raw)?;
+
+    Ok(raw
+        .chunks_exact(DIRECTORY_ENTRY_SIZE as usize)
+        .map(|entry| DirectoryEntry {
+            stream_type: u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+            data_size: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+            rva: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+        })
+        .collect())
+}
+
+/// Read the data of a directory entry
+pub(crate) fn read_stream<R: Read + Seek>(file: &mut R, entry: &DirectoryEntry) -> Result<Vec<u8>> {
+    check_within(file, entry.rva as u64, entry.data_size as u64, "stream outside the dump")?;
+    let mut data = vec![0u8; entry.data_size as usize];
+    file.seek(SeekFrom::Start(entry.rva as u64))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Fail with `message` unless `size` bytes at `offset` lie within the file,
+/// before allocating a buffer sized from untrusted fields
+fn check_within<R: Seek>(file: &mut R, offset: u64, size: u64, message: &str) -> Result<()> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if offset.checked_add(size).map_or(true, |end| end > len) {
+        return Err(invalid_data(message));
+    }
+    Ok(())
+}
+
+/// Append `streams` (type, data) to the dump and rewrite the directory.
+///
+/// The old directory is left in place as unreferenced bytes, which keeps every
+/// existing RVA valid.
+pub fn append_streams<F: Read + Write + Seek, D: AsRef<[u8]>>(file: &mut F, streams: &[(u32, D)]) -> Result<()> {
+    if streams.is_empty() {
+        return Ok(());
+    }
+
+    let header = read_header(file)?;
+    let mut directory = read_directory(file, &header)?;
+
+    for (stream_type, data) in streams {
+        let (stream_type, data) = (*stream_type, data.as_ref());
+        let rva = align_end(file)?;
+        file.write_all(data)?;
+        if stream_type == MEMORY_LIST {
+            let appended = memory_descriptors(data)?
+                .into_iter()
+                .map(|(start, size, offset)| {
+                    let rva = offset
+                        .checked_add(rva)
+                        .ok_or_else(|| invalid_data("minidump larger than 4 GiB"))?;
+                    Ok((start, size, rva))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            merge_memory_list(file, &mut directory, &appended)?;
+            continue;
+        }
+        directory.push(DirectoryEntry {
+            stream_type,
+            data_size: to_u32(data.len() as u64)?,
+            rva,
+        });
+    }
+
+    commit_directory(file, &directory)
+}
+
+/// Add memory already written into the dump to its memory list. Each
+/// descriptor is (start address, size, RVA of the bytes).
+pub fn append_memory<F: Read + Write + Seek>(file: &mut F, descriptors: &[(u64, u32, u32)]) -> Result<()> {
+    if descriptors.is_empty() {
+        return Ok(());
+    }
+    let header = read_header(file)?;
+    let mut directory = read_directory(file, &header)?;
+    merge_memory_list(file, &mut directory, descriptors)?;
+    commit_directory(file, &directory)
+}
+
+/// Append `directory` and point the header at it
+fn commit_directory<F: Write + Seek>(file: &mut F, directory: &[DirectoryEntry]) -> Result<()> {
+    let directory_rva = align_end(file)?;
+    write_directory(file, directory)?;
+
+    // Patch stream count and directory RVA in the header
+    file.seek(SeekFrom::Start(8))?;
+    file.write_all(&to_u32(directory.len() as u64)?.to_le_bytes())?;
+    file.write_all(&directory_rva.to_le_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// A `MINIDUMP_MEMORY_LIST` carrying its memory inline, for `append_streams`.
+///
+/// Descriptor RVAs are relative to the start of the returned data;
+/// `append_streams` rebases them and merges the ranges into the dump's
+/// existing memory list instead of adding a second one.
+pub fn memory_list_stream<D: AsRef<[u8]>>(regions: &[(u64, D)]) -> Result<Vec<u8>> {
+    let mut list = Vec::new();
+    list.extend_from_slice(&to_u32(regions.len() as u64)?.to_le_bytes());
+    let mut rva = 4 + regions.len() * MEMORY_DESCRIPTOR_SIZE;
+    for (start, bytes) in regions {
+        let size = bytes.as_ref().len();
+        list.extend_from_slice(&start.to_le_bytes());
+        list.extend_from_slice(&to_u32(size as u64)?.to_le_bytes());
+        list.extend_from_slice(&to_u32(rva as u64)?.to_le_bytes());
+        rva = rva
+            .checked_add(size)
+            .ok_or_else(|| invalid_data("memory list larger than the address space"))?;
+    }
+    for (_, bytes) in regions {
+        list.extend_from_slice(bytes.as_ref());
+    }
+    Ok(list)
+}
+
+/// Descriptors of a `MINIDUMP_MEMORY_LIST` as (start, size, RVA)
+fn memory_descriptors(list: &[u8]) -> Result<Vec<(u64, u32, u32)>> {
+    let count = list
+        .get(0..4)
+        .map(|raw| u32::from_le_bytes(raw.try_into().unwrap()) as usize)
+        .ok_or_else(|| invalid_data("truncated memory list"))?;
+    let raw = list
+        .get(4..)
+        .and_then(|raw| raw.get(..count.checked_mul(MEMORY_DESCRIPTOR_SIZE)?))
+        .ok_or_else(|| invalid_data("truncated memory list"))?;
+    Ok(raw
+        .chunks_exact(MEMORY_DESCRIPTOR_SIZE)
+        .map(|d| {
+            (
+                u64::from_le_bytes(d[0..8].try_into().unwrap()),
+                u32::from_le_bytes(d[8..12].try_into().unwrap()),
+                u32::from_le_bytes(d[12..16].try_into().unwrap()),
+            )
+        })
+        .collect())
+}
+
+/// Write a memory list holding the descriptors of the dump's own list
+/// followed by `appended`, and point the directory at it
+fn merge_memory_list<F: Read + Write + Seek>(
+    file: &mut F,
+    directory: &mut Vec<DirectoryEntry>,
+    appended: &[(u64, u32, u32)],
+) -> Result<()> {
+    let existing = directory.iter().position(|e| e.stream_type == MEMORY_LIST);
+    let mut merged = match existing {
+        Some(index) => {
+            let list = read_stream(file, &directory[index])?;
+            memory_descriptors(&list)?
+        }
+        None => Vec::new(),
+    };
+    merged.extend_from_slice(appended);
+
+    let rva = align_end(file)?;
+    file.write_all(&to_u32(merged.len() as u64)?.to_le_bytes())?;
+    for (start, size, data_rva) in &merged {
+        file.write_all(&start.to_le_bytes())?;
+        file.write_all(&size.to_le_bytes())?;
+        file.write_all(&data_rva.to_le_bytes())?;
+    }
+    let entry = DirectoryEntry {
+        stream_type: MEMORY_LIST,
+        data_size: to_u32(4 + (merged.len() * MEMORY_DESCRIPTOR_SIZE) as u64)?,
+        rva,
+    };
+    match existing {
+        Some(index) => directory[index] = entry,
+        None => directory.push(entry),
+    }
+    Ok(())
+}
+
+/// Serialize directory entries at the current position
+pub fn write_directory<W: Write>(file: &mut W, directory: &[DirectoryEntry]) -> Result<()> {
+    for entry in directory {
+        file.write_all(&entry.stream_type.to_le_bytes())?;
+        file.write_all(&entry.data_size.to_le_bytes())?;
+        file.write_all(&entry.rva.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Seek to the end, pad to a 4-byte boundary and return the offset
+pub fn align_end<F: Write + Seek>(file: &mut F) -> Result<u32> {
+    let end = file.seek(SeekFrom::End(0))?;
+    let padding = (4 - end % 4) % 4;
+    file.write_all(&[0u8; 3][..padding as usize])?;
+    to_u32(end + padding)
+}
+
+/// Minidump RVAs are 32-bit
+pub fn to_u32(value: u64) -> Result<u32> {
+    u32::try_from(value).map_err(|_| invalid_data("minidump larger than 4 GiB"))
+}
+
+pub(crate) fn invalid_data(message: &str) -> crate::HandlerError {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string()).into()
+}
+
+/// Hand-built dumps for tests, also for dependent crates with `test-util`
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing {
+    use super::{append_streams, HEADER_SIZE, MINIDUMP_SIGNATURE};
+    use crate::reader::{standard_stream, Minidump};
+    use std::io::Cursor;
+
+    /// A minidump header followed by raw bytes at known offsets, with streams
+    /// appended by `append_streams` when built
+    pub struct TestDump {
+        raw: Vec<u8>,
+        streams: Vec<(u32, Vec<u8>)>,
+    }
+
+    impl Default for TestDump {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl TestDump {
+        /// Smallest valid minidump: a header and an empty directory
+        pub fn new() -> Self {
+            let mut raw = Vec::new();
+            raw.extend_from_slice(&MINIDUMP_SIGNATURE.to_le_bytes());
+            raw.extend_from_slice(&0xa793u32.to_le_bytes());
+            raw.extend_from_slice(&0u32.to_le_bytes());
+            raw.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+            raw.extend_from_slice(&[0u8; 16]);
+            Self {
+                raw,
+                streams: Vec::new(),
+            }
+        }
+
+        /// Put `bytes` after the header and earlier data, for streams that
+        /// point at them by RVA
+        pub fn data(mut self, bytes: &[u8]) -> Self {
+            self.raw.extend_from_slice(bytes);
+            self
+        }
+
+        pub fn stream(mut self, stream_type: u32, data: impl Into<Vec<u8>>) -> Self {
+            self.streams.push((stream_type, data.into()));
+            self
+        }
+
+        /// System info of an x86_64 Linux machine
+        pub fn amd64_linux(self) -> Self {
+            let mut system_info = vec![0u8; 56];
+            system_info[0..2].copy_from_slice(&9u16.to_le_bytes());
+            system_info[20..24].copy_from_slice(&0x8201u32.to_le_bytes());
+            self.stream(standard_stream::SYSTEM_INFO, system_info)
+        }
+
+        pub fn cursor(self) -> Cursor<Vec<u8>> {
+            let mut dump = Cursor::new(self.raw);
+            append_streams(&mut dump, &self.streams).unwrap();
+            dump
+        }
+
+        pub fn bytes(self) -> Vec<u8> {
+            self.cursor().into_inner()
+        }
+
+        pub fn build(self) -> Minidump {
+            Minidump::from_bytes(self.bytes()).unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::TestDump;
+    use super::*;
+
+    #[test]
+    fn test_append_streams() {
+        let mut dump = TestDump::new().cursor();
+        append_streams(&mut dump, &[(stream_type::CRASH_ID, b"abc")]).unwrap();
+        append_streams(&mut dump, &[(0x4d48_ffff, b"12345")]).unwrap();
+
+        let header = read_header(&mut dump).unwrap();
+        let directory = read_directory(&mut dump, &header).unwrap();
+        assert_eq!(directory.len(), 2);
+        assert_eq!(directory[0].stream_type, stream_type::CRASH_ID);
+
+        let data = dump.get_ref();
+        let entry = directory[1];
+        assert_eq!(&data[entry.rva as usize..][..entry.data_size as usize], b"12345");
+    }
+
+    #[test]
+    fn test_memory_lists_are_merged() {
+        let mut dump = TestDump::new().cursor();
+        let first = memory_list_stream(&[(0x1000u64, b"abcd")]).unwrap();
+        append_streams(&mut dump, &[(MEMORY_LIST, first)]).unwrap();
+        let second = memory_list_stream(&[(0x2000u64, b"xyz")]).unwrap();
+        append_streams(&mut dump, &[(MEMORY_LIST, second)]).unwrap();
+
+        let header = read_header(&mut dump).unwrap();
+        let directory = read_directory(&mut dump, &header).unwrap();
+        assert_eq!(directory.len(), 1);
+
+        let dump = crate::reader::Minidump::from_bytes(dump.into_inner()).unwrap();
+        assert_eq!(dump.memory_regions().len(), 2);
+        assert_eq!(dump.memory_at(0x1001), Some(&b"bcd"[..]));
+        assert_eq!(dump.memory_at(0x2000), Some(&b"xyz"[..]));
+    }
+
+    #[test]
+    fn test_directory_outside_the_dump_is_rejected() {
+        let mut dump = TestDump::new().cursor();
+        append_streams(&mut dump, &[(stream_type::CRASH_ID, b"abc")]).unwrap();
+
+        // A stream count far beyond the file must not size an allocation
+        let header = read_header(&mut dump).unwrap();
+        let bogus = Header {
+            stream_count: u32::MAX,
+            ..header
+        };
+        assert!(read_directory(&mut dump, &bogus).is_err());
+
+        let mut directory = read_directory(&mut dump, &header).unwrap();
+        directory[0].data_size = u32::MAX;
+        assert!(read_stream(&mut dump, &directory[0]).is_err());
+        directory[0].data_size = 3;
+        assert_eq!(read_stream(&mut dump, &directory[0]).unwrap(), b"abc");
+    }
+}
@@ -0,0 +1,24 @@
+//! Small async-signal-safe wrappers around OS facilities
+
+/// Kernel id of the calling thread
+pub(crate) fn current_tid() -> u64 {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        unsafe { libc::syscall(libc::SYS_gettid) as u64 }
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        let mut tid = 0u64;
+        unsafe { libc::pthread_threadid_np(0, &mut tid) };
+        tid
+    }
+}
+
+/// Wall-clock time in milliseconds since the Unix epoch (`clock_gettime` only)
+pub(crate) fn unix_time_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
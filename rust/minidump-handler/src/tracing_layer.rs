@@ -0,0 +1,261 @@
+//! `tracing` integration: what each thread was doing when the process crashed.
+//!
+//! [`CrashContextLayer`] keeps the most recent events as breadcrumbs and the
+//! stack of spans each thread is currently inside. Dumps written by the crash
+//! handler embed both as the `TRACING_CONTEXT` stream.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Breadcrumbs kept by default
+pub const DEFAULT_BREADCRUMBS: usize = 100;
+
+static STATE: Mutex<State> = Mutex::new(State {
+    breadcrumbs: VecDeque::new(),
+    capacity: DEFAULT_BREADCRUMBS,
+    threads: None,
+});
+
+struct State {
+    breadcrumbs: VecDeque<Breadcrumb>,
+    capacity: usize,
+    /// Entered spans per thread id, innermost last
+    threads: Option<HashMap<u64, Vec<(Id, SpanFrame)>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Breadcrumb {
+    timestamp_ms: u64,
+    tid: u64,
+    level: &'static str,
+    target: String,
+    message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct SpanFrame {
+    name: &'static str,
+    target: String,
+    fields: String,
+}
+
+#[derive(Serialize)]
+struct ThreadSpans {
+    tid: u64,
+    spans: Vec<SpanFrame>,
+}
+
+#[derive(Serialize)]
+struct Snapshot {
+    breadcrumbs: Vec<Breadcrumb>,
+    threads: Vec<ThreadSpans>,
+}
+
+/// Layer recording breadcrumbs and per-thread span stacks for crash dumps
+pub struct CrashContextLayer {
+    _private: (),
+}
+
+impl CrashContextLayer {
+    /// Layer keeping the last [`DEFAULT_BREADCRUMBS`] events
+    pub fn new() -> Self {
+        Self::with_breadcrumbs(DEFAULT_BREADCRUMBS)
+    }
+
+    /// Layer keeping the last `capacity` events
+    pub fn with_breadcrumbs(capacity: usize) -> Self {
+        let mut state = lock();
+        state.capacity = capacity;
+        while state.breadcrumbs.len() > capacity {
+            state.breadcrumbs.pop_front();
+        }
+        Self { _private: () }
+    }
+}
+
+impl Default for CrashContextLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Formatted fields of a span, stored in its extensions
+struct SpanFields(String);
+
+/// Collects `message` separately and everything else as `name=value`
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            let _ = write!(self.fields, "{}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+}
+
+impl<S> Layer<S> for CrashContextLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let message = match (visitor.message.is_empty(), visitor.fields.is_empty()) {
+            (_, true) => visitor.message,
+            (true, false) => visitor.fields,
+            (false, false) => format!("{} {}", visitor.message, visitor.fields),
+        };
+
+        let metadata = event.metadata();
+        let breadcrumb = Breadcrumb {
+            timestamp_ms: crate::sys::unix_time_ms(),
+            tid: crate::sys::current_tid(),
+            level: metadata.level().as_str(),
+            target: metadata.target().to_string(),
+            message,
+        };
+
+        let mut state = lock();
+        if state.capacity == 0 {
+            return;
+        }
+        while state.breadcrumbs.len() >= state.capacity {
+            state.breadcrumbs.pop_front();
+        }
+        state.breadcrumbs.push_back(breadcrumb);
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let fields = span
+            .extensions()
+            .get::<SpanFields>()
+            .map(|f| f.0.clone())
+            .unwrap_or_default();
+        let frame = SpanFrame {
+            name: span.name(),
+            target: span.metadata().target().to_string(),
+            fields,
+        };
+
+        let tid = crate::sys::current_tid();
+        let mut state = lock();
+        state
+            .threads
+            .get_or_insert_with(HashMap::new)
+            .entry(tid)
+            .or_default()
+            .push((id.clone(), frame));
+    }
+
+    fn on_exit(&self, id: &Id, _ctx: Context<'_, S>) {
+        let tid = crate::sys::current_tid();
+        let mut state = lock();
+        let Some(threads) = state.threads.as_mut() else {
+            return;
+        };
+        if let Some(stack) = threads.get_mut(&tid) {
+            if let Some(position) = stack.iter().rposition(|(entered, _)| entered == id) {
+                stack.remove(position);
+            }
+            if stack.is_empty() {
+                threads.remove(&tid);
+            }
+        }
+    }
+}
+
+/// JSON of the breadcrumbs and span stacks for a dump. Gives up rather than
+/// wait if a crashing thread holds the state lock.
+pub(crate) fn snapshot() -> Option<Vec<u8>> {
+    let state = STATE.try_lock().ok()?;
+    let mut threads: Vec<ThreadSpans> = state
+        .threads
+        .iter()
+        .flatten()
+        .map(|(&tid, stack)| ThreadSpans {
+            tid,
+            spans: stack.iter().map(|(_, frame)| frame.clone()).collect(),
+        })
+        .collect();
+    threads.sort_by_key(|t| t.tid);
+
+    if threads.is_empty() && state.breadcrumbs.is_empty() {
+        return None;
+    }
+    serde_json::to_vec(&Snapshot {
+        breadcrumbs: state.breadcrumbs.iter().cloned().collect(),
+        threads,
+    })
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_snapshot_has_spans_and_breadcrumbs() {
+        let subscriber = tracing_subscriber::registry().with(CrashContextLayer::new());
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("handle_request", id = 7);
+            let _entered = span.enter();
+            tracing::warn!(attempt = 2, "retrying upload");
+
+            let json: serde_json::Value = serde_json::from_slice(&snapshot().unwrap()).unwrap();
+            let tid = crate::sys::current_tid();
+            let thread = json["threads"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|t| t["tid"] == tid)
+                .unwrap();
+            assert_eq!(thread["spans"][0]["name"], "handle_request");
+            assert_eq!(thread["spans"][0]["fields"], "id=7");
+
+            let last = json["breadcrumbs"].as_array().unwrap().last().unwrap();
+            assert_eq!(last["level"], "WARN");
+            assert_eq!(last["message"], "retrying upload attempt=2");
+        });
+    }
+}